@@ -0,0 +1,623 @@
+// The sound channels of the 2A03 and the units they share
+// Ref: https://wiki.nesdev.com/w/index.php/APU
+
+// Lengths loaded into the length counter, indexed by the upper 5 bits of the fourth channel register
+// Ref: https://wiki.nesdev.com/w/index.php/APU_Length_Counter
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20,  2, 40,  4, 80,  6, 160,  8, 60, 10, 14, 12, 26, 14,
+    12,  16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30
+];
+
+// Ref: https://wiki.nesdev.com/w/index.php/APU_Pulse
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1]
+];
+
+// Ref: https://wiki.nesdev.com/w/index.php/APU_Triangle
+const TRIANGLE_TABLE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10,  9,  8,  7,  6,  5,  4,  3,  2,  1,  0,
+     0,  1,  2,  3,  4,  5,  6,  7,  8,  9, 10, 11, 12, 13, 14, 15
+];
+
+// Timer periods in CPU cycles
+// Ref: https://wiki.nesdev.com/w/index.php/APU_Noise
+pub const NOISE_PERIODS_NTSC: [u16; 16] = [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+pub const NOISE_PERIODS_PAL: [u16; 16] = [4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778];
+
+// Ref: https://wiki.nesdev.com/w/index.php/APU_DMC
+pub const DMC_RATES_NTSC: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+pub const DMC_RATES_PAL: [u16; 16] = [398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50];
+
+// Silences a channel once it runs out, unless it's halted
+#[derive(Default)]
+pub struct LengthCounter {
+    enabled: bool,
+    halted: bool,
+    counter: u8
+}
+
+impl LengthCounter {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index >> 3) as usize];
+        }
+    }
+
+    // Clocked by the half frames of the frame counter
+    fn clock(&mut self) {
+        if !self.halted && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+// Either a constant volume, or a decaying volume that can loop
+// Ref: https://wiki.nesdev.com/w/index.php/APU_Envelope
+#[derive(Default)]
+struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    volume: u8,                     // The constant volume, or the period of the decay
+    divider: u8,
+    decay: u8
+}
+
+impl Envelope {
+    fn write(&mut self, value: u8) {
+        self.looping = value & 0b0010_0000 != 0;
+        self.constant = value & 0b0001_0000 != 0;
+        self.volume = value & 0b0000_1111;
+    }
+
+    // Clocked by the quarter frames of the frame counter
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant { self.volume } else { self.decay }
+    }
+}
+
+// Ref: https://wiki.nesdev.com/w/index.php/APU_Pulse
+pub struct Pulse {
+    pub length: LengthCounter,
+    envelope: Envelope,
+    ones_complement: bool,          // The first pulse channel negates its sweep with one's complement
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool
+}
+
+impl Pulse {
+    pub fn new(ones_complement: bool) -> Pulse {
+        Pulse {
+            length: LengthCounter::default(),
+            envelope: Envelope::default(),
+            ones_complement,
+            duty: 0,
+            step: 0,
+            period: 0,
+            timer: 0,
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false
+        }
+    }
+
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.duty = value >> 6;
+                self.length.halted = value & 0b0010_0000 != 0;
+                self.envelope.write(value);
+            }
+            1 => {
+                self.sweep_enabled = value & 0b1000_0000 != 0;
+                self.sweep_period = (value >> 4) & 0b0000_0111;
+                self.sweep_negate = value & 0b0000_1000 != 0;
+                self.sweep_shift = value & 0b0000_0111;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0b0000_0111) << 8);
+                self.length.load(value);
+                self.envelope.start = true;
+                self.step = 0;
+            }
+        }
+    }
+
+    // Clocked every other CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 0b0000_0111;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        match (self.sweep_negate, self.ones_complement) {
+            (false, _) => self.period + change,
+            (true, true) => self.period.saturating_sub(change + 1),
+            (true, false) => self.period.saturating_sub(change)
+        }
+    }
+
+    // Periods that are too short or a sweep that would overflow silence the channel, even when the sweep is disabled
+    fn muted(&self) -> bool {
+        self.period < 8 || self.sweep_target() > 0x07FF
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.muted() || DUTY_TABLE[self.duty as usize][self.step as usize] == 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+// Ref: https://wiki.nesdev.com/w/index.php/APU_Triangle
+#[derive(Default)]
+pub struct Triangle {
+    pub length: LengthCounter,
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    step: u8,
+    period: u16,
+    timer: u16
+}
+
+impl Triangle {
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.control = value & 0b1000_0000 != 0;
+                self.length.halted = self.control;
+                self.linear_reload_value = value & 0b0111_1111;
+            }
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            3 => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0b0000_0111) << 8);
+                self.length.load(value);
+                self.linear_reload = true;
+            }
+            _ => ()
+        }
+    }
+
+    // Clocked every CPU cycle, the sequencer only moves while both counters are running
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.length.active() && self.linear_counter > 0 {
+                self.step = (self.step + 1) & 0b0001_1111;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    // Ultrasonic periods are held at the middle of the waveform, which is what they amount to once filtered
+    pub fn output(&self) -> u8 {
+        if self.period < 2 { 7 } else { TRIANGLE_TABLE[self.step as usize] }
+    }
+}
+
+// Ref: https://wiki.nesdev.com/w/index.php/APU_Noise
+pub struct Noise {
+    pub length: LengthCounter,
+    envelope: Envelope,
+    periods: &'static [u16; 16],
+    short_mode: bool,
+    shift: u16,
+    period: u16,
+    timer: u16
+}
+
+impl Noise {
+    pub fn new(periods: &'static [u16; 16]) -> Noise {
+        Noise {
+            length: LengthCounter::default(),
+            envelope: Envelope::default(),
+            periods,
+            short_mode: false,
+            shift: 1,
+            period: periods[0],
+            timer: 0
+        }
+    }
+
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.length.halted = value & 0b0010_0000 != 0;
+                self.envelope.write(value);
+            }
+            2 => {
+                self.short_mode = value & 0b1000_0000 != 0;
+                self.period = self.periods[(value & 0b0000_1111) as usize];
+            }
+            3 => {
+                self.length.load(value);
+                self.envelope.start = true;
+            }
+            _ => ()
+        }
+    }
+
+    // Clocked every CPU cycle, the shift register gets its feedback from bit 1, or bit 6 in short mode
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift & 1) ^ ((self.shift >> tap) & 1);
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.shift & 1 != 0 { 0 } else { self.envelope.output() }
+    }
+}
+
+// Plays delta-encoded samples straight from CPU memory, the memory reads are done by the APU's owner, see Apu::dmc_request
+// Ref: https://wiki.nesdev.com/w/index.php/APU_DMC
+pub struct Dmc {
+    rates: &'static [u16; 16],
+    pub irq_enabled: bool,
+    pub irq: bool,
+    looping: bool,
+    rate: u16,
+    timer: u16,
+    output: u8,
+    sample_address: u16,
+    sample_length: u16,
+    address: u16,
+    pub bytes_remaining: u16,
+    buffer: Option<u8>,
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool
+}
+
+impl Dmc {
+    pub fn new(rates: &'static [u16; 16]) -> Dmc {
+        Dmc {
+            rates,
+            irq_enabled: false,
+            irq: false,
+            looping: false,
+            rate: rates[0],
+            timer: 0,
+            output: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            address: 0xC000,
+            bytes_remaining: 0,
+            buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true
+        }
+    }
+
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = value & 0b1000_0000 != 0;
+                self.looping = value & 0b0100_0000 != 0;
+                self.rate = self.rates[(value & 0b0000_1111) as usize];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            }
+            1 => self.output = value & 0b0111_1111,
+            2 => self.sample_address = 0xC000 | ((value as u16) << 6),
+            _ => self.sample_length = ((value as u16) << 4) | 1
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.address = self.sample_address;
+            self.bytes_remaining = self.sample_length;
+        }
+    }
+
+    // The address of the next sample byte, when the sample buffer is empty and there are bytes left to play
+    pub fn request(&self) -> Option<u16> {
+        if self.buffer.is_none() && self.bytes_remaining > 0 { Some(self.address) } else { None }
+    }
+
+    pub fn fill(&mut self, value: u8) {
+        self.buffer = Some(value);
+        self.address = if self.address == 0xFFFF { 0x8000 } else { self.address + 1 };
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.address = self.sample_address;
+                self.bytes_remaining = self.sample_length;
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // Clocked every CPU cycle, every bit of the sample moves the output level up or down by 2
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.rate - 1;
+
+        if !self.silence {
+            if self.shift & 1 != 0 {
+                if self.output <= 125 { self.output += 2; }
+            } else if self.output >= 2 {
+                self.output -= 2;
+            }
+        }
+        self.shift >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(value) => {
+                    self.silence = false;
+                    self.shift = value;
+                }
+                None => self.silence = true
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_counter() {
+        let mut length = LengthCounter::default();
+        length.load(0b0000_1000);
+        assert!(!length.active(), "A disabled length counter shouldn't load");
+
+        length.set_enabled(true);
+        length.load(0b0000_1000);
+        assert_eq!(length.counter, 254);
+        length.load(0b1111_1000);
+        assert_eq!(length.counter, 30);
+
+        length.halted = true;
+        length.clock();
+        assert_eq!(length.counter, 30);
+        length.halted = false;
+        length.clock();
+        assert_eq!(length.counter, 29);
+
+        length.set_enabled(false);
+        assert!(!length.active());
+    }
+
+    #[test]
+    fn envelope_decays_and_loops() {
+        let mut envelope = Envelope::default();
+        envelope.write(0b0000_0000);
+        envelope.start = true;
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+        for _ in 0..15 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.output(), 0);
+        envelope.clock();
+        assert_eq!(envelope.output(), 0);
+
+        envelope.write(0b0010_0000);
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+
+        envelope.write(0b0001_0111);
+        assert_eq!(envelope.output(), 7);
+    }
+
+    // The first pulse channel subtracts one more when sweeping down
+    #[test]
+    fn pulse_sweep() {
+        for &(ones_complement, period) in &[(true, 0x0FF), (false, 0x100)] {
+            let mut pulse = Pulse::new(ones_complement);
+            pulse.write(2, 0x00);
+            pulse.write(3, 0x02);
+            pulse.write(1, 0b1000_1001);
+            pulse.clock_half_frame();
+            assert_eq!(pulse.period, period);
+        }
+
+        let mut pulse = Pulse::new(false);
+        pulse.write(2, 0x00);
+        pulse.write(3, 0x02);
+        pulse.write(1, 0b1000_0001);
+        pulse.clock_half_frame();
+        assert_eq!(pulse.period, 0x300);
+    }
+
+    #[test]
+    fn pulse_is_muted() {
+        let mut pulse = Pulse::new(false);
+        pulse.length.set_enabled(true);
+        pulse.write(0, 0b1101_1111);
+        pulse.write(2, 0x00);
+        pulse.write(3, 0x01);
+        assert_eq!(pulse.output(), 15);
+
+        // A period that would overflow when swept, even with the sweep disabled
+        pulse.write(3, 0x04);
+        assert_eq!(pulse.output(), 0);
+
+        pulse.write(2, 0x07);
+        pulse.write(3, 0x00);
+        assert_eq!(pulse.output(), 0);
+    }
+
+    #[test]
+    fn triangle_needs_the_linear_counter() {
+        let mut triangle = Triangle::default();
+        triangle.length.set_enabled(true);
+        triangle.write(0, 0x01);
+        triangle.write(2, 0x10);
+        triangle.write(3, 0x00);
+
+        for _ in 0..=0x10 {
+            triangle.clock_timer();
+        }
+        assert_eq!(triangle.step, 0);
+
+        triangle.clock_quarter_frame();
+        for _ in 0..=0x10 {
+            triangle.clock_timer();
+        }
+        assert_eq!((triangle.step, triangle.output()), (1, 14));
+
+        // The linear counter runs out after its reload value of 1
+        triangle.clock_quarter_frame();
+        for _ in 0..=0x10 {
+            triangle.clock_timer();
+        }
+        assert_eq!(triangle.step, 1);
+    }
+
+    #[test]
+    fn noise_shift_register() {
+        let mut noise = Noise::new(&NOISE_PERIODS_NTSC);
+        noise.clock_timer();
+        assert_eq!(noise.shift, 0x4000);
+
+        let mut noise = Noise::new(&NOISE_PERIODS_NTSC);
+        noise.write(2, 0b1000_0000);
+        noise.shift = 0b0100_0000;
+        noise.clock_timer();
+        assert_eq!(noise.shift, 0x4020);
+    }
+
+    #[test]
+    fn dmc_reads_its_sample() {
+        let mut dmc = Dmc::new(&DMC_RATES_NTSC);
+        dmc.write(0, 0b1000_0000);
+        dmc.write(2, 0x01);
+        dmc.write(3, 0x00);
+        assert_eq!(dmc.request(), None);
+
+        dmc.set_enabled(true);
+        assert_eq!(dmc.request(), Some(0xC040));
+        dmc.fill(0xFF);
+        assert_eq!(dmc.request(), None);
+        assert_eq!(dmc.bytes_remaining, 0);
+        assert!(dmc.irq);
+
+        // Every set bit moves the output up by 2
+        dmc.write(1, 0x40);
+        for _ in 0..DMC_RATES_NTSC[0] as usize * 9 {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 0x42);
+    }
+}
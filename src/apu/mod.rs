@@ -0,0 +1,346 @@
+pub mod channels;
+pub mod wav;
+
+use apu::channels::{Dmc, Noise, Pulse, Triangle};
+use apu::channels::{DMC_RATES_NTSC, DMC_RATES_PAL, NOISE_PERIODS_NTSC, NOISE_PERIODS_PAL};
+
+// CPU clock rates, which the APU runs along with
+// Ref: https://wiki.nesdev.com/w/index.php/Cycle_reference_chart
+pub const NTSC_CLOCK_RATE: f64 = 1_789_773.0;
+pub const PAL_CLOCK_RATE: f64 = 1_662_607.0;
+
+// CPU cycles at which the frame counter clocks the envelopes, length counters and sweeps, for both sequencer modes
+// Ref: https://wiki.nesdev.com/w/index.php/APU_Frame_Counter
+const FOUR_STEP_SEQUENCE_NTSC: [u32; 4] = [7457, 14913, 22371, 29829];
+const FIVE_STEP_SEQUENCE_NTSC: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+const FOUR_STEP_SEQUENCE_PAL: [u32; 4] = [8313, 16627, 24939, 33253];
+const FIVE_STEP_SEQUENCE_PAL: [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+
+// The high-pass filter of the NES' audio output, which removes the DC offset of the mixer
+// Ref: https://wiki.nesdev.com/w/index.php/APU_Mixer
+const HIGH_PASS_CUTOFF: f64 = 90.0;
+
+// The 2A03 audio processing unit: two pulse channels, a triangle, a noise channel and the delta modulation channel
+// $4000-$4003  Pulse 1
+// $4004-$4007  Pulse 2
+// $4008-$400B  Triangle
+// $400C-$400F  Noise
+// $4010-$4013  DMC
+// $4015        Channel enable (write) and status (read)
+// $4017        Frame counter
+// The APU is clocked along with the CPU and produces samples at the requested rate, which are averaged over every CPU
+// cycle they span. The DMC reads its samples from CPU memory, which is left to the owner of the APU, see dmc_request.
+// Ref: https://wiki.nesdev.com/w/index.php/APU
+pub struct Apu {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+
+    pal: bool,
+    cycle: u32,                         // CPU cycles since the frame counter sequence started
+    odd_cycle: bool,
+    five_step: bool,
+    frame_irq_inhibit: bool,
+    frame_irq: bool,
+
+    cycles_per_sample: f64,
+    sample_cycles: f64,                 // CPU cycles that went into the sample that's being built up
+    sample_sum: f64,
+    filter_input: f64,
+    filter_output: f64,
+    filter_factor: f64,
+    samples: Vec<i16>
+}
+
+impl Apu {
+    pub fn new(pal: bool, sample_rate: u32) -> Apu {
+        let clock_rate = if pal { PAL_CLOCK_RATE } else { NTSC_CLOCK_RATE };
+        let rc = 1.0 / (2.0 * ::std::f64::consts::PI * HIGH_PASS_CUTOFF);
+
+        Apu {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::default(),
+            noise: Noise::new(if pal { &NOISE_PERIODS_PAL } else { &NOISE_PERIODS_NTSC }),
+            dmc: Dmc::new(if pal { &DMC_RATES_PAL } else { &DMC_RATES_NTSC }),
+            pal,
+            cycle: 0,
+            odd_cycle: false,
+            five_step: false,
+            frame_irq_inhibit: false,
+            frame_irq: false,
+            cycles_per_sample: clock_rate / sample_rate as f64,
+            sample_cycles: 0.0,
+            sample_sum: 0.0,
+            filter_input: 0.0,
+            filter_output: 0.0,
+            filter_factor: rc / (rc + 1.0 / sample_rate as f64),
+            samples: vec![]
+        }
+    }
+
+    // Reading the status acknowledges the frame IRQ
+    pub fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x4015 => {
+                let mut value = 0;
+                if self.pulse1.length.active() { value |= 0b0000_0001; }
+                if self.pulse2.length.active() { value |= 0b0000_0010; }
+                if self.triangle.length.active() { value |= 0b0000_0100; }
+                if self.noise.length.active() { value |= 0b0000_1000; }
+                if self.dmc.bytes_remaining > 0 { value |= 0b0001_0000; }
+                if self.frame_irq { value |= 0b0100_0000; }
+                if self.dmc.irq { value |= 0b1000_0000; }
+
+                self.frame_irq = false;
+                value
+            }
+            _ => 0
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x4000..=0x4003 => self.pulse1.write(address - 0x4000, value),
+            0x4004..=0x4007 => self.pulse2.write(address - 0x4004, value),
+            0x4008..=0x400B => self.triangle.write(address - 0x4008, value),
+            0x400C..=0x400F => self.noise.write(address - 0x400C, value),
+            0x4010..=0x4013 => self.dmc.write(address - 0x4010, value),
+            0x4015 => {
+                self.pulse1.length.set_enabled(value & 0b0000_0001 != 0);
+                self.pulse2.length.set_enabled(value & 0b0000_0010 != 0);
+                self.triangle.length.set_enabled(value & 0b0000_0100 != 0);
+                self.noise.length.set_enabled(value & 0b0000_1000 != 0);
+                self.dmc.set_enabled(value & 0b0001_0000 != 0);
+            }
+            // Restarts the sequence, the 5-step mode immediately clocks everything once
+            0x4017 => {
+                self.five_step = value & 0b1000_0000 != 0;
+                self.frame_irq_inhibit = value & 0b0100_0000 != 0;
+                if self.frame_irq_inhibit {
+                    self.frame_irq = false;
+                }
+                self.cycle = 0;
+                if self.five_step {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            }
+            _ => ()
+        }
+    }
+
+    // The address the DMC wants to read its next sample byte from, which should be handed to it with dmc_fill
+    pub fn dmc_request(&self) -> Option<u16> {
+        self.dmc.request()
+    }
+
+    pub fn dmc_fill(&mut self, value: u8) {
+        self.dmc.fill(value);
+    }
+
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    // Runs the APU for a single CPU cycle
+    pub fn clock(&mut self) {
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.odd_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+
+        self.clock_frame_counter();
+        self.mix();
+    }
+
+    // Takes the samples produced so far
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        ::std::mem::take(&mut self.samples)
+    }
+
+    fn clock_frame_counter(&mut self) {
+        self.cycle += 1;
+
+        let (four_step, five_step) = if self.pal {
+            (&FOUR_STEP_SEQUENCE_PAL, &FIVE_STEP_SEQUENCE_PAL)
+        } else {
+            (&FOUR_STEP_SEQUENCE_NTSC, &FIVE_STEP_SEQUENCE_NTSC)
+        };
+
+        if self.five_step {
+            match five_step.iter().position(|&cycle| cycle == self.cycle) {
+                Some(0) | Some(2) => self.clock_quarter_frame(),
+                Some(1) | Some(4) => { self.clock_quarter_frame(); self.clock_half_frame(); }
+                _ => ()
+            }
+            if self.cycle >= five_step[4] {
+                self.cycle = 0;
+            }
+        } else {
+            match four_step.iter().position(|&cycle| cycle == self.cycle) {
+                Some(0) | Some(2) => self.clock_quarter_frame(),
+                Some(1) => { self.clock_quarter_frame(); self.clock_half_frame(); }
+                Some(3) => {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                    if !self.frame_irq_inhibit {
+                        self.frame_irq = true;
+                    }
+                    self.cycle = 0;
+                }
+                _ => ()
+            }
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.clock_quarter_frame();
+        self.pulse2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_half_frame();
+        self.pulse2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    // The channels are mixed non-linearly, the output ranges from 0.0 to 1.0
+    // Ref: https://wiki.nesdev.com/w/index.php/APU_Mixer
+    fn mix(&mut self) {
+        let pulse = (self.pulse1.output() + self.pulse2.output()) as f64;
+        let pulse_out = if pulse == 0.0 { 0.0 } else { 95.88 / (8128.0 / pulse + 100.0) };
+
+        let tnd = self.triangle.output() as f64 / 8227.0 + self.noise.output() as f64 / 12241.0 + self.dmc.output() as f64 / 22638.0;
+        let tnd_out = if tnd == 0.0 { 0.0 } else { 159.79 / (1.0 / tnd + 100.0) };
+
+        self.sample_sum += pulse_out + tnd_out;
+        self.sample_cycles += 1.0;
+
+        if self.sample_cycles >= self.cycles_per_sample {
+            let input = self.sample_sum / self.sample_cycles;
+            self.sample_sum = 0.0;
+            self.sample_cycles -= self.cycles_per_sample;
+
+            self.filter_output = self.filter_factor * (self.filter_output + input - self.filter_input);
+            self.filter_input = input;
+
+            let sample = (self.filter_output * i16::MAX as f64).clamp(i16::MIN as f64, i16::MAX as f64);
+            self.samples.push(sample as i16);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(apu: &mut Apu, cycles: u32) {
+        for _ in 0..cycles {
+            apu.clock();
+        }
+    }
+
+    // Starts the first pulse channel with a length of 2, which runs out after two half frames
+    fn start_pulse(apu: &mut Apu) {
+        apu.write(0x4015, 0b0000_0001);
+        apu.write(0x4003, 0b0001_1000);
+    }
+
+    #[test]
+    fn four_step_sequence_sets_the_frame_irq() {
+        let mut apu = Apu::new(false, 44100);
+        run(&mut apu, FOUR_STEP_SEQUENCE_NTSC[3] - 1);
+        assert!(!apu.irq());
+        run(&mut apu, 1);
+        assert!(apu.irq());
+
+        // Reading the status acknowledges it
+        assert_eq!(apu.read(0x4015) & 0b0100_0000, 0b0100_0000);
+        assert!(!apu.irq());
+        assert_eq!(apu.read(0x4015) & 0b0100_0000, 0);
+    }
+
+    #[test]
+    fn frame_irq_can_be_inhibited() {
+        let mut apu = Apu::new(false, 44100);
+        run(&mut apu, FOUR_STEP_SEQUENCE_NTSC[3]);
+        apu.write(0x4017, 0b0100_0000);
+        assert!(!apu.irq());
+        run(&mut apu, FOUR_STEP_SEQUENCE_NTSC[3]);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn five_step_sequence_has_no_frame_irq() {
+        let mut apu = Apu::new(false, 44100);
+        apu.write(0x4017, 0b1000_0000);
+        run(&mut apu, FIVE_STEP_SEQUENCE_NTSC[4] * 2);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn pal_sequence() {
+        let mut apu = Apu::new(true, 44100);
+        run(&mut apu, FOUR_STEP_SEQUENCE_NTSC[3]);
+        assert!(!apu.irq());
+        run(&mut apu, FOUR_STEP_SEQUENCE_PAL[3] - FOUR_STEP_SEQUENCE_NTSC[3]);
+        assert!(apu.irq());
+    }
+
+    // The length counter is clocked on the second and fourth step of the 4-step sequence
+    #[test]
+    fn length_counter_runs_out_on_half_frames() {
+        let mut apu = Apu::new(false, 44100);
+        start_pulse(&mut apu);
+        assert_eq!(apu.read(0x4015) & 0b0000_0001, 1);
+
+        run(&mut apu, FOUR_STEP_SEQUENCE_NTSC[1]);
+        assert_eq!(apu.read(0x4015) & 0b0000_0001, 1);
+        run(&mut apu, FOUR_STEP_SEQUENCE_NTSC[3] - FOUR_STEP_SEQUENCE_NTSC[1]);
+        assert_eq!(apu.read(0x4015) & 0b0000_0001, 0);
+    }
+
+    // Switching to the 5-step sequence clocks a half frame right away
+    #[test]
+    fn five_step_sequence_clocks_immediately() {
+        let mut apu = Apu::new(false, 44100);
+        start_pulse(&mut apu);
+        apu.write(0x4017, 0b1000_0000);
+        run(&mut apu, FIVE_STEP_SEQUENCE_NTSC[1] - 1);
+        assert_eq!(apu.read(0x4015) & 0b0000_0001, 1);
+        run(&mut apu, 1);
+        assert_eq!(apu.read(0x4015) & 0b0000_0001, 0);
+    }
+
+    #[test]
+    fn halted_length_counter() {
+        let mut apu = Apu::new(false, 44100);
+        apu.write(0x4000, 0b0010_0000);
+        start_pulse(&mut apu);
+        run(&mut apu, FOUR_STEP_SEQUENCE_NTSC[3] * 2);
+        assert_eq!(apu.read(0x4015) & 0b0000_0001, 1);
+
+        // Disabling the channel clears the length counter
+        apu.write(0x4015, 0);
+        assert_eq!(apu.read(0x4015) & 0b0000_0001, 0);
+    }
+
+    // A second of cycles gives a second of samples, give or take the one that's still being built up
+    #[test]
+    fn samples_are_produced_at_the_sample_rate() {
+        let mut apu = Apu::new(false, 44100);
+        run(&mut apu, NTSC_CLOCK_RATE as u32);
+        assert!((44099..=44100).contains(&apu.take_samples().len()));
+        assert!(apu.take_samples().is_empty());
+    }
+}
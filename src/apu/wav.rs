@@ -0,0 +1,50 @@
+use byteorder::{ByteOrder, LittleEndian};
+
+const HEADER_LENGTH: usize = 44;
+
+// Encodes 16-bit mono samples as a RIFF WAVE file
+// Ref: http://soundfile.sapp.org/doc/WaveFormat/
+pub fn encode(samples: &[i16], sample_rate: u32) -> Vec<u8> {
+    let data_length = samples.len() * 2;
+    let mut wav = vec![0; HEADER_LENGTH + data_length];
+
+    wav[0..4].copy_from_slice(b"RIFF");
+    LittleEndian::write_u32(&mut wav[4..], (HEADER_LENGTH - 8 + data_length) as u32);
+    wav[8..12].copy_from_slice(b"WAVE");
+
+    wav[12..16].copy_from_slice(b"fmt ");
+    LittleEndian::write_u32(&mut wav[16..], 16);                // Length of the format chunk
+    LittleEndian::write_u16(&mut wav[20..], 1);                 // PCM
+    LittleEndian::write_u16(&mut wav[22..], 1);                 // Mono
+    LittleEndian::write_u32(&mut wav[24..], sample_rate);
+    LittleEndian::write_u32(&mut wav[28..], sample_rate * 2);   // Bytes per second
+    LittleEndian::write_u16(&mut wav[32..], 2);                 // Bytes per sample
+    LittleEndian::write_u16(&mut wav[34..], 16);                // Bits per sample
+
+    wav[36..40].copy_from_slice(b"data");
+    LittleEndian::write_u32(&mut wav[40..], data_length as u32);
+    LittleEndian::write_i16_into(samples, &mut wav[HEADER_LENGTH..]);
+
+    wav
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header() {
+        let wav = encode(&[1, -2], 44100);
+        assert_eq!(wav.len(), HEADER_LENGTH + 4);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(LittleEndian::read_u32(&wav[4..]), 40);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(LittleEndian::read_u32(&wav[16..]), 16);
+        assert_eq!((LittleEndian::read_u16(&wav[20..]), LittleEndian::read_u16(&wav[22..])), (1, 1));
+        assert_eq!((LittleEndian::read_u32(&wav[24..]), LittleEndian::read_u32(&wav[28..])), (44100, 88200));
+        assert_eq!((LittleEndian::read_u16(&wav[32..]), LittleEndian::read_u16(&wav[34..])), (2, 16));
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(LittleEndian::read_u32(&wav[40..]), 4);
+        assert_eq!(&wav[HEADER_LENGTH..], &[0x01, 0x00, 0xFE, 0xFF]);
+    }
+}
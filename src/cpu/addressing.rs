@@ -0,0 +1,214 @@
+use cpu::{Address, AddressingMode, Cpu};
+
+// The operand of an instruction, resolved from its addressing mode
+// Immediate operands are represented by the address of the byte following the opcode
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Operand {
+    Implied,
+    Accumulator,
+    Memory(Address)
+}
+
+impl Operand {
+    pub fn address(&self) -> Address {
+        match *self {
+            Operand::Memory(address) => address,
+            _ => panic!("Attempted to take the address of a non-memory operand!")
+        }
+    }
+}
+
+// The outcome of resolving an addressing mode against the current CPU state
+// Indexed modes that cross a page boundary cost an extra cycle on reads, as does a branch to another page
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Resolved {
+    pub operand: Operand,
+    pub page_crossed: bool
+}
+
+impl Resolved {
+    fn new(operand: Operand) -> Resolved {
+        Resolved { operand, page_crossed: false }
+    }
+
+    fn indexed(base: Address, address: Address) -> Resolved {
+        Resolved { operand: Operand::Memory(address), page_crossed: page_crossed(base, address) }
+    }
+}
+
+// Implemented by each addressing mode to compute the effective operand of the instruction at the Program Counter
+pub trait Addressing {
+    fn resolve(&self, cpu: &mut Cpu) -> Resolved;
+}
+
+// Structs for each addressing mode that implement the Addressing trait
+// Ref: http://www.thealmightyguru.com/Games/Hacking/Wiki/index.php?title=Addressing_Modes
+#[derive(Debug, Copy, Clone)]
+pub struct ImmediateAddressing;
+#[derive(Debug, Copy, Clone)]
+pub struct ZeroPageAddressing;
+#[derive(Debug, Copy, Clone)]
+pub struct AbsoluteAddressing;
+#[derive(Debug, Copy, Clone)]
+pub struct ImpliedAddressing;
+#[derive(Debug, Copy, Clone)]
+pub struct AccumulatorAddressing;
+#[derive(Debug, Copy, Clone)]
+pub struct IndexedXAddressing;
+#[derive(Debug, Copy, Clone)]
+pub struct IndexedYAddressing;
+#[derive(Debug, Copy, Clone)]
+pub struct ZeroPageIndexedXAddressing;
+#[derive(Debug, Copy, Clone)]
+pub struct ZeroPageIndexedYAddressing;
+#[derive(Debug, Copy, Clone)]
+pub struct IndirectAddressing;
+#[derive(Debug, Copy, Clone)]
+pub struct PreIndexedIndirect;
+#[derive(Debug, Copy, Clone)]
+pub struct PostIndexedIndirectAddressing;
+#[derive(Debug, Copy, Clone)]
+pub struct RelativeAddressing;
+
+impl AddressingMode {
+    // Resolves the operand of the instruction at the Program Counter using the matching addressing struct
+    pub fn resolve(&self, cpu: &mut Cpu) -> Resolved {
+        match *self {
+            AddressingMode::ZPG => ZeroPageAddressing.resolve(cpu),
+            AddressingMode::ZPX => ZeroPageIndexedXAddressing.resolve(cpu),
+            AddressingMode::ZPY => ZeroPageIndexedYAddressing.resolve(cpu),
+            AddressingMode::ABS => AbsoluteAddressing.resolve(cpu),
+            AddressingMode::ABX => IndexedXAddressing.resolve(cpu),
+            AddressingMode::ABY => IndexedYAddressing.resolve(cpu),
+            AddressingMode::IND => IndirectAddressing.resolve(cpu),
+            AddressingMode::IMP => ImpliedAddressing.resolve(cpu),
+            AddressingMode::ACC => AccumulatorAddressing.resolve(cpu),
+            AddressingMode::IMM => ImmediateAddressing.resolve(cpu),
+            AddressingMode::REL => RelativeAddressing.resolve(cpu),
+            AddressingMode::IDX => PreIndexedIndirect.resolve(cpu),
+            AddressingMode::IDY => PostIndexedIndirectAddressing.resolve(cpu),
+            AddressingMode::UNKNOWN => ImpliedAddressing.resolve(cpu)
+        }
+    }
+}
+
+// Instructions without operands still read the byte following the opcode, and then ignore it
+impl Addressing for ImpliedAddressing {
+    fn resolve(&self, cpu: &mut Cpu) -> Resolved {
+        operand_u8(cpu);
+        Resolved::new(Operand::Implied)
+    }
+}
+
+impl Addressing for AccumulatorAddressing {
+    fn resolve(&self, cpu: &mut Cpu) -> Resolved {
+        operand_u8(cpu);
+        Resolved::new(Operand::Accumulator)
+    }
+}
+
+impl Addressing for ImmediateAddressing {
+    fn resolve(&self, cpu: &mut Cpu) -> Resolved {
+        Resolved::new(Operand::Memory(cpu.registers.pc.wrapping_add(1)))
+    }
+}
+
+impl Addressing for ZeroPageAddressing {
+    fn resolve(&self, cpu: &mut Cpu) -> Resolved {
+        let address = operand_u8(cpu) as Address;
+        Resolved::new(Operand::Memory(address))
+    }
+}
+
+// Indexing a zero page address wraps around within the zero page, eg: LDA $FF,X with X = 1 reads $0000
+// The unindexed address is read while the index is being added
+impl Addressing for ZeroPageIndexedXAddressing {
+    fn resolve(&self, cpu: &mut Cpu) -> Resolved {
+        let base = operand_u8(cpu);
+        cpu.read(base as Address);
+        let address = base.wrapping_add(cpu.registers.x) as Address;
+        Resolved::new(Operand::Memory(address))
+    }
+}
+
+impl Addressing for ZeroPageIndexedYAddressing {
+    fn resolve(&self, cpu: &mut Cpu) -> Resolved {
+        let base = operand_u8(cpu);
+        cpu.read(base as Address);
+        let address = base.wrapping_add(cpu.registers.y) as Address;
+        Resolved::new(Operand::Memory(address))
+    }
+}
+
+impl Addressing for AbsoluteAddressing {
+    fn resolve(&self, cpu: &mut Cpu) -> Resolved {
+        let address = operand_u16(cpu);
+        Resolved::new(Operand::Memory(address))
+    }
+}
+
+impl Addressing for IndexedXAddressing {
+    fn resolve(&self, cpu: &mut Cpu) -> Resolved {
+        let base = operand_u16(cpu);
+        Resolved::indexed(base, base.wrapping_add(cpu.registers.x as Address))
+    }
+}
+
+impl Addressing for IndexedYAddressing {
+    fn resolve(&self, cpu: &mut Cpu) -> Resolved {
+        let base = operand_u16(cpu);
+        Resolved::indexed(base, base.wrapping_add(cpu.registers.y as Address))
+    }
+}
+
+// Only used by JMP, reproduces the bug where a pointer at $xxFF fetches its high byte from $xx00
+// Ref: http://www.6502.org/tutorials/6502opcodes.html#JMP
+impl Addressing for IndirectAddressing {
+    fn resolve(&self, cpu: &mut Cpu) -> Resolved {
+        let pointer = operand_u16(cpu);
+        Resolved::new(Operand::Memory(cpu.read_u16_wrapped(pointer)))
+    }
+}
+
+// The pointer is fetched from the zero page after adding X, both the index and the pointer wrap around within the zero page
+// Like the zero page indexed modes, the unindexed address is read while X is being added
+impl Addressing for PreIndexedIndirect {
+    fn resolve(&self, cpu: &mut Cpu) -> Resolved {
+        let base = operand_u8(cpu);
+        cpu.read(base as Address);
+        let pointer = base.wrapping_add(cpu.registers.x);
+        Resolved::new(Operand::Memory(cpu.read_u16_wrapped(pointer as Address)))
+    }
+}
+
+// The pointer is fetched from the zero page (wrapping around within it), Y is added to the address it points to
+impl Addressing for PostIndexedIndirectAddressing {
+    fn resolve(&self, cpu: &mut Cpu) -> Resolved {
+        let pointer = operand_u8(cpu);
+        let base = cpu.read_u16_wrapped(pointer as Address);
+        Resolved::indexed(base, base.wrapping_add(cpu.registers.y as Address))
+    }
+}
+
+// The signed offset is relative to the address of the next instruction, which is also the page used for the page-cross check
+impl Addressing for RelativeAddressing {
+    fn resolve(&self, cpu: &mut Cpu) -> Resolved {
+        let offset = operand_u8(cpu) as i8;
+        let next = cpu.registers.pc.wrapping_add(2);
+        Resolved::indexed(next, next.wrapping_add(offset as Address))
+    }
+}
+
+fn operand_u8(cpu: &mut Cpu) -> u8 {
+    let pc = cpu.registers.pc;
+    cpu.read(pc.wrapping_add(1))
+}
+
+fn operand_u16(cpu: &mut Cpu) -> u16 {
+    let pc = cpu.registers.pc;
+    cpu.read_u16(pc.wrapping_add(1))
+}
+
+fn page_crossed(a: Address, b: Address) -> bool {
+    a & 0xFF00 != b & 0xFF00
+}
@@ -17,6 +17,7 @@ pub enum Mnemonic {
     CLC, CLD, CLI, CLV, CMP, CPX, CPY, SEC, SED, SEI,               // Registers
     PHA, PHP, PLA, PLP,                                             // Stack
     BRK, NOP,                                                       // System
+    LAX, SAX, DCP, ISC, SLO, RLA, SRE, RRA, ANC, ALR, ARR, AXS, KIL, // Unofficial
     UNKNOWN
 }
 
@@ -106,9 +107,9 @@ pub fn decode(opcode: OpCode) -> Instruction {
             0xe9 => (Mnemonic::SBC, AddressingMode::IMM, 2, 2),
             0xe5 => (Mnemonic::SBC, AddressingMode::ZPG, 2, 3),
             0xf5 => (Mnemonic::SBC, AddressingMode::ZPX, 2, 4),
-            0xed => (Mnemonic::SBC, AddressingMode::ABS, 3, 5),
-            0xfd => (Mnemonic::SBC, AddressingMode::ABX, 3, 5),
-            0xf9 => (Mnemonic::SBC, AddressingMode::ABY, 3, 5),
+            0xed => (Mnemonic::SBC, AddressingMode::ABS, 3, 4),
+            0xfd => (Mnemonic::SBC, AddressingMode::ABX, 3, 4),
+            0xf9 => (Mnemonic::SBC, AddressingMode::ABY, 3, 4),
             0xe1 => (Mnemonic::SBC, AddressingMode::IDX, 2, 6),
             0xf1 => (Mnemonic::SBC, AddressingMode::IDY, 2, 5),
             // AND
@@ -155,16 +156,16 @@ pub fn decode(opcode: OpCode) -> Instruction {
             0x11 => (Mnemonic::ORA, AddressingMode::IDY, 2, 5),
             // ROL
             0x2a => (Mnemonic::ROL, AddressingMode::ACC, 1, 2),
-            0x26 => (Mnemonic::ROL, AddressingMode::ACC, 2, 5),
-            0x36 => (Mnemonic::ROL, AddressingMode::ACC, 2, 6),
-            0x2e => (Mnemonic::ROL, AddressingMode::ACC, 3, 6),
-            0x3e => (Mnemonic::ROL, AddressingMode::ACC, 3, 7),
+            0x26 => (Mnemonic::ROL, AddressingMode::ZPG, 2, 5),
+            0x36 => (Mnemonic::ROL, AddressingMode::ZPX, 2, 6),
+            0x2e => (Mnemonic::ROL, AddressingMode::ABS, 3, 6),
+            0x3e => (Mnemonic::ROL, AddressingMode::ABX, 3, 7),
             // ROR
             0x6a => (Mnemonic::ROR, AddressingMode::ACC, 1, 2),
-            0x66 => (Mnemonic::ROR, AddressingMode::ACC, 2, 5),
-            0x76 => (Mnemonic::ROR, AddressingMode::ACC, 2, 6),
-            0x6e => (Mnemonic::ROR, AddressingMode::ACC, 3, 6),
-            0x7e => (Mnemonic::ROR, AddressingMode::ACC, 3, 7),
+            0x66 => (Mnemonic::ROR, AddressingMode::ZPG, 2, 5),
+            0x76 => (Mnemonic::ROR, AddressingMode::ZPX, 2, 6),
+            0x6e => (Mnemonic::ROR, AddressingMode::ABS, 3, 6),
+            0x7e => (Mnemonic::ROR, AddressingMode::ABX, 3, 7),
             // BPL
             0x10 => (Mnemonic::BPL, AddressingMode::REL, 2, 2),
             // MBI
@@ -234,6 +235,92 @@ pub fn decode(opcode: OpCode) -> Instruction {
             // NOP
             0xea => (Mnemonic::NOP, AddressingMode::IMP, 1, 2),
 
+            // Unofficial opcodes
+            // Ref: https://wiki.nesdev.com/w/index.php/CPU_unofficial_opcodes
+            // Ref: http://www.oxyron.de/html/opcodes02.html
+            // LAX
+            0xa7 => (Mnemonic::LAX, AddressingMode::ZPG, 2, 3),
+            0xb7 => (Mnemonic::LAX, AddressingMode::ZPY, 2, 4),
+            0xaf => (Mnemonic::LAX, AddressingMode::ABS, 3, 4),
+            0xbf => (Mnemonic::LAX, AddressingMode::ABY, 3, 4),
+            0xa3 => (Mnemonic::LAX, AddressingMode::IDX, 2, 6),
+            0xb3 => (Mnemonic::LAX, AddressingMode::IDY, 2, 5),
+            // SAX
+            0x87 => (Mnemonic::SAX, AddressingMode::ZPG, 2, 3),
+            0x97 => (Mnemonic::SAX, AddressingMode::ZPY, 2, 4),
+            0x8f => (Mnemonic::SAX, AddressingMode::ABS, 3, 4),
+            0x83 => (Mnemonic::SAX, AddressingMode::IDX, 2, 6),
+            // DCP
+            0xc7 => (Mnemonic::DCP, AddressingMode::ZPG, 2, 5),
+            0xd7 => (Mnemonic::DCP, AddressingMode::ZPX, 2, 6),
+            0xcf => (Mnemonic::DCP, AddressingMode::ABS, 3, 6),
+            0xdf => (Mnemonic::DCP, AddressingMode::ABX, 3, 7),
+            0xdb => (Mnemonic::DCP, AddressingMode::ABY, 3, 7),
+            0xc3 => (Mnemonic::DCP, AddressingMode::IDX, 2, 8),
+            0xd3 => (Mnemonic::DCP, AddressingMode::IDY, 2, 8),
+            // ISC
+            0xe7 => (Mnemonic::ISC, AddressingMode::ZPG, 2, 5),
+            0xf7 => (Mnemonic::ISC, AddressingMode::ZPX, 2, 6),
+            0xef => (Mnemonic::ISC, AddressingMode::ABS, 3, 6),
+            0xff => (Mnemonic::ISC, AddressingMode::ABX, 3, 7),
+            0xfb => (Mnemonic::ISC, AddressingMode::ABY, 3, 7),
+            0xe3 => (Mnemonic::ISC, AddressingMode::IDX, 2, 8),
+            0xf3 => (Mnemonic::ISC, AddressingMode::IDY, 2, 8),
+            // SLO
+            0x07 => (Mnemonic::SLO, AddressingMode::ZPG, 2, 5),
+            0x17 => (Mnemonic::SLO, AddressingMode::ZPX, 2, 6),
+            0x0f => (Mnemonic::SLO, AddressingMode::ABS, 3, 6),
+            0x1f => (Mnemonic::SLO, AddressingMode::ABX, 3, 7),
+            0x1b => (Mnemonic::SLO, AddressingMode::ABY, 3, 7),
+            0x03 => (Mnemonic::SLO, AddressingMode::IDX, 2, 8),
+            0x13 => (Mnemonic::SLO, AddressingMode::IDY, 2, 8),
+            // RLA
+            0x27 => (Mnemonic::RLA, AddressingMode::ZPG, 2, 5),
+            0x37 => (Mnemonic::RLA, AddressingMode::ZPX, 2, 6),
+            0x2f => (Mnemonic::RLA, AddressingMode::ABS, 3, 6),
+            0x3f => (Mnemonic::RLA, AddressingMode::ABX, 3, 7),
+            0x3b => (Mnemonic::RLA, AddressingMode::ABY, 3, 7),
+            0x23 => (Mnemonic::RLA, AddressingMode::IDX, 2, 8),
+            0x33 => (Mnemonic::RLA, AddressingMode::IDY, 2, 8),
+            // SRE
+            0x47 => (Mnemonic::SRE, AddressingMode::ZPG, 2, 5),
+            0x57 => (Mnemonic::SRE, AddressingMode::ZPX, 2, 6),
+            0x4f => (Mnemonic::SRE, AddressingMode::ABS, 3, 6),
+            0x5f => (Mnemonic::SRE, AddressingMode::ABX, 3, 7),
+            0x5b => (Mnemonic::SRE, AddressingMode::ABY, 3, 7),
+            0x43 => (Mnemonic::SRE, AddressingMode::IDX, 2, 8),
+            0x53 => (Mnemonic::SRE, AddressingMode::IDY, 2, 8),
+            // RRA
+            0x67 => (Mnemonic::RRA, AddressingMode::ZPG, 2, 5),
+            0x77 => (Mnemonic::RRA, AddressingMode::ZPX, 2, 6),
+            0x6f => (Mnemonic::RRA, AddressingMode::ABS, 3, 6),
+            0x7f => (Mnemonic::RRA, AddressingMode::ABX, 3, 7),
+            0x7b => (Mnemonic::RRA, AddressingMode::ABY, 3, 7),
+            0x63 => (Mnemonic::RRA, AddressingMode::IDX, 2, 8),
+            0x73 => (Mnemonic::RRA, AddressingMode::IDY, 2, 8),
+            // ANC
+            0x0b => (Mnemonic::ANC, AddressingMode::IMM, 2, 2),
+            0x2b => (Mnemonic::ANC, AddressingMode::IMM, 2, 2),
+            // ALR
+            0x4b => (Mnemonic::ALR, AddressingMode::IMM, 2, 2),
+            // ARR
+            0x6b => (Mnemonic::ARR, AddressingMode::IMM, 2, 2),
+            // AXS
+            0xcb => (Mnemonic::AXS, AddressingMode::IMM, 2, 2),
+            // SBC
+            0xeb => (Mnemonic::SBC, AddressingMode::IMM, 2, 2),
+            // NOP
+            0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa => (Mnemonic::NOP, AddressingMode::IMP, 1, 2),
+            0x80 | 0x82 | 0x89 | 0xc2 | 0xe2        => (Mnemonic::NOP, AddressingMode::IMM, 2, 2),
+            0x04 | 0x44 | 0x64                      => (Mnemonic::NOP, AddressingMode::ZPG, 2, 3),
+            0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 => (Mnemonic::NOP, AddressingMode::ZPX, 2, 4),
+            0x0c                                    => (Mnemonic::NOP, AddressingMode::ABS, 3, 4),
+            0x1c | 0x3c | 0x5c | 0x7c | 0xdc | 0xfc => (Mnemonic::NOP, AddressingMode::ABX, 3, 4),
+            // KIL
+            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 |
+            0x62 | 0x72 | 0x92 | 0xb2 | 0xd2 | 0xf2 => (Mnemonic::KIL, AddressingMode::IMP, 1, 2),
+
+            // The remaining opcodes ($8B, $93, $9B, $9C, $9E, $9F, $AB and $BB) are unstable on real hardware
             _    => (Mnemonic::UNKNOWN, AddressingMode::UNKNOWN, 1, 1)
         };
 
@@ -253,6 +340,28 @@ impl Instruction {
     fn new(opcode: OpCode, mnemonic: Mnemonic, mode: AddressingMode, length: u8, cycles: u8) -> Instruction {
         Instruction { opcode: opcode, mnemonic: mnemonic, mode: mode, length: length, cycles: cycles }
     }
+
+    // Whether the opcode is part of the documented 6502 instruction set
+    pub fn is_official(&self) -> bool {
+        match self.mnemonic {
+            Mnemonic::LAX | Mnemonic::SAX | Mnemonic::DCP | Mnemonic::ISC | Mnemonic::SLO | Mnemonic::RLA | Mnemonic::SRE |
+            Mnemonic::RRA | Mnemonic::ANC | Mnemonic::ALR | Mnemonic::ARR | Mnemonic::AXS | Mnemonic::KIL | Mnemonic::UNKNOWN => false,
+            Mnemonic::NOP => self.opcode == 0xea,
+            Mnemonic::SBC => self.opcode != 0xeb,
+            _ => true
+        }
+    }
+
+    // Whether an indexed operand crossing a page boundary costs this instruction an extra cycle
+    // Only instructions that just read their operand pay this penalty, stores and read-modify-write instructions
+    // always take the worst case which is already included in their base cycle count
+    // Ref: http://www.obelisk.me.uk/6502/reference.html
+    pub fn has_page_cross_penalty(&self) -> bool {
+        matches!(self.mnemonic,
+            Mnemonic::LDA | Mnemonic::LDX | Mnemonic::LDY |
+            Mnemonic::ADC | Mnemonic::SBC | Mnemonic::AND | Mnemonic::EOR | Mnemonic::ORA |
+            Mnemonic::CMP | Mnemonic::LAX | Mnemonic::NOP)
+    }
 }
 
 impl Display for Instruction {
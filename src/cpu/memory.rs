@@ -1,9 +1,84 @@
-type Address = u16;
+use cpu::Address;
+use nes::mapper::Mapper;
 
-trait Memory {
-    type Storage;
+// The NES CPU had access to 2Kb (or 8192 bytes of RAM)
+// Ref: https://en.wikipedia.org/wiki/Nintendo_Entertainment_System#Technical_specifications
+pub type WorkingMemory = [u8; 2048];
 
-    fn fetch(&Address) -> u8;
-    fn store(&Address, &u8);
+// Anything that can be attached to the CPU address bus
+// Reads take a mutable reference since reading a memory-mapped register can have side effects
+pub trait Memory {
+    fn read(&mut self, address: Address) -> u8;
+    fn write(&mut self, address: Address, value: u8);
 
-}
\ No newline at end of file
+    // Reads a byte without any side effects, for debugging facilities like the trace logger
+    fn peek(&self, address: Address) -> u8;
+
+    // Lets hardware that keeps time along with the CPU, like IRQ counters, run for the given amount of cycles
+    fn clock(&mut self, _cycles: u8) {}
+
+    // Whether the hardware is asserting the IRQ line
+    fn irq(&self) -> bool {
+        false
+    }
+}
+
+// The CPU memory map of the NES
+// Ref: https://wiki.nesdev.com/w/index.php/CPU_memory_map
+// $0000-$07FF  2KB internal RAM
+// $0800-$1FFF  Mirrors of $0000-$07FF
+// $2000-$2007  PPU registers
+// $2008-$3FFF  Mirrors of $2000-$2007 (repeats every 8 bytes)
+// $4000-$4017  APU and I/O registers
+// $4018-$401F  APU and I/O functionality that is normally disabled
+// $4020-$FFFF  Cartridge space: PRG ROM, PRG RAM and mapper registers
+pub struct CpuBus {
+    ram: WorkingMemory,
+    ppu_registers: [u8; 8],
+    io_registers: [u8; 0x20],
+    cartridge: Box<dyn Mapper>
+}
+
+impl CpuBus {
+    pub fn new(cartridge: Box<dyn Mapper>) -> CpuBus {
+        CpuBus { ram: [0; 2048], ppu_registers: [0; 8], io_registers: [0; 0x20], cartridge }
+    }
+}
+
+// TODO: The PPU and APU don't exist yet, so their registers simply hold on to the last value written to them
+impl Memory for CpuBus {
+    fn read(&mut self, address: Address) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.ram[(address & 0x07FF) as usize],
+            0x2000..=0x3FFF => self.ppu_registers[(address & 0x0007) as usize],
+            0x4000..=0x401F => self.io_registers[(address & 0x001F) as usize],
+            _               => self.cartridge.read(address)
+        }
+    }
+
+    fn write(&mut self, address: Address, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram[(address & 0x07FF) as usize] = value,
+            0x2000..=0x3FFF => self.ppu_registers[(address & 0x0007) as usize] = value,
+            0x4000..=0x401F => self.io_registers[(address & 0x001F) as usize] = value,
+            _               => self.cartridge.write(address, value)
+        }
+    }
+
+    fn peek(&self, address: Address) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.ram[(address & 0x07FF) as usize],
+            0x2000..=0x3FFF => self.ppu_registers[(address & 0x0007) as usize],
+            0x4000..=0x401F => self.io_registers[(address & 0x001F) as usize],
+            _               => self.cartridge.peek(address)
+        }
+    }
+
+    fn clock(&mut self, cycles: u8) {
+        self.cartridge.clock(cycles);
+    }
+
+    fn irq(&self) -> bool {
+        self.cartridge.irq()
+    }
+}
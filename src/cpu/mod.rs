@@ -1,10 +1,18 @@
+pub mod addressing;
 pub mod disassembler;
 pub mod instructions;
 pub mod memory;
+mod trace;
+#[cfg(test)]
+mod processor_tests;
+#[cfg(test)]
+mod tests;
 
-// The NES CPU had access to 2Kb (or 8192 bytes of RAM)
-// Ref: https://en.wikipedia.org/wiki/Nintendo_Entertainment_System#Technical_specifications
-type WorkingMemory = [u8; 2048];
+use std::io::Write;
+
+use cpu::addressing::{Operand, Resolved};
+use cpu::instructions::{Instruction, Mnemonic};
+use cpu::memory::Memory;
 
 // Type aliases for the individual registers of the CPU
 // Ref: https://wiki.nesdev.com/w/index.php/CPU_registers
@@ -34,12 +42,28 @@ bitflags! {
         const Z = 0b0000_0010;  // Zero
         const I = 0b0000_0100;  // Interrupt
         const D = 0b0000_1000;  // Decimal
+        const B = 0b0001_0000;  // Break (only exists in the copy pushed on the stack)
+        const U = 0b0010_0000;  // Unused (always pushed as 1)
         const V = 0b0100_0000;  // Overflow
         const N = 0b1000_0000;  // Negative
     }
 }
 
-type Address = u16;
+pub type Address = u16;
+
+// Addresses of the interrupt vectors, each holding the address of the handler for that interrupt
+// Ref: https://wiki.nesdev.com/w/index.php/CPU_interrupts
+const NMI_VECTOR: Address = 0xFFFA;
+const RESET_VECTOR: Address = 0xFFFC;
+const IRQ_VECTOR: Address = 0xFFFE;
+
+// The 6502 variants this core can act as
+// Ref: https://wiki.nesdev.com/w/index.php/CPU
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Variant {
+    RP2A03,     // Ricoh 2A03/2A07 as found in the NES, decimal mode is disconnected and the D flag is ignored
+    NMOS6502    // Original NMOS 6502, ADC and SBC perform BCD arithmetic when the D flag is set
+}
 
 // All possible 6502 addressing modes
 // Addressing modes define how the CPU fetched the required operands for an instructions
@@ -62,40 +86,18 @@ pub enum AddressingMode {
     UNKNOWN
 }
 
-// Structs for each addressing mode that implement the Addressing trait
-// Ref: http://www.thealmightyguru.com/Games/Hacking/Wiki/index.php?title=Addressing_Modes
-#[derive(Debug, Copy, Clone)]
-struct ImmediateAddressing;
-#[derive(Debug, Copy, Clone)]
-struct ZeroPageAddressing;
-#[derive(Debug, Copy, Clone)]
-struct AbsoluteAddressing;
-#[derive(Debug, Copy, Clone)]
-struct ImpliedAddressing;
-#[derive(Debug, Copy, Clone)]
-struct AccumulatorAddressing;
-#[derive(Debug, Copy, Clone)]
-struct IndexedXAddressing;
-#[derive(Debug, Copy, Clone)]
-struct IndexedYAddressing;
-#[derive(Debug, Copy, Clone)]
-struct ZeroPageIndexedXAddressing;
-#[derive(Debug, Copy, Clone)]
-struct ZeroPageIndexedYAddressing;
-#[derive(Debug, Copy, Clone)]
-struct IndirectAddressing;
-#[derive(Debug, Copy, Clone)]
-struct PreIndexedIndirect;
-#[derive(Debug, Copy, Clone)]
-struct PostIndexedIndirectAddressing;
-#[derive(Debug, Copy, Clone)]
-struct RelativeAddressing;
-
 pub struct Cpu {
-    memory: WorkingMemory,
+    bus: Box<dyn Memory>,
+    variant: Variant,
     registers: CpuRegisters,
     status: StatusRegister,
-    pub program: Vec<u8>
+    cycles: u64,
+    nmi_line: bool,                         // Whether the NMI input is currently asserted
+    nmi_pending: bool,                      // Set by the edge detector when the NMI input becomes asserted
+    irq_line: bool,                         // Whether the IRQ input is currently asserted
+    interrupt_pending: bool,                // Set when the lines polled at the end of the last instruction call for an interrupt
+    jammed: bool,                           // Set by KIL, the CPU stops responding to anything but a reset
+    trace: Option<Box<dyn Write>>           // Output for the trace mode, see trace.rs
 }
 
 struct CpuRegisters {
@@ -113,38 +115,611 @@ impl CpuRegisters {
 }
 
 impl Cpu {
-    pub fn new() -> Cpu {
-        // TODO: Figure out initial state of the Status Register
-        Cpu { memory: [0; 2048], registers: CpuRegisters::new(), status: StatusRegister{ bits: 0 }, program: vec![] }
+    // The CPU state is undefined until the machine is powered on, see power_on
+    pub fn new(bus: Box<dyn Memory>) -> Cpu {
+        Cpu {
+            bus,
+            variant: Variant::RP2A03,
+            registers: CpuRegisters::new(),
+            status: StatusRegister{ bits: 0 },
+            cycles: 0,
+            nmi_line: false,
+            nmi_pending: false,
+            irq_line: false,
+            interrupt_pending: false,
+            jammed: false,
+            trace: None
+        }
+    }
+
+    // Selects the 6502 variant to emulate, the default is the NES' 2A03
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.variant = variant;
     }
 
     // Powers on the machine and sets the initial state
     // Ref: https://wiki.nesdev.com/w/index.php/CPU_power_up_state
     // Ref: https://github.com/fogleman/nes/blob/master/nes/cpu.go#L261
-    pub fn power_on (&mut self) {
-        // TODO need clearer info on what this does precisely, Program Counter probably isn't correct
-        self.status.bits = 0x24;    // This is 0b0010_0100
-        self.registers.sp = 0xFD;
+    pub fn power_on(&mut self) {
+        // A, X and Y start out cleared. The stack pointer starts at $00, the reset sequence then brings it to $FD
+        self.registers = CpuRegisters::new();
+        self.status = StatusRegister::U;
+        self.cycles = 0;
+
+        // The contents of the RAM are unreliable at power on, we clear them like most emulators do
+        for address in 0x0000..0x0800 {
+            self.write(address, 0x00);
+        }
+
+        // Silence the APU ($4000-$4013, $4015) and enable the frame IRQ ($4017)
+        for address in 0x4000..0x4014 {
+            self.write(address, 0x00);
+        }
+        self.write(0x4017, 0x00);
+
+        self.reset();
     }
 
     // Resets the machine and sets the initial state
+    // A reset goes through the same sequence as an interrupt, except that the pushes to the stack are turned into reads.
+    // The stack pointer still gets decremented by 3, RAM and the A, X and Y registers are left as they were.
     // Ref: https://wiki.nesdev.com/w/index.php/CPU_power_up_state
-    // Ref: https://github.com/fogleman/nes/blob/master/nes/cpu.go#L261
+    // Ref: https://wiki.nesdev.com/w/index.php/CPU_interrupts
     pub fn reset(&mut self) {
-        // TODO need clearer info on what this does precisely, Program Counter probably isn't correct
-        self.status.bits = 0x24;    // This is 0b0010_0100
-        self.registers.sp = 0xFD;
+        self.registers.sp = self.registers.sp.wrapping_sub(3);
+        self.status.insert(StatusRegister::I);
+        self.write(0x4015, 0x00);
+
+        self.nmi_pending = false;
+        self.interrupt_pending = false;
+        self.jammed = false;
+
+        // The Program Counter is loaded from the RESET vector
+        self.registers.pc = self.read_u16(RESET_VECTOR);
+        self.cycles += 7;
+    }
+
+    // Drives the NMI input, an NMI is triggered when the input goes from inactive to asserted
+    // The input stays latched until it's serviced, so even a short pulse is never missed
+    pub fn set_nmi(&mut self, asserted: bool) {
+        if asserted && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = asserted;
+    }
+
+    // Drives the IRQ input, an IRQ is triggered for as long as the input is asserted and the I flag is clear
+    // Hardware on the bus, like a cartridge, can assert the line as well, see Memory::irq
+    pub fn set_irq(&mut self, asserted: bool) {
+        self.irq_line = asserted;
     }
 
     // Takes a single-step through the execution process, reading the first instruction at the Program Counter and executing it
-    pub fn step(&mut self) {
+    // Returns the amount of cycles the instruction took, including any page-cross or branch penalties
+    // Ref: http://nesdev.com/6502_cpu.txt
+    pub fn step(&mut self) -> u8 {
+        if self.interrupt_pending {
+            self.interrupt_pending = false;
+            self.service_interrupt();
+            self.bus.clock(7);
+            self.cycles += 7;
+            return 7;
+        }
+
+        self.write_trace();
+
+        let interrupts_disabled = self.status.contains(StatusRegister::I);
         let pc = self.registers.pc;
-        let instruction = instructions::decode(self.program[pc as usize]);
+        let instruction = instructions::decode(self.read(pc));
 
-        let operands: Vec<_> = self.program.iter().skip(pc as usize).take(instruction.length as usize).collect();
+        // JSR only fetches the high byte of its target after pushing the return address, so it resolves its own operand
+        let resolved = match instruction.mnemonic {
+            Mnemonic::JSR => Resolved { operand: Operand::Implied, page_crossed: false },
+            _ => instruction.mode.resolve(self)
+        };
 
-        println!("{} - Operands: {:?}", instruction, operands);
+        // Indexed modes first read from the address before the carry was added to its high byte
+        // Reads only need this extra cycle when a page was crossed, writes always take it
+        match instruction.mode {
+            AddressingMode::ABX | AddressingMode::ABY | AddressingMode::IDY
+                if resolved.page_crossed || !instruction.has_page_cross_penalty() => {
+                let address = resolved.operand.address();
+                self.read(if resolved.page_crossed { address.wrapping_sub(0x100) } else { address });
+            }
+            _ => ()
+        }
 
         self.registers.pc = pc.wrapping_add(instruction.length as u16);
+
+        let mut cycles = instruction.cycles + self.execute(&instruction, &resolved);
+        if resolved.page_crossed && instruction.has_page_cross_penalty() {
+            cycles += 1;
+        }
+
+        self.bus.clock(cycles);
+        self.poll_interrupts(&instruction, interrupts_disabled);
+
+        self.cycles += cycles as u64;
+        cycles
+    }
+
+    // Decides whether an interrupt should be serviced before the next instruction
+    // CLI, SEI and PLP change the I flag after the interrupt lines have been polled, so the old value of the flag
+    // still applies for one more instruction. RTI restores the flag before polling, so it takes effect immediately.
+    // Ref: https://wiki.nesdev.com/w/index.php/CPU_interrupts#Delayed_IRQ_response_after_CLI.2C_SEI.2C_and_PLP
+    fn poll_interrupts(&mut self, instruction: &Instruction, interrupts_disabled: bool) {
+        let masked = match instruction.mnemonic {
+            Mnemonic::CLI | Mnemonic::SEI | Mnemonic::PLP => interrupts_disabled,
+            _ => self.status.contains(StatusRegister::I)
+        };
+
+        let irq = self.irq_line || self.bus.irq();
+        self.interrupt_pending = !self.jammed && (self.nmi_pending || (irq && !masked));
+    }
+
+    // Runs the 7 cycle interrupt sequence for a hardware interrupt
+    // The pushed status has the B flag clear, which is how a handler can tell a hardware interrupt apart from BRK
+    // The sequence doesn't poll for interrupts, so the first instruction of the handler always gets executed
+    // Whether it's an NMI or an IRQ is only decided when the vector is fetched, see enter_interrupt
+    fn service_interrupt(&mut self) {
+        // The opcode fetch is suppressed, the interrupt sequence reads the next instruction twice without using it
+        let ret = self.registers.pc;
+        self.read(ret);
+        self.read(ret);
+
+        let status = self.status.bits | StatusRegister::U.bits;
+        self.enter_interrupt(ret, status);
+    }
+
+    // Pushes the return address and status and jumps to the interrupt handler
+    // If an NMI was detected before the vector is fetched, it hijacks the IRQ or BRK sequence and the NMI vector is
+    // used instead. The pushed status still shows where the sequence came from, so a BRK can get lost this way.
+    // Ref: https://wiki.nesdev.com/w/index.php/CPU_interrupts#Interrupt_hijacking
+    fn enter_interrupt(&mut self, ret: Address, status: u8) {
+        self.push_u16(ret);
+        self.push(status);
+        self.status.insert(StatusRegister::I);
+
+        let vector = if self.nmi_pending {
+            self.nmi_pending = false;
+            NMI_VECTOR
+        } else {
+            IRQ_VECTOR
+        };
+
+        self.registers.pc = self.read_u16(vector);
+    }
+
+    // Calls a subroutine with the given A and X, as if a JSR from just before the return address got there
+    // This lets a driver run code that isn't reached through the reset vector, like the routines of an NSF file
+    pub fn call(&mut self, address: Address, ret: Address, a: u8, x: u8) {
+        self.push_u16(ret.wrapping_sub(1));
+        self.registers.a = a;
+        self.registers.x = x;
+        self.registers.pc = address;
+    }
+
+    pub fn pc(&self) -> Address {
+        self.registers.pc
+    }
+
+    // The bus the CPU is attached to, for drivers that need to set up the hardware on it directly
+    pub fn bus_mut(&mut self) -> &mut dyn Memory {
+        &mut *self.bus
+    }
+
+    // Total amount of cycles the CPU has executed since it was powered on
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Executes a decoded instruction with its resolved operand
+    // Returns the amount of extra cycles taken on top of the base cycle count, which only applies to taken branches
+    // Ref: http://www.obelisk.me.uk/6502/reference.html
+    fn execute(&mut self, instruction: &Instruction, resolved: &Resolved) -> u8 {
+        let operand = resolved.operand;
+
+        match instruction.mnemonic {
+            // Storage
+            Mnemonic::LDA => { let value = self.load(&operand); self.registers.a = value; self.update_zero_and_negative(value); }
+            Mnemonic::LDX => { let value = self.load(&operand); self.registers.x = value; self.update_zero_and_negative(value); }
+            Mnemonic::LDY => { let value = self.load(&operand); self.registers.y = value; self.update_zero_and_negative(value); }
+            Mnemonic::STA => { let value = self.registers.a; self.store(&operand, value); }
+            Mnemonic::STX => { let value = self.registers.x; self.store(&operand, value); }
+            Mnemonic::STY => { let value = self.registers.y; self.store(&operand, value); }
+            Mnemonic::TAX => { let value = self.registers.a; self.registers.x = value; self.update_zero_and_negative(value); }
+            Mnemonic::TAY => { let value = self.registers.a; self.registers.y = value; self.update_zero_and_negative(value); }
+            Mnemonic::TSX => { let value = self.registers.sp; self.registers.x = value; self.update_zero_and_negative(value); }
+            Mnemonic::TXA => { let value = self.registers.x; self.registers.a = value; self.update_zero_and_negative(value); }
+            Mnemonic::TXS => { self.registers.sp = self.registers.x; }  // TXS is the only transfer that leaves the flags alone
+            Mnemonic::TYA => { let value = self.registers.y; self.registers.a = value; self.update_zero_and_negative(value); }
+
+            // Math
+            Mnemonic::ADC => { let value = self.load(&operand); self.add(value); }
+            Mnemonic::SBC => { let value = self.load(&operand); self.subtract(value); }
+            Mnemonic::DEC => { let value = self.load_for_modify(&operand).wrapping_sub(1); self.store(&operand, value); self.update_zero_and_negative(value); }
+            Mnemonic::DEX => { let value = self.registers.x.wrapping_sub(1); self.registers.x = value; self.update_zero_and_negative(value); }
+            Mnemonic::DEY => { let value = self.registers.y.wrapping_sub(1); self.registers.y = value; self.update_zero_and_negative(value); }
+            Mnemonic::INC => { let value = self.load_for_modify(&operand).wrapping_add(1); self.store(&operand, value); self.update_zero_and_negative(value); }
+            Mnemonic::INX => { let value = self.registers.x.wrapping_add(1); self.registers.x = value; self.update_zero_and_negative(value); }
+            Mnemonic::INY => { let value = self.registers.y.wrapping_add(1); self.registers.y = value; self.update_zero_and_negative(value); }
+
+            // Bitwise
+            Mnemonic::AND => { let value = self.registers.a & self.load(&operand); self.registers.a = value; self.update_zero_and_negative(value); }
+            Mnemonic::EOR => { let value = self.registers.a ^ self.load(&operand); self.registers.a = value; self.update_zero_and_negative(value); }
+            Mnemonic::ORA => { let value = self.registers.a | self.load(&operand); self.registers.a = value; self.update_zero_and_negative(value); }
+            Mnemonic::BIT => {
+                let value = self.load(&operand);
+                let result = self.registers.a & value;
+                self.status.set(StatusRegister::Z, result == 0);
+                self.status.set(StatusRegister::V, value & 0b0100_0000 != 0);
+                self.status.set(StatusRegister::N, value & 0b1000_0000 != 0);
+            }
+            Mnemonic::ASL => { let value = self.load_for_modify(&operand); let result = self.shift_left(value); self.store(&operand, result); self.update_zero_and_negative(result); }
+            Mnemonic::LSR => { let value = self.load_for_modify(&operand); let result = self.shift_right(value); self.store(&operand, result); self.update_zero_and_negative(result); }
+            Mnemonic::ROL => { let value = self.load_for_modify(&operand); let result = self.rotate_left(value); self.store(&operand, result); self.update_zero_and_negative(result); }
+            Mnemonic::ROR => { let value = self.load_for_modify(&operand); let result = self.rotate_right(value); self.store(&operand, result); self.update_zero_and_negative(result); }
+
+            // Branch
+            Mnemonic::BCC => { let condition = !self.status.contains(StatusRegister::C); return self.branch(condition, resolved); }
+            Mnemonic::BCS => { let condition = self.status.contains(StatusRegister::C); return self.branch(condition, resolved); }
+            Mnemonic::BEQ => { let condition = self.status.contains(StatusRegister::Z); return self.branch(condition, resolved); }
+            Mnemonic::BMI => { let condition = self.status.contains(StatusRegister::N); return self.branch(condition, resolved); }
+            Mnemonic::BNE => { let condition = !self.status.contains(StatusRegister::Z); return self.branch(condition, resolved); }
+            Mnemonic::BPL => { let condition = !self.status.contains(StatusRegister::N); return self.branch(condition, resolved); }
+            Mnemonic::BVC => { let condition = !self.status.contains(StatusRegister::V); return self.branch(condition, resolved); }
+            Mnemonic::BVS => { let condition = self.status.contains(StatusRegister::V); return self.branch(condition, resolved); }
+
+            // Jump
+            Mnemonic::JMP => { self.registers.pc = operand.address(); }
+            Mnemonic::JSR => {
+                // JSR pushes the address of its own last byte, RTS compensates for this
+                let ret = self.registers.pc.wrapping_sub(1);
+                let lo = self.read(ret.wrapping_sub(1)) as Address;
+                self.peek_stack();
+                self.push_u16(ret);
+                let hi = self.read(ret) as Address;
+                self.registers.pc = (hi << 8) | lo;
+            }
+            Mnemonic::RTI => {
+                self.peek_stack();
+                let status = self.pop();
+                self.pull_status(status);
+                self.registers.pc = self.pop_u16();
+            }
+            Mnemonic::RTS => {
+                self.peek_stack();
+                let ret = self.pop_u16();
+                self.read(ret);
+                self.registers.pc = ret.wrapping_add(1);
+            }
+
+            // Registers
+            Mnemonic::CLC => self.status.remove(StatusRegister::C),
+            Mnemonic::CLD => self.status.remove(StatusRegister::D),
+            Mnemonic::CLI => self.status.remove(StatusRegister::I),
+            Mnemonic::CLV => self.status.remove(StatusRegister::V),
+            Mnemonic::SEC => self.status.insert(StatusRegister::C),
+            Mnemonic::SED => self.status.insert(StatusRegister::D),
+            Mnemonic::SEI => self.status.insert(StatusRegister::I),
+            Mnemonic::CMP => { let (register, value) = (self.registers.a, self.load(&operand)); self.compare(register, value); }
+            Mnemonic::CPX => { let (register, value) = (self.registers.x, self.load(&operand)); self.compare(register, value); }
+            Mnemonic::CPY => { let (register, value) = (self.registers.y, self.load(&operand)); self.compare(register, value); }
+
+            // Stack
+            Mnemonic::PHA => { let value = self.registers.a; self.push(value); }
+            Mnemonic::PHP => { let value = self.status.bits | StatusRegister::B.bits | StatusRegister::U.bits; self.push(value); }
+            Mnemonic::PLA => { self.peek_stack(); let value = self.pop(); self.registers.a = value; self.update_zero_and_negative(value); }
+            Mnemonic::PLP => { self.peek_stack(); let value = self.pop(); self.pull_status(value); }
+
+            // System
+            Mnemonic::BRK => {
+                // BRK is followed by a padding byte, so the return address skips over it
+                let ret = self.registers.pc.wrapping_add(1);
+                let status = self.status.bits | StatusRegister::B.bits | StatusRegister::U.bits;
+                self.enter_interrupt(ret, status);
+            }
+            Mnemonic::NOP => {
+                // The unofficial NOPs with an operand still read from it
+                if let Operand::Memory(_) = operand {
+                    self.load(&operand);
+                }
+            }
+            Mnemonic::UNKNOWN => (),
+
+            // Unofficial, these mostly combine two official instructions into one
+            // Ref: https://wiki.nesdev.com/w/index.php/Programming_with_unofficial_opcodes
+            Mnemonic::LAX => { let value = self.load(&operand); self.registers.a = value; self.registers.x = value; self.update_zero_and_negative(value); }
+            Mnemonic::SAX => { let value = self.registers.a & self.registers.x; self.store(&operand, value); }
+            Mnemonic::DCP => { let value = self.load_for_modify(&operand).wrapping_sub(1); self.store(&operand, value); let register = self.registers.a; self.compare(register, value); }
+            Mnemonic::ISC => { let value = self.load_for_modify(&operand).wrapping_add(1); self.store(&operand, value); self.subtract(value); }
+            Mnemonic::SLO => {
+                let value = self.load_for_modify(&operand);
+                let result = self.shift_left(value);
+                self.store(&operand, result);
+                let value = self.registers.a | result; self.registers.a = value; self.update_zero_and_negative(value);
+            }
+            Mnemonic::RLA => {
+                let value = self.load_for_modify(&operand);
+                let result = self.rotate_left(value);
+                self.store(&operand, result);
+                let value = self.registers.a & result; self.registers.a = value; self.update_zero_and_negative(value);
+            }
+            Mnemonic::SRE => {
+                let value = self.load_for_modify(&operand);
+                let result = self.shift_right(value);
+                self.store(&operand, result);
+                let value = self.registers.a ^ result; self.registers.a = value; self.update_zero_and_negative(value);
+            }
+            Mnemonic::RRA => {
+                let value = self.load_for_modify(&operand);
+                let result = self.rotate_right(value);
+                self.store(&operand, result);
+                self.add(result);
+            }
+            Mnemonic::ANC => {
+                // AND, after which the Negative flag is copied into the Carry as if an ASL had been executed
+                let value = self.registers.a & self.load(&operand);
+                self.registers.a = value;
+                self.update_zero_and_negative(value);
+                self.status.set(StatusRegister::C, value & 0b1000_0000 != 0);
+            }
+            Mnemonic::ALR => {
+                let value = self.registers.a & self.load(&operand);
+                let result = self.shift_right(value);
+                self.registers.a = result;
+                self.update_zero_and_negative(result);
+            }
+            Mnemonic::ARR => {
+                // AND followed by ROR A, the Carry is taken from bit 6 of the result and Overflow from bit 6 xor bit 5
+                let value = self.registers.a & self.load(&operand);
+                let result = (value >> 1) | ((self.status.contains(StatusRegister::C) as u8) << 7);
+                self.registers.a = result;
+                self.update_zero_and_negative(result);
+                self.status.set(StatusRegister::C, result & 0b0100_0000 != 0);
+                self.status.set(StatusRegister::V, ((result >> 6) ^ (result >> 5)) & 1 != 0);
+            }
+            Mnemonic::AXS => {
+                // X = (A & X) - M without borrow, the flags are set like CMP
+                let register = self.registers.a & self.registers.x;
+                let value = self.load(&operand);
+                self.compare(register, value);
+                self.registers.x = register.wrapping_sub(value);
+            }
+            Mnemonic::KIL => {
+                // Locks up the CPU on the same instruction, only a reset brings it back
+                self.registers.pc = self.registers.pc.wrapping_sub(1);
+                self.jammed = true;
+            }
+        }
+
+        0
+    }
+
+    // Sets the Zero and Negative flags based on the result of the last operation
+    fn update_zero_and_negative(&mut self, value: u8) {
+        self.status.set(StatusRegister::Z, value == 0);
+        self.status.set(StatusRegister::N, value & 0b1000_0000 != 0);
+    }
+
+    // Shifts a value one bit to the left, the bit shifted out ends up in the Carry flag
+    fn shift_left(&mut self, value: u8) -> u8 {
+        self.status.set(StatusRegister::C, value & 0b1000_0000 != 0);
+        value << 1
+    }
+
+    // Shifts a value one bit to the right, the bit shifted out ends up in the Carry flag
+    fn shift_right(&mut self, value: u8) -> u8 {
+        self.status.set(StatusRegister::C, value & 0b0000_0001 != 0);
+        value >> 1
+    }
+
+    // Rotates a value one bit to the left through the Carry flag
+    fn rotate_left(&mut self, value: u8) -> u8 {
+        let result = (value << 1) | self.status.contains(StatusRegister::C) as u8;
+        self.status.set(StatusRegister::C, value & 0b1000_0000 != 0);
+        result
+    }
+
+    // Rotates a value one bit to the right through the Carry flag
+    fn rotate_right(&mut self, value: u8) -> u8 {
+        let result = (value >> 1) | ((self.status.contains(StatusRegister::C) as u8) << 7);
+        self.status.set(StatusRegister::C, value & 0b0000_0001 != 0);
+        result
+    }
+
+    // ADC, in BCD when the variant supports decimal mode and the D flag is set
+    fn add(&mut self, value: u8) {
+        if self.decimal_mode() {
+            self.add_decimal(value);
+        } else {
+            self.add_with_carry(value);
+        }
+    }
+
+    // SBC, in BCD when the variant supports decimal mode and the D flag is set
+    fn subtract(&mut self, value: u8) {
+        if self.decimal_mode() {
+            self.subtract_decimal(value);
+        } else {
+            self.add_with_carry(!value);    // A - M - (1 - C) == A + !M + C
+        }
+    }
+
+    fn decimal_mode(&self) -> bool {
+        self.variant == Variant::NMOS6502 && self.status.contains(StatusRegister::D)
+    }
+
+    // Adds a value and the carry to the accumulator, also used by SBC with the operand inverted
+    // Ref: http://www.righto.com/2012/12/the-6502-overflow-flag-explained.html
+    fn add_with_carry(&mut self, value: u8) {
+        let a = self.registers.a;
+        let sum = a as u16 + value as u16 + self.status.contains(StatusRegister::C) as u16;
+        let result = sum as u8;
+
+        self.status.set(StatusRegister::C, sum > 0xFF);
+        self.status.set(StatusRegister::V, (a ^ result) & (value ^ result) & 0b1000_0000 != 0);
+        self.registers.a = result;
+        self.update_zero_and_negative(result);
+    }
+
+    // BCD addition as performed by the NMOS 6502
+    // Only C and the accumulator are valid decimal results. Z is taken from the binary sum, while
+    // N and V are taken from the sum after the low nibble was adjusted but before the high nibble was
+    // Ref: http://www.6502.org/tutorials/decimal_mode.html#A
+    fn add_decimal(&mut self, value: u8) {
+        let a = self.registers.a as i16;
+        let b = value as i16;
+        let carry = self.status.contains(StatusRegister::C) as i16;
+
+        let mut low = (a & 0x0F) + (b & 0x0F) + carry;
+        if low >= 0x0A {
+            low = ((low + 0x06) & 0x0F) + 0x10;
+        }
+
+        let intermediate = (a & 0xF0) + (b & 0xF0) + low;
+        let signed = (a & 0xF0) as u8 as i8 as i16 + (b & 0xF0) as u8 as i8 as i16 + low;
+        let result = if intermediate >= 0xA0 { intermediate + 0x60 } else { intermediate };
+
+        self.status.set(StatusRegister::Z, (a + b + carry) & 0xFF == 0);
+        self.status.set(StatusRegister::N, intermediate & 0x80 != 0);
+        self.status.set(StatusRegister::V, !(-128..=127).contains(&signed));
+        self.status.set(StatusRegister::C, result >= 0x100);
+        self.registers.a = result as u8;
+    }
+
+    // BCD subtraction as performed by the NMOS 6502
+    // All flags are set exactly as in binary mode, only the accumulator holds the decimal result
+    // Ref: http://www.6502.org/tutorials/decimal_mode.html#A
+    fn subtract_decimal(&mut self, value: u8) {
+        let a = self.registers.a as i16;
+        let b = value as i16;
+        let carry = self.status.contains(StatusRegister::C) as i16;
+
+        let mut low = (a & 0x0F) - (b & 0x0F) + carry - 1;
+        if low < 0 {
+            low = ((low - 0x06) & 0x0F) - 0x10;
+        }
+
+        let mut result = (a & 0xF0) - (b & 0xF0) + low;
+        if result < 0 {
+            result -= 0x60;
+        }
+
+        self.add_with_carry(!value);
+        self.registers.a = result as u8;
+    }
+
+    // Compares a register to a value as if they were subtracted from each other
+    fn compare(&mut self, register: u8, value: u8) {
+        self.status.set(StatusRegister::C, register >= value);
+        self.update_zero_and_negative(register.wrapping_sub(value));
+    }
+
+    // Taken branches cost an extra cycle, and another one if the destination is on a different page
+    // Ref: http://www.6502.org/tutorials/6502opcodes.html#BRA
+    fn branch(&mut self, condition: bool, resolved: &Resolved) -> u8 {
+        if !condition {
+            return 0;
+        }
+
+        // The CPU reads the next opcode while adding the offset, and once more while fixing the high byte of the PC
+        let pc = self.registers.pc;
+        let target = resolved.operand.address();
+        self.read(pc);
+        if resolved.page_crossed {
+            self.read((pc & 0xFF00) | (target & 0x00FF));
+        }
+
+        self.registers.pc = target;
+        if resolved.page_crossed { 2 } else { 1 }
+    }
+
+    // Restores the status register from a value pulled off the stack, the B and unused bits only exist on the stack
+    fn pull_status(&mut self, value: u8) {
+        self.status = (StatusRegister::from_bits_truncate(value) - StatusRegister::B) | StatusRegister::U;
+    }
+
+    fn load(&mut self, operand: &Operand) -> u8 {
+        match *operand {
+            Operand::Accumulator => self.registers.a,
+            Operand::Memory(address) => self.read(address),
+            Operand::Implied => panic!("Attempted to load an operand for an implied instruction!")
+        }
+    }
+
+    // Read-modify-write instructions write the unmodified value back while they're modifying it
+    fn load_for_modify(&mut self, operand: &Operand) -> u8 {
+        let value = self.load(operand);
+        if let Operand::Memory(address) = *operand {
+            self.write(address, value);
+        }
+        value
+    }
+
+    fn store(&mut self, operand: &Operand, value: u8) {
+        match *operand {
+            Operand::Accumulator => self.registers.a = value,
+            Operand::Memory(address) => self.write(address, value),
+            Operand::Implied => panic!("Attempted to store an operand for an implied instruction!")
+        }
+    }
+
+    // The stack lives in page $01 and grows downwards
+    // Ref: https://wiki.nesdev.com/w/index.php/Stack
+    fn push(&mut self, value: u8) {
+        let address = 0x0100 | self.registers.sp as Address;
+        self.write(address, value);
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+    }
+
+    // Reads the top of the stack without pulling it, instructions that pull from the stack spend a cycle doing this
+    fn peek_stack(&mut self) {
+        let address = 0x0100 | self.registers.sp as Address;
+        self.read(address);
+    }
+
+    fn pop(&mut self) -> u8 {
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        let address = 0x0100 | self.registers.sp as Address;
+        self.read(address)
+    }
+
+    fn push_u16(&mut self, value: u16) {
+        self.push((value >> 8) as u8);
+        self.push(value as u8);
+    }
+
+    fn pop_u16(&mut self) -> u16 {
+        let lo = self.pop() as u16;
+        let hi = self.pop() as u16;
+        (hi << 8) | lo
+    }
+
+    // Reads a single byte from the CPU address space through the bus
+    fn read(&mut self, address: Address) -> u8 {
+        self.bus.read(address)
+    }
+
+    // Reads a little-endian 16-bit value from the CPU address space
+    fn read_u16(&mut self, address: Address) -> u16 {
+        let lo = self.read(address) as u16;
+        let hi = self.read(address.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    // Reads a little-endian 16-bit value without carrying into the high byte of the address
+    // The 6502 never increments the page when fetching the second byte of a pointer, so a pointer at $xxFF
+    // takes its high byte from $xx00. This affects zero page pointers and the JMP ($xxFF) bug.
+    // Ref: http://www.6502.org/tutorials/6502opcodes.html#JMP
+    fn read_u16_wrapped(&mut self, address: Address) -> u16 {
+        let lo = self.read(address) as u16;
+        let hi = self.read((address & 0xFF00) | (address.wrapping_add(1) & 0x00FF)) as u16;
+        (hi << 8) | lo
+    }
+
+    // Writes a single byte to the CPU address space through the bus
+    fn write(&mut self, address: Address, value: u8) {
+        self.bus.write(address, value);
     }
 }
\ No newline at end of file
@@ -0,0 +1,212 @@
+// Harness for the single-step CPU tests in the ProcessorTests JSON format
+// Every test describes the CPU and RAM state before and after executing one instruction, together with every
+// cycle of bus activity in between. The test vectors aren't distributed with Mudkip, place the nes6502 set in
+// tests/processor_tests/nes6502/v1 (one file per opcode, eg: a9.json) and run the ignored tests.
+// Ref: https://github.com/TomHarte/ProcessorTests
+
+use std::cell::RefCell;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::rc::Rc;
+
+use serde_json;
+use serde_json::Value;
+
+use cpu::{Address, Cpu, StatusRegister};
+use cpu::instructions;
+use cpu::instructions::Mnemonic;
+use cpu::memory::Memory;
+
+const TEST_DIRECTORY: &str = "tests/processor_tests/nes6502/v1";
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+enum BusOperation {
+    Read,
+    Write
+}
+
+// A single cycle of bus activity
+#[derive(PartialEq, Debug, Copy, Clone)]
+struct BusCycle {
+    cycle: u64,
+    address: Address,
+    value: u8,
+    operation: BusOperation
+}
+
+// A flat 64KB address space that logs every read and write along with the cycle it happened on
+// The log is shared, so it can still be inspected once the bus has been handed over to the CPU
+struct RecordingBus {
+    memory: Vec<u8>,
+    cycle: u64,
+    log: Rc<RefCell<Vec<BusCycle>>>
+}
+
+impl RecordingBus {
+    fn new(log: Rc<RefCell<Vec<BusCycle>>>) -> RecordingBus {
+        RecordingBus { memory: vec![0; 0x10000], cycle: 0, log }
+    }
+
+    fn record(&mut self, address: Address, value: u8, operation: BusOperation) {
+        self.log.borrow_mut().push(BusCycle { cycle: self.cycle, address, value, operation });
+        self.cycle += 1;
+    }
+}
+
+impl Memory for RecordingBus {
+    fn read(&mut self, address: Address) -> u8 {
+        let value = self.memory[address as usize];
+        self.record(address, value, BusOperation::Read);
+        value
+    }
+
+    fn write(&mut self, address: Address, value: u8) {
+        self.memory[address as usize] = value;
+        self.record(address, value, BusOperation::Write);
+    }
+
+    fn peek(&self, address: Address) -> u8 {
+        self.memory[address as usize]
+    }
+}
+
+// Runs a single test, returning a description of the first difference with the expected outcome
+fn run_test(test: &Value) -> Result<(), String> {
+    let initial = &test["initial"];
+    let expected = &test["final"];
+
+    let mut bus = RecordingBus::new(Rc::new(RefCell::new(vec![])));
+    for entry in initial["ram"].as_array().unwrap() {
+        bus.memory[entry[0].as_u64().unwrap() as usize] = entry[1].as_u64().unwrap() as u8;
+    }
+
+    let log = bus.log.clone();
+    let mut cpu = Cpu::new(Box::new(bus));
+    cpu.registers.pc = initial["pc"].as_u64().unwrap() as u16;
+    cpu.registers.sp = initial["s"].as_u64().unwrap() as u8;
+    cpu.registers.a = initial["a"].as_u64().unwrap() as u8;
+    cpu.registers.x = initial["x"].as_u64().unwrap() as u8;
+    cpu.registers.y = initial["y"].as_u64().unwrap() as u8;
+    cpu.pull_status(initial["p"].as_u64().unwrap() as u8);
+
+    let cycles = cpu.step();
+
+    // The B and unused bits only exist on the stack, so they're left out of the comparison
+    let ignored = StatusRegister::B.bits | StatusRegister::U.bits;
+    let registers = [
+        ("pc", cpu.registers.pc as u64, expected["pc"].as_u64().unwrap()),
+        ("s", cpu.registers.sp as u64, expected["s"].as_u64().unwrap()),
+        ("a", cpu.registers.a as u64, expected["a"].as_u64().unwrap()),
+        ("x", cpu.registers.x as u64, expected["x"].as_u64().unwrap()),
+        ("y", cpu.registers.y as u64, expected["y"].as_u64().unwrap()),
+        ("p", (cpu.status.bits | ignored) as u64, expected["p"].as_u64().unwrap() | ignored as u64)
+    ];
+
+    for &(name, actual, expected) in registers.iter() {
+        if actual != expected {
+            return Err(format!("{} is ${:02X}, expected ${:02X}", name, actual, expected));
+        }
+    }
+
+    for entry in expected["ram"].as_array().unwrap() {
+        let address = entry[0].as_u64().unwrap() as Address;
+        let value = entry[1].as_u64().unwrap() as u8;
+        if cpu.bus.peek(address) != value {
+            return Err(format!("${:04X} is ${:02X}, expected ${:02X}", address, cpu.bus.peek(address), value));
+        }
+    }
+
+    let expected_cycles: Vec<BusCycle> = test["cycles"].as_array().unwrap().iter().enumerate()
+        .map(|(cycle, entry)| BusCycle {
+            cycle: cycle as u64,
+            address: entry[0].as_u64().unwrap() as Address,
+            value: entry[1].as_u64().unwrap() as u8,
+            operation: if entry[2] == "write" { BusOperation::Write } else { BusOperation::Read }
+        })
+        .collect();
+
+    if *log.borrow() != expected_cycles {
+        return Err(format!("bus activity was {:?}, expected {:?}", log.borrow(), expected_cycles));
+    }
+
+    if cycles as usize != expected_cycles.len() {
+        return Err(format!("took {} cycles, expected {}", cycles, expected_cycles.len()));
+    }
+
+    Ok(())
+}
+
+// Runs all tests in a list, returning a line for every failure
+fn run_tests(tests: &Value) -> Vec<String> {
+    tests.as_array().unwrap().iter()
+        .filter_map(|test| run_test(test).err().map(|error| format!("{}: {}", test["name"], error)))
+        .collect()
+}
+
+#[test]
+fn recorded_bus_activity() {
+    // LDA ($28),Y crossing a page, JSR and INC $1000,X, worked out by hand from 6502_cpu.txt
+    // Ref: http://nesdev.com/6502_cpu.txt
+    let tests: Value = serde_json::from_str(r#"[
+        {
+            "name": "b1 28 page cross",
+            "initial": { "pc": 512, "s": 253, "a": 0, "x": 0, "y": 32, "p": 36,
+                         "ram": [[512, 177], [513, 40], [40, 240], [41, 18], [4880, 119]] },
+            "final":   { "pc": 514, "s": 253, "a": 119, "x": 0, "y": 32, "p": 36,
+                         "ram": [[4880, 119]] },
+            "cycles":  [[512, 177, "read"], [513, 40, "read"], [40, 240, "read"], [41, 18, "read"],
+                        [4624, 0, "read"], [4880, 119, "read"]]
+        },
+        {
+            "name": "20 34 12",
+            "initial": { "pc": 768, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36,
+                         "ram": [[768, 32], [769, 52], [770, 18]] },
+            "final":   { "pc": 4660, "s": 251, "a": 0, "x": 0, "y": 0, "p": 36,
+                         "ram": [[509, 3], [508, 2]] },
+            "cycles":  [[768, 32, "read"], [769, 52, "read"], [509, 0, "read"], [509, 3, "write"],
+                        [508, 2, "write"], [770, 18, "read"]]
+        },
+        {
+            "name": "fe 00 10",
+            "initial": { "pc": 1024, "s": 253, "a": 0, "x": 1, "y": 0, "p": 36,
+                         "ram": [[1024, 254], [1025, 0], [1026, 16], [4097, 127]] },
+            "final":   { "pc": 1027, "s": 253, "a": 0, "x": 1, "y": 0, "p": 164,
+                         "ram": [[4097, 128]] },
+            "cycles":  [[1024, 254, "read"], [1025, 0, "read"], [1026, 16, "read"], [4097, 127, "read"],
+                        [4097, 127, "read"], [4097, 127, "write"], [4097, 128, "write"]]
+        }
+    ]"#).unwrap();
+
+    let failures = run_tests(&tests);
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+#[ignore = "needs tests/processor_tests/nes6502/v1"]
+fn processor_tests() {
+    let directory = Path::new(TEST_DIRECTORY);
+    assert!(directory.exists(), "{} is missing", TEST_DIRECTORY);
+
+    let mut failures = vec![];
+
+    for entry in fs::read_dir(directory).unwrap() {
+        let path = entry.unwrap().path();
+        let opcode = match path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| u8::from_str_radix(stem, 16).ok()) {
+            Some(opcode) => opcode,
+            None => continue
+        };
+
+        // Jams and the unstable unofficial opcodes aren't emulated
+        match instructions::decode(opcode).mnemonic {
+            Mnemonic::KIL | Mnemonic::UNKNOWN => continue,
+            _ => ()
+        }
+
+        let tests: Value = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+        failures.extend(run_tests(&tests).into_iter().map(|failure| format!("{:02x}.json {}", opcode, failure)));
+    }
+
+    assert!(failures.is_empty(), "{} failures, the first ones being:\n{}", failures.len(),
+            failures.iter().take(20).cloned().collect::<Vec<_>>().join("\n"));
+}
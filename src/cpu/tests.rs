@@ -0,0 +1,582 @@
+use cpu::{Address, Cpu, StatusRegister, Variant};
+use cpu::memory::{CpuBus, Memory};
+use nes::mapper;
+use nes::rom::RomBuilder;
+
+const PROGRAM_ADDRESS: Address = 0x8000;
+const NMI_HANDLER: Address = 0x9000;
+const IRQ_HANDLER: Address = 0xA000;
+
+// A flat 64KB address space without any memory-mapped hardware
+struct FlatBus {
+    memory: Vec<u8>
+}
+
+impl Memory for FlatBus {
+    fn read(&mut self, address: Address) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn write(&mut self, address: Address, value: u8) {
+        self.memory[address as usize] = value;
+    }
+
+    fn peek(&self, address: Address) -> u8 {
+        self.memory[address as usize]
+    }
+}
+
+// A powered on CPU about to execute the given program, with the NMI and IRQ vectors pointing at separate handlers
+// Memory is filled with NOPs, so the handlers can be stepped through as well
+fn cpu(program: &[u8]) -> Cpu {
+    let mut memory = vec![0xEA; 0x10000];
+    memory[PROGRAM_ADDRESS as usize..PROGRAM_ADDRESS as usize + program.len()].copy_from_slice(program);
+    for &(vector, address) in &[(0xFFFA, NMI_HANDLER), (0xFFFC, PROGRAM_ADDRESS), (0xFFFE, IRQ_HANDLER)] {
+        memory[vector] = address as u8;
+        memory[vector + 1] = (address >> 8) as u8;
+    }
+
+    let mut cpu = Cpu::new(Box::new(FlatBus { memory }));
+    cpu.power_on();
+    cpu
+}
+
+// The return address and status the last interrupt sequence pushed on the stack
+fn pushed(cpu: &Cpu) -> (Address, u8) {
+    let stack = |offset: u8| cpu.bus.peek(0x0100 | cpu.registers.sp.wrapping_add(offset) as Address);
+    let ret = ((stack(3) as Address) << 8) | stack(2) as Address;
+    (ret, stack(1))
+}
+
+#[test]
+fn power_on_loads_the_reset_vector() {
+    let cpu = cpu(&[]);
+    assert_eq!(cpu.registers.pc, PROGRAM_ADDRESS);
+    assert_eq!(cpu.registers.sp, 0xFD);
+    assert_eq!(cpu.status.bits, 0x24);
+    assert_eq!((cpu.registers.a, cpu.registers.x, cpu.registers.y), (0, 0, 0));
+    assert_eq!(cpu.cycles(), 7);
+}
+
+#[test]
+fn reset_keeps_registers_and_ram() {
+    let mut cpu = cpu(&[0xA9, 0x42, 0x85, 0x10, 0x58]);    // LDA #$42, STA $10, CLI
+    cpu.step();
+    cpu.step();
+    cpu.step();
+    assert!(!cpu.status.contains(StatusRegister::I));
+
+    // The stack pointer is decremented by 3 as the pushes of the interrupt sequence are turned into reads
+    cpu.reset();
+    assert_eq!(cpu.registers.pc, PROGRAM_ADDRESS);
+    assert_eq!(cpu.registers.sp, 0xFA);
+    assert!(cpu.status.contains(StatusRegister::I));
+    assert_eq!(cpu.registers.a, 0x42);
+    assert_eq!(cpu.bus.peek(0x0010), 0x42);
+}
+
+#[test]
+fn cpu_bus_mirrors_ram_and_ppu_registers() {
+    let mut bus = CpuBus::new(mapper::new(&RomBuilder::new(0).build()).unwrap());
+    bus.write(0x0001, 0x12);
+    assert_eq!((bus.read(0x0801), bus.read(0x1001), bus.read(0x1801)), (0x12, 0x12, 0x12));
+    bus.write(0x1FFF, 0x34);
+    assert_eq!(bus.read(0x07FF), 0x34);
+
+    bus.write(0x2000, 0x56);
+    assert_eq!((bus.read(0x2008), bus.read(0x3FF8)), (0x56, 0x56));
+    bus.write(0x3FFF, 0x78);
+    assert_eq!(bus.peek(0x2007), 0x78);
+
+    // Everything from $4020 is the cartridge, the first PRG-ROM page starts with its number
+    assert_eq!(bus.read(0x8000), 0);
+    bus.write(0x8000, 0xFF);
+    assert_eq!(bus.read(0xC000), 0);
+}
+
+// Runs a single instruction with the zero page byte at $10 set to the given value
+fn execute(program: &[u8], a: u8, x: u8, carry: bool, memory: u8) -> Cpu {
+    let mut cpu = cpu(program);
+    cpu.registers.a = a;
+    cpu.registers.x = x;
+    cpu.status.set(StatusRegister::C, carry);
+    cpu.write(0x0010, memory);
+    cpu.step();
+    cpu
+}
+
+// The flags that aren't always the same, for comparing against the expected results
+fn flags(cpu: &Cpu) -> StatusRegister {
+    cpu.status & (StatusRegister::N | StatusRegister::V | StatusRegister::Z | StatusRegister::C)
+}
+
+#[test]
+fn adc_and_sbc_set_carry_and_overflow() {
+    let cpu = execute(&[0x69, 0x50], 0x50, 0x00, false, 0x00);      // ADC #$50
+    assert_eq!((cpu.registers.a, flags(&cpu)), (0xA0, StatusRegister::N | StatusRegister::V));
+
+    let cpu = execute(&[0x69, 0x01], 0xFF, 0x00, false, 0x00);      // ADC #$01
+    assert_eq!((cpu.registers.a, flags(&cpu)), (0x00, StatusRegister::Z | StatusRegister::C));
+
+    let cpu = execute(&[0x65, 0x10], 0x01, 0x00, true, 0x01);       // ADC $10 with carry
+    assert_eq!((cpu.registers.a, flags(&cpu)), (0x03, StatusRegister::empty()));
+
+    let cpu = execute(&[0xE9, 0xF0], 0x50, 0x00, true, 0x00);       // SBC #$F0
+    assert_eq!((cpu.registers.a, flags(&cpu)), (0x60, StatusRegister::empty()));
+
+    let cpu = execute(&[0xE9, 0xB0], 0x50, 0x00, true, 0x00);       // SBC #$B0
+    assert_eq!((cpu.registers.a, flags(&cpu)), (0xA0, StatusRegister::N | StatusRegister::V));
+
+    let cpu = execute(&[0xE5, 0x10], 0x05, 0x00, false, 0x04);      // SBC $10 with borrow
+    assert_eq!((cpu.registers.a, flags(&cpu)), (0x00, StatusRegister::Z | StatusRegister::C));
+}
+
+#[test]
+fn compares_and_bit() {
+    let cpu = execute(&[0xC9, 0x10], 0x10, 0x00, false, 0x00);      // CMP #$10
+    assert_eq!(flags(&cpu), StatusRegister::Z | StatusRegister::C);
+
+    let cpu = execute(&[0xE0, 0x11], 0x00, 0x10, false, 0x00);      // CPX #$11
+    assert_eq!(flags(&cpu), StatusRegister::N);
+
+    let cpu = execute(&[0x24, 0x10], 0x0F, 0x00, false, 0xC0);      // BIT $10
+    assert_eq!(flags(&cpu), StatusRegister::N | StatusRegister::V | StatusRegister::Z);
+    assert_eq!(cpu.registers.a, 0x0F);
+}
+
+#[test]
+fn shifts_and_rotates() {
+    let cpu = execute(&[0x0A], 0x81, 0x00, false, 0x00);            // ASL A
+    assert_eq!((cpu.registers.a, flags(&cpu)), (0x02, StatusRegister::C));
+
+    let cpu = execute(&[0x46, 0x10], 0x00, 0x00, false, 0x01);      // LSR $10
+    assert_eq!((cpu.bus.peek(0x0010), flags(&cpu)), (0x00, StatusRegister::Z | StatusRegister::C));
+
+    let cpu = execute(&[0x2A], 0x80, 0x00, true, 0x00);             // ROL A
+    assert_eq!((cpu.registers.a, flags(&cpu)), (0x01, StatusRegister::C));
+
+    let cpu = execute(&[0x66, 0x10], 0x00, 0x00, true, 0x02);       // ROR $10
+    assert_eq!((cpu.bus.peek(0x0010), flags(&cpu)), (0x81, StatusRegister::N));
+}
+
+#[test]
+fn increments_and_transfers_wrap() {
+    let cpu = execute(&[0xE8], 0x00, 0xFF, false, 0x00);            // INX
+    assert_eq!((cpu.registers.x, flags(&cpu)), (0x00, StatusRegister::Z));
+
+    let cpu = execute(&[0xC6, 0x10], 0x00, 0x00, false, 0x00);      // DEC $10
+    assert_eq!((cpu.bus.peek(0x0010), flags(&cpu)), (0xFF, StatusRegister::N));
+
+    let cpu = execute(&[0xAA], 0x80, 0x00, false, 0x00);            // TAX
+    assert_eq!((cpu.registers.x, flags(&cpu)), (0x80, StatusRegister::N));
+}
+
+#[test]
+fn jsr_and_rts() {
+    let mut cpu = cpu(&[0x20, 0x10, 0x80]);                         // JSR $8010
+    cpu.write(0x8010, 0x60);                                        // RTS
+    assert_eq!(cpu.step(), 6);
+    assert_eq!(cpu.registers.pc, 0x8010);
+    assert_eq!(cpu.registers.sp, 0xFB);
+    assert_eq!((cpu.bus.peek(0x01FD), cpu.bus.peek(0x01FC)), (0x80, 0x02));     // The address of the last byte of JSR
+
+    assert_eq!(cpu.step(), 6);
+    assert_eq!(cpu.registers.pc, 0x8003);
+    assert_eq!(cpu.registers.sp, 0xFD);
+}
+
+#[test]
+fn php_pushes_b_and_plp_ignores_it() {
+    let mut cpu = cpu(&[0x08, 0x28]);                               // PHP, PLP
+    cpu.step();
+    assert_eq!(cpu.bus.peek(0x01FD), 0x34);
+    cpu.step();
+    assert_eq!(cpu.status.bits, 0x24);
+}
+
+#[test]
+fn jmp_indirect_wraps_within_the_page() {
+    let mut cpu = cpu(&[0x6C, 0xFF, 0x02]);                         // JMP ($02FF)
+    cpu.write(0x02FF, 0x34);
+    cpu.write(0x0200, 0x12);
+    cpu.write(0x0300, 0x56);
+    cpu.step();
+    assert_eq!(cpu.registers.pc, 0x1234);
+}
+
+#[test]
+fn zero_page_indexing_wraps_within_the_zero_page() {
+    let mut cpu = cpu(&[0xB5, 0xFF]);                               // LDA $FF,X
+    cpu.registers.x = 0x02;
+    cpu.write(0x0001, 0x42);
+    cpu.write(0x0101, 0x24);
+    cpu.step();
+    assert_eq!(cpu.registers.a, 0x42);
+
+    let mut cpu = self::cpu(&[0xB6, 0x80]);                         // LDX $80,Y
+    cpu.registers.y = 0x90;
+    cpu.write(0x0010, 0x42);
+    cpu.step();
+    assert_eq!(cpu.registers.x, 0x42);
+}
+
+#[test]
+fn indirect_pointers_wrap_within_the_zero_page() {
+    // ($FF,X) with X = 0 takes its pointer from $FF and $00
+    let mut cpu = cpu(&[0xA1, 0xFF]);                               // LDA ($FF,X)
+    cpu.write(0x00FF, 0x34);
+    cpu.write(0x0000, 0x02);
+    cpu.write(0x0100, 0x03);
+    cpu.write(0x0234, 0x42);
+    cpu.step();
+    assert_eq!(cpu.registers.a, 0x42);
+
+    let mut cpu = self::cpu(&[0xB1, 0xFF]);                         // LDA ($FF),Y
+    cpu.registers.y = 0x01;
+    cpu.write(0x00FF, 0x34);
+    cpu.write(0x0000, 0x02);
+    cpu.write(0x0235, 0x24);
+    cpu.step();
+    assert_eq!(cpu.registers.a, 0x24);
+}
+
+// Cycles taken by a single instruction with the given X and Y
+fn cycles(program: &[u8], x: u8, y: u8) -> u8 {
+    let mut cpu = cpu(program);
+    cpu.registers.x = x;
+    cpu.registers.y = y;
+    cpu.step()
+}
+
+#[test]
+fn page_crosses_cost_reads_a_cycle() {
+    assert_eq!(cycles(&[0xBD, 0xF0, 0x02], 0x0F, 0), 4);           // LDA $02F0,X
+    assert_eq!(cycles(&[0xBD, 0xF0, 0x02], 0x10, 0), 5);
+    assert_eq!(cycles(&[0xF9, 0xF0, 0x02], 0, 0x10), 5);            // SBC $02F0,Y
+    assert_eq!(cycles(&[0xB1, 0x10], 0, 0x00), 5);                  // LDA ($10),Y
+    let mut cpu = cpu(&[0x11, 0x10]);                               // ORA ($10),Y
+    cpu.write(0x0010, 0xF0);
+    cpu.write(0x0011, 0x02);
+    cpu.registers.y = 0x10;
+    assert_eq!(cpu.step(), 6);
+
+    // Stores and read-modify-write instructions always take the worst case
+    assert_eq!(cycles(&[0x9D, 0x00, 0x02], 0x00, 0), 5);            // STA $0200,X
+    assert_eq!(cycles(&[0x91, 0x10], 0, 0x00), 6);                  // STA ($10),Y
+    assert_eq!(cycles(&[0x3E, 0x00, 0x02], 0x00, 0), 7);            // ROL $0200,X
+}
+
+#[test]
+fn base_cycle_counts() {
+    assert_eq!(cycles(&[0xED, 0x00, 0x02], 0, 0), 4);               // SBC $0200
+    assert_eq!(cycles(&[0xFD, 0x00, 0x02], 0, 0), 4);               // SBC $0200,X
+    assert_eq!(cycles(&[0x2A], 0, 0), 2);                           // ROL A
+    assert_eq!(cycles(&[0x26, 0x10], 0, 0), 5);                     // ROL $10
+    assert_eq!(cycles(&[0x76, 0x10], 0, 0), 6);                     // ROR $10,X
+    assert_eq!(cycles(&[0x6E, 0x00, 0x02], 0, 0), 6);               // ROR $0200
+    assert_eq!(cycles(&[0x7E, 0x00, 0x02], 0, 0), 7);               // ROR $0200,X
+    assert_eq!(cycles(&[0x6C, 0x00, 0x02], 0, 0), 5);               // JMP ($0200)
+    assert_eq!(cycles(&[0x00], 0, 0), 7);                           // BRK
+}
+
+#[test]
+fn branches_cost_a_cycle_when_taken_and_another_to_another_page() {
+    assert_eq!(cycles(&[0xF0, 0x10], 0, 0), 2);                     // BEQ, not taken as Z is clear after the reset
+    let mut cpu = cpu(&[0xF0, 0x10]);                               // BEQ +$10
+    cpu.status.insert(StatusRegister::Z);
+    assert_eq!(cpu.step(), 3);
+    assert_eq!(cpu.registers.pc, 0x8012);
+
+    let mut cpu = self::cpu(&[0xF0, 0x80]);                         // BEQ -$80
+    cpu.status.insert(StatusRegister::Z);
+    assert_eq!(cpu.step(), 4);
+    assert_eq!(cpu.registers.pc, 0x7F82);
+}
+
+#[test]
+fn nmi_is_serviced_after_the_current_instruction() {
+    let mut cpu = cpu(&[0xEA, 0xEA]);                       // NOP, NOP
+    cpu.set_nmi(true);
+    assert_eq!(cpu.step(), 2);
+    assert_eq!(cpu.registers.pc, 0x8001);
+
+    assert_eq!(cpu.step(), 7);
+    assert_eq!(cpu.registers.pc, NMI_HANDLER);
+    assert!(cpu.status.contains(StatusRegister::I));
+    assert_eq!(pushed(&cpu), (0x8001, 0x24));               // B clear, unused bit set
+
+    // NMIs are edge triggered, holding the line doesn't trigger another one
+    cpu.step();
+    assert_eq!(cpu.registers.pc, NMI_HANDLER + 1);
+}
+
+#[test]
+fn irq_is_masked_by_the_i_flag() {
+    let mut masked = cpu(&[0xEA, 0xEA]);                    // NOP, NOP
+    masked.set_irq(true);
+    masked.step();
+    masked.step();
+    assert_eq!(masked.registers.pc, 0x8002);
+
+    let mut unmasked = cpu(&[0xEA, 0xEA]);
+    unmasked.status.remove(StatusRegister::I);
+    unmasked.set_irq(true);
+    unmasked.step();
+    unmasked.step();
+    assert_eq!(unmasked.registers.pc, IRQ_HANDLER);
+    assert_eq!(pushed(&unmasked), (0x8001, 0x20));
+}
+
+#[test]
+fn brk_pushes_the_b_flag() {
+    let mut cpu = cpu(&[0x00, 0xFF]);                       // BRK and its padding byte
+    assert_eq!(cpu.step(), 7);
+    assert_eq!(cpu.registers.pc, IRQ_HANDLER);
+    assert!(cpu.status.contains(StatusRegister::I));
+    assert_eq!(pushed(&cpu), (0x8002, 0x34));
+}
+
+#[test]
+fn nmi_hijacks_brk() {
+    let mut cpu = cpu(&[0x00, 0xFF]);
+    cpu.set_nmi(true);
+    cpu.step();
+    assert_eq!(cpu.registers.pc, NMI_HANDLER);
+    assert_eq!(pushed(&cpu), (0x8002, 0x34));               // Still marked as a BRK
+
+    // The NMI was used up by the BRK
+    cpu.step();
+    assert_eq!(cpu.registers.pc, NMI_HANDLER + 1);
+}
+
+#[test]
+fn nmi_hijacks_irq() {
+    let mut cpu = cpu(&[0xEA, 0xEA]);
+    cpu.status.remove(StatusRegister::I);
+    cpu.set_irq(true);
+    cpu.step();
+    cpu.set_nmi(true);
+    cpu.step();
+    assert_eq!(cpu.registers.pc, NMI_HANDLER);
+    assert_eq!(pushed(&cpu), (0x8001, 0x20));
+}
+
+#[test]
+fn cli_takes_effect_after_the_next_instruction() {
+    let mut cpu = cpu(&[0x58, 0xEA, 0xEA]);                 // CLI, NOP, NOP
+    cpu.set_irq(true);
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.registers.pc, 0x8002);
+    cpu.step();
+    assert_eq!(cpu.registers.pc, IRQ_HANDLER);
+    assert_eq!(pushed(&cpu), (0x8002, 0x20));
+}
+
+#[test]
+fn sei_still_lets_an_irq_through() {
+    let mut cpu = cpu(&[0x78, 0xEA]);                       // SEI, NOP
+    cpu.status.remove(StatusRegister::I);
+    cpu.set_irq(true);
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.registers.pc, IRQ_HANDLER);
+    assert_eq!(pushed(&cpu), (0x8001, 0x24));               // The handler sees the I flag SEI set
+}
+
+#[test]
+fn plp_takes_effect_after_the_next_instruction() {
+    let mut cpu = cpu(&[0x28, 0xEA, 0xEA]);                 // PLP, NOP, NOP
+    cpu.push(0x20);
+    cpu.set_irq(true);
+    cpu.step();
+    assert!(!cpu.status.contains(StatusRegister::I));
+    cpu.step();
+    assert_eq!(cpu.registers.pc, 0x8002);
+    cpu.step();
+    assert_eq!(cpu.registers.pc, IRQ_HANDLER);
+}
+
+#[test]
+fn rti_takes_effect_immediately() {
+    let mut cpu = cpu(&[0x40]);                             // RTI
+    cpu.push_u16(0x8010);
+    cpu.push(0x20);
+    cpu.set_irq(true);
+    cpu.step();
+    assert_eq!(cpu.registers.pc, 0x8010);
+    cpu.step();
+    assert_eq!(cpu.registers.pc, IRQ_HANDLER);
+    assert_eq!(pushed(&cpu), (0x8010, 0x20));
+}
+
+#[test]
+fn lax_loads_a_and_x() {
+    let cpu = execute(&[0xA7, 0x10], 0x00, 0x00, false, 0x80);      // LAX $10
+    assert_eq!((cpu.registers.a, cpu.registers.x), (0x80, 0x80));
+    assert!(cpu.status.contains(StatusRegister::N));
+}
+
+#[test]
+fn sax_stores_a_and_x() {
+    let cpu = execute(&[0x87, 0x10], 0xF0, 0x3C, false, 0x00);      // SAX $10
+    assert_eq!(cpu.bus.peek(0x0010), 0x30);
+}
+
+#[test]
+fn dcp_decrements_and_compares() {
+    let cpu = execute(&[0xC7, 0x10], 0x40, 0x00, false, 0x41);      // DCP $10
+    assert_eq!(cpu.bus.peek(0x0010), 0x40);
+    assert!(cpu.status.contains(StatusRegister::Z | StatusRegister::C));
+}
+
+#[test]
+fn isc_increments_and_subtracts() {
+    let cpu = execute(&[0xE7, 0x10], 0x20, 0x00, true, 0x0F);       // ISC $10
+    assert_eq!(cpu.bus.peek(0x0010), 0x10);
+    assert_eq!(cpu.registers.a, 0x10);
+    assert!(cpu.status.contains(StatusRegister::C));
+}
+
+#[test]
+fn slo_shifts_and_ors() {
+    let cpu = execute(&[0x07, 0x10], 0x01, 0x00, false, 0x81);      // SLO $10
+    assert_eq!(cpu.bus.peek(0x0010), 0x02);
+    assert_eq!(cpu.registers.a, 0x03);
+    assert!(cpu.status.contains(StatusRegister::C));
+}
+
+#[test]
+fn rla_rotates_and_ands() {
+    let cpu = execute(&[0x27, 0x10], 0xFF, 0x00, false, 0x81);      // RLA $10
+    assert_eq!(cpu.bus.peek(0x0010), 0x02);
+    assert_eq!(cpu.registers.a, 0x02);
+    assert!(cpu.status.contains(StatusRegister::C));
+}
+
+#[test]
+fn sre_shifts_and_eors() {
+    let cpu = execute(&[0x47, 0x10], 0xFF, 0x00, false, 0x03);      // SRE $10
+    assert_eq!(cpu.bus.peek(0x0010), 0x01);
+    assert_eq!(cpu.registers.a, 0xFE);
+    assert!(cpu.status.contains(StatusRegister::C | StatusRegister::N));
+}
+
+#[test]
+fn rra_rotates_and_adds() {
+    let cpu = execute(&[0x67, 0x10], 0x10, 0x00, true, 0x02);       // RRA $10
+    assert_eq!(cpu.bus.peek(0x0010), 0x81);
+    assert_eq!(cpu.registers.a, 0x91);
+    assert!(!cpu.status.contains(StatusRegister::C));
+}
+
+#[test]
+fn read_modify_write_combinations_take_the_worst_case() {
+    assert_eq!(cpu(&[0xDF, 0x00, 0x02]).step(), 7);                 // DCP $0200,X
+    assert_eq!(cpu(&[0xD3, 0x10]).step(), 8);                       // DCP ($10),Y
+}
+
+#[test]
+fn anc_copies_n_into_c() {
+    let cpu = execute(&[0x0B, 0x80], 0xFF, 0x00, false, 0x00);      // ANC #$80
+    assert_eq!(cpu.registers.a, 0x80);
+    assert!(cpu.status.contains(StatusRegister::C | StatusRegister::N));
+}
+
+#[test]
+fn alr_ands_and_shifts() {
+    let cpu = execute(&[0x4B, 0x03], 0xFF, 0x00, false, 0x00);      // ALR #$03
+    assert_eq!(cpu.registers.a, 0x01);
+    assert!(cpu.status.contains(StatusRegister::C));
+}
+
+#[test]
+fn arr_sets_c_and_v_from_bits_6_and_5() {
+    let cpu = execute(&[0x6B, 0xFF], 0xC0, 0x00, true, 0x00);       // ARR #$FF
+    assert_eq!(cpu.registers.a, 0xE0);
+    assert!(cpu.status.contains(StatusRegister::C | StatusRegister::N));
+    assert!(!cpu.status.contains(StatusRegister::V));
+
+    let cpu = execute(&[0x6B, 0xFF], 0x40, 0x00, false, 0x00);
+    assert_eq!(cpu.registers.a, 0x20);
+    assert!(cpu.status.contains(StatusRegister::V));
+    assert!(!cpu.status.contains(StatusRegister::C));
+}
+
+#[test]
+fn axs_subtracts_from_a_and_x() {
+    let cpu = execute(&[0xCB, 0x02], 0x0F, 0x05, false, 0x00);      // AXS #$02
+    assert_eq!(cpu.registers.x, 0x03);
+    assert_eq!(cpu.registers.a, 0x0F);
+    assert!(cpu.status.contains(StatusRegister::C));
+}
+
+#[test]
+fn kil_jams_until_reset() {
+    let mut cpu = cpu(&[0x02]);                                     // KIL
+    cpu.status.remove(StatusRegister::I);
+    cpu.set_irq(true);
+    cpu.set_nmi(true);
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.registers.pc, PROGRAM_ADDRESS);
+
+    cpu.reset();
+    assert_eq!(cpu.registers.pc, PROGRAM_ADDRESS);
+    assert!(!cpu.jammed);
+}
+
+// Runs ADC or SBC with an immediate operand while the D flag is set
+fn decimal(variant: Variant, opcode: u8, a: u8, operand: u8, carry: bool) -> Cpu {
+    let mut cpu = cpu(&[opcode, operand]);
+    cpu.set_variant(variant);
+    cpu.registers.a = a;
+    cpu.status.set(StatusRegister::C, carry);
+    cpu.status.insert(StatusRegister::D);
+    cpu.step();
+    cpu
+}
+
+// Ref: http://www.6502.org/tutorials/decimal_mode.html
+#[test]
+fn nmos_decimal_adc() {
+    let cpu = decimal(Variant::NMOS6502, 0x69, 0x12, 0x34, false);
+    assert_eq!((cpu.registers.a, flags(&cpu)), (0x46, StatusRegister::empty()));
+
+    // N and V come from the sum before the high nibble was adjusted ($A5)
+    let cpu = decimal(Variant::NMOS6502, 0x69, 0x58, 0x46, true);
+    assert_eq!((cpu.registers.a, flags(&cpu)), (0x05, StatusRegister::N | StatusRegister::V | StatusRegister::C));
+
+    // Z comes from the binary sum ($9A), so it's clear even though the result is zero
+    let cpu = decimal(Variant::NMOS6502, 0x69, 0x99, 0x01, false);
+    assert_eq!((cpu.registers.a, flags(&cpu)), (0x00, StatusRegister::N | StatusRegister::C));
+
+    // Invalid BCD digits still get adjusted
+    let cpu = decimal(Variant::NMOS6502, 0x69, 0x0F, 0x0F, false);
+    assert_eq!((cpu.registers.a, flags(&cpu)), (0x14, StatusRegister::empty()));
+}
+
+#[test]
+fn nmos_decimal_sbc() {
+    let cpu = decimal(Variant::NMOS6502, 0xE9, 0x46, 0x12, true);
+    assert_eq!((cpu.registers.a, flags(&cpu)), (0x34, StatusRegister::C));
+
+    let cpu = decimal(Variant::NMOS6502, 0xE9, 0x32, 0x02, false);
+    assert_eq!((cpu.registers.a, flags(&cpu)), (0x29, StatusRegister::C));
+
+    // A borrow, the flags are the ones of the binary difference ($F1)
+    let cpu = decimal(Variant::NMOS6502, 0xE9, 0x12, 0x21, true);
+    assert_eq!((cpu.registers.a, flags(&cpu)), (0x91, StatusRegister::N));
+
+    let cpu = decimal(Variant::NMOS6502, 0xE9, 0x00, 0x0F, true);
+    assert_eq!((cpu.registers.a, flags(&cpu)), (0x9B, StatusRegister::N));
+}
+
+#[test]
+fn rp2a03_ignores_the_d_flag() {
+    let cpu = decimal(Variant::RP2A03, 0x69, 0x58, 0x46, false);
+    assert_eq!((cpu.registers.a, flags(&cpu)), (0x9E, StatusRegister::N | StatusRegister::V));
+
+    let cpu = decimal(Variant::RP2A03, 0xE9, 0x10, 0x01, true);
+    assert_eq!((cpu.registers.a, flags(&cpu)), (0x0F, StatusRegister::C));
+}
@@ -0,0 +1,207 @@
+use std::io::Write;
+
+use cpu::{Address, AddressingMode, Cpu};
+use cpu::disassembler::InstructionDeNovo;
+use cpu::instructions;
+use cpu::instructions::{Instruction, Mnemonic};
+
+impl Cpu {
+    // Enables or disables the trace mode, which writes a line for every executed instruction to the given output
+    pub fn set_trace(&mut self, output: Option<Box<dyn Write>>) {
+        self.trace = output;
+    }
+
+    // Writes the trace line for the instruction that's about to be executed, if the trace mode is enabled
+    pub(super) fn write_trace(&mut self) {
+        if self.trace.is_none() {
+            return;
+        }
+
+        let line = self.trace_line();
+        if let Some(ref mut output) = self.trace {
+            writeln!(output, "{}", line).expect("Failed to write CPU trace");
+        }
+    }
+
+    // Formats the instruction at the Program Counter and the CPU state in the Nintendulator format used by nestest.log
+    // Memory operands are annotated with their effective address and current value, unofficial opcodes are marked with a *
+    // The PPU doesn't exist yet, so its scanline and dot are derived from the cycle count (3 PPU dots per CPU cycle)
+    // Ref: http://www.qmtpro.com/~nes/misc/nestest.log
+    // Ref: https://wiki.nesdev.com/w/index.php/Emulator_tests
+    pub fn trace_line(&self) -> String {
+        let pc = self.registers.pc;
+        let instruction = instructions::decode(self.bus.peek(pc));
+        let official = instruction.is_official();
+        let Instruction { opcode, mnemonic, mode, length, cycles } = instruction;
+
+        let disassembly = InstructionDeNovo {
+            address: pc,
+            opcode,
+            mnemonic,
+            mode,
+            length,
+            cycles,
+            operands: (1..length).map(|i| self.bus.peek(pc.wrapping_add(i as u16))).collect()
+        };
+
+        let mnemonic = trace_mnemonic(&disassembly.mnemonic);
+        let text = match disassembly.mode {
+            AddressingMode::IMP | AddressingMode::UNKNOWN => mnemonic,
+            _ => format!("{} {}{}", mnemonic, disassembly.operand_str(), self.trace_operand(&disassembly))
+        };
+
+        let dots = self.cycles * 3;
+
+        format!("{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+                pc, disassembly.bytes_str(" "), if official { " " } else { "*" }, text,
+                self.registers.a, self.registers.x, self.registers.y, self.status.bits, self.registers.sp,
+                (dots / 341) % 262, dots % 341, self.cycles)
+    }
+
+    // Describes where a memory operand points to and the value currently stored there, eg: " @ 0300 = 89"
+    fn trace_operand(&self, disassembly: &InstructionDeNovo) -> String {
+        let operands = &disassembly.operands;
+        let x = self.registers.x;
+        let y = self.registers.y;
+
+        match disassembly.mode {
+            AddressingMode::ZPG => {
+                let address = operands[0] as Address;
+                format!(" = {:02X}", self.bus.peek(address))
+            }
+            AddressingMode::ZPX | AddressingMode::ZPY => {
+                let index = if disassembly.mode == AddressingMode::ZPX { x } else { y };
+                let address = operands[0].wrapping_add(index) as Address;
+                format!(" @ {:02X} = {:02X}", address, self.bus.peek(address))
+            }
+            AddressingMode::ABS => {
+                // Jumps don't access their operand, so there's no value to show
+                match disassembly.mnemonic {
+                    Mnemonic::JMP | Mnemonic::JSR => "".to_string(),
+                    _ => format!(" = {:02X}", self.bus.peek(to_address(operands)))
+                }
+            }
+            AddressingMode::ABX | AddressingMode::ABY => {
+                let index = if disassembly.mode == AddressingMode::ABX { x } else { y };
+                let address = to_address(operands).wrapping_add(index as Address);
+                format!(" @ {:04X} = {:02X}", address, self.bus.peek(address))
+            }
+            AddressingMode::IND => {
+                format!(" = {:04X}", self.peek_u16_wrapped(to_address(operands)))
+            }
+            AddressingMode::IDX => {
+                let pointer = operands[0].wrapping_add(x);
+                let address = self.peek_u16_wrapped(pointer as Address);
+                format!(" @ {:02X} = {:04X} = {:02X}", pointer, address, self.bus.peek(address))
+            }
+            AddressingMode::IDY => {
+                let base = self.peek_u16_wrapped(operands[0] as Address);
+                let address = base.wrapping_add(y as Address);
+                format!(" = {:04X} @ {:04X} = {:02X}", base, address, self.bus.peek(address))
+            }
+            _ => "".to_string()
+        }
+    }
+
+    // Side-effect free version of read_u16_wrapped
+    fn peek_u16_wrapped(&self, address: Address) -> u16 {
+        let lo = self.bus.peek(address) as u16;
+        let hi = self.bus.peek((address & 0xFF00) | (address.wrapping_add(1) & 0x00FF)) as u16;
+        (hi << 8) | lo
+    }
+}
+
+// The names Nintendulator uses for the unofficial opcodes, which aren't always the same as ours (ISB for ISC)
+// The official mnemonics are named the same everywhere
+fn trace_mnemonic(mnemonic: &Mnemonic) -> String {
+    let name = match *mnemonic {
+        Mnemonic::LAX => "LAX",
+        Mnemonic::SAX => "SAX",
+        Mnemonic::DCP => "DCP",
+        Mnemonic::ISC => "ISB",
+        Mnemonic::SLO => "SLO",
+        Mnemonic::RLA => "RLA",
+        Mnemonic::SRE => "SRE",
+        Mnemonic::RRA => "RRA",
+        Mnemonic::ANC => "ANC",
+        Mnemonic::ALR => "ALR",
+        Mnemonic::ARR => "ARR",
+        Mnemonic::AXS => "AXS",
+        Mnemonic::KIL => "KIL",
+        ref official => return format!("{:?}", official)
+    };
+    name.to_string()
+}
+
+fn to_address(operands: &[u8]) -> Address {
+    ((operands[1] as Address) << 8) | operands[0] as Address
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::fs::File;
+    use std::io::{BufRead, BufReader};
+    use std::path::Path;
+
+    use cpu::Cpu;
+    use cpu::memory::CpuBus;
+    use nes::mapper;
+    use nes::rom;
+    use nes::rom::PRG_ROM_PAGE_LENGTH;
+
+    const NESTEST_ROM: &str = "tests/nestest/nestest.nes";
+    const NESTEST_LOG: &str = "tests/nestest/nestest.log";
+
+    // Runs nestest in automation mode (starting at $C000 instead of the reset vector) and compares every
+    // line of the trace against the reference log from Nintendulator
+    // The ROM and log aren't checked in, tests/nestest/fetch.sh downloads them and the test fails when they're missing
+    // Ref: https://wiki.nesdev.com/w/index.php/Emulator_tests
+    #[test]
+    #[ignore = "needs tests/nestest/nestest.nes and nestest.log, see tests/nestest/fetch.sh"]
+    fn nestest_matches_reference_log() {
+        for path in &[NESTEST_ROM, NESTEST_LOG] {
+            assert!(Path::new(path).exists(), "{} is missing", path);
+        }
+
+        let rom = rom::load(&fs::read(NESTEST_ROM).unwrap()).unwrap();
+        let mut cpu = Cpu::new(Box::new(CpuBus::new(mapper::new(&rom).unwrap())));
+        cpu.power_on();
+        cpu.registers.pc = 0xC000;
+
+        let log = BufReader::new(File::open(NESTEST_LOG).unwrap());
+
+        for (number, expected) in log.lines().enumerate() {
+            let expected = expected.unwrap();
+            assert_eq!(cpu.trace_line(), expected.trim_end(), "Trace differs from {} at line {}", NESTEST_LOG, number + 1);
+            cpu.step();
+        }
+    }
+
+    // The first lines of nestest.log, followed by an unofficial opcode that Nintendulator names differently
+    #[test]
+    fn trace_matches_nintendulator_format() {
+        let mut bytes = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg_rom = vec![0xEA; PRG_ROM_PAGE_LENGTH];
+        prg_rom[0x0000..0x0003].copy_from_slice(&[0x4C, 0xF5, 0xC5]);                       // JMP $C5F5
+        prg_rom[0x05F5..0x05FB].copy_from_slice(&[0xA2, 0x00, 0x86, 0x00, 0xE7, 0x10]);     // LDX #$00, STX $00, ISC $10
+        bytes.extend(prg_rom);
+        bytes.extend(vec![0; 0x2000]);
+
+        let rom = rom::load(&bytes).unwrap();
+        let mut cpu = Cpu::new(Box::new(CpuBus::new(mapper::new(&rom).unwrap())));
+        cpu.power_on();
+        cpu.registers.pc = 0xC000;
+
+        let expected = [
+            "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7",
+            "C5F5  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 30 CYC:10",
+            "C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 36 CYC:12",
+            "C5F9  E7 10    *ISB $10 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 45 CYC:15"
+        ];
+        for line in expected.iter() {
+            assert_eq!(cpu.trace_line(), *line);
+            cpu.step();
+        }
+    }
+}
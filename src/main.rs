@@ -3,26 +3,51 @@ extern crate ansi_term;
 extern crate bitflags;
 extern crate byteorder;
 extern crate clap;
+extern crate crc32fast;
+extern crate flate2;
+extern crate md5;
 #[macro_use]
 extern crate nom;
+#[macro_use]
+extern crate serde_json;
+extern crate sha1;
 
 
 
+mod apu;
 mod cpu;
 mod nes;
 
+use std::env;
+use std::fs;
 use std::fs::File;
+use std::io;
 use std::io::{Cursor, Read};
-use std::path::Path;
-use ansi_term::Colour::Red;
+use std::path::{Path, PathBuf};
+use std::process;
+use ansi_term::Colour::{Red, Yellow};
 use byteorder::{LittleEndian, ReadBytesExt};
-use clap::{Arg, App, AppSettings, SubCommand};
+use clap::{Arg, App, AppSettings, ArgMatches, SubCommand};
+use apu::wav;
 use cpu::disassembler;
 use cpu::instructions;
 use cpu::instructions::Instruction;
 use nes::NES;
+use nes::archive;
+use nes::database::{Database, DATABASE_VARIABLE};
+use nes::fds;
+use nes::info;
+use nes::nsf;
+use nes::nsf::{ExpansionAudio, Nsf2Flags};
+use nes::nsf_player;
+use nes::patch;
+use nes::patch::PatchFormat;
 use nes::rom;
-use nes::rom::ROM;
+use nes::rom::{Format, Region, ROM, ScreenMode};
+
+// Output format and default length of rendered NSF songs
+const NSF_SAMPLE_RATE: u32 = 44100;
+const NSF_DEFAULT_SECONDS: f64 = 120.0;
 
 fn main() {
     let input = App::new("Mudkip")
@@ -30,6 +55,11 @@ fn main() {
         .version("0.1")
         .author("Robin Mattheussen <robin.mattheussen@gmail.com>")
         .about("A bare-bones NES emulator")
+        .arg(Arg::with_name("database")
+            .long("database")
+            .value_name("/path/to/database.xml")
+            .global(true)
+            .help("Game database to correct headers with (nes20db.xml or NstDatabase.xml), defaults to $MUDKIP_DATABASE"))
         .subcommand(SubCommand::with_name("disassemble")
             .about("Disassembles the target ROM")
             .version("1.0")
@@ -38,24 +68,423 @@ fn main() {
                 .long("file")
                 .value_name("/path/to/file")
                 .required(true)
-                .help("Path to the ROM you want to disassemble")))
+                .help("Path to the ROM you want to disassemble"))
+            .arg(entry_arg())
+            .arg(patch_arg()))
+        .subcommand(SubCommand::with_name("info")
+            .about("Shows the header and checksums of the target ROM or FDS disk image")
+            .version("1.0")
+            .arg(Arg::with_name("file")
+                .short("f")
+                .long("file")
+                .value_name("/path/to/file")
+                .required(true)
+                .help("Path to the ROM or disk image you want to inspect"))
+            .arg(Arg::with_name("json")
+                .long("json")
+                .help("Prints the information as JSON"))
+            .arg(entry_arg())
+            .arg(patch_arg()))
+        .subcommand(SubCommand::with_name("fix-header")
+            .about("Rewrites the header of the target ROM, using the game database and the given values")
+            .version("1.0")
+            .arg(Arg::with_name("file")
+                .short("f")
+                .long("file")
+                .value_name("/path/to/file")
+                .required(true)
+                .help("Path to the ROM you want to fix"))
+            .arg(entry_arg())
+            .arg(patch_arg())
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("/path/to/file")
+                .required(true)
+                .help("Path to write the fixed ROM to"))
+            .arg(Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["ines", "nes20"])
+                .help("Header format to write, defaults to the format of the ROM"))
+            .arg(Arg::with_name("mapper")
+                .long("mapper")
+                .takes_value(true)
+                .help("Mapper number"))
+            .arg(Arg::with_name("submapper")
+                .long("submapper")
+                .takes_value(true)
+                .help("Submapper number, NES 2.0 only"))
+            .arg(Arg::with_name("mirroring")
+                .long("mirroring")
+                .takes_value(true)
+                .possible_values(&["horizontal", "vertical", "four-screen"])
+                .help("Nametable mirroring"))
+            .arg(Arg::with_name("region")
+                .long("region")
+                .takes_value(true)
+                .possible_values(&["ntsc", "pal", "multi", "dendy"])
+                .help("CPU/PPU timing"))
+            .arg(Arg::with_name("battery")
+                .long("battery")
+                .takes_value(true)
+                .possible_values(&["yes", "no"])
+                .help("Whether the PRG-RAM is battery-backed"))
+            .arg(Arg::with_name("prg-ram")
+                .long("prg-ram")
+                .takes_value(true)
+                .help("PRG-RAM size in bytes"))
+            .arg(Arg::with_name("chr-ram")
+                .long("chr-ram")
+                .takes_value(true)
+                .help("CHR-RAM size in bytes")))
+        .subcommand(SubCommand::with_name("create-patch")
+            .about("Creates a patch that turns one ROM into another")
+            .version("1.0")
+            .arg(Arg::with_name("source")
+                .short("s")
+                .long("source")
+                .value_name("/path/to/file")
+                .required(true)
+                .help("Path to the original ROM"))
+            .arg(Arg::with_name("target")
+                .short("t")
+                .long("target")
+                .value_name("/path/to/file")
+                .required(true)
+                .help("Path to the modified ROM"))
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("/path/to/patch")
+                .required(true)
+                .help("Path to write the patch to"))
+            .arg(Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["ips", "bps"])
+                .help("Patch format, defaults to BPS for a .bps output and IPS otherwise")))
+        .subcommand(SubCommand::with_name("run")
+            .about("Runs the target ROM, an instruction every time enter is pressed unless --steps is given")
+            .version("1.0")
+            .arg(Arg::with_name("file")
+                .short("f")
+                .long("file")
+                .value_name("/path/to/file")
+                .required(true)
+                .help("Path to the ROM or FDS disk image you want to run"))
+            .arg(entry_arg())
+            .arg(patch_arg())
+            .arg(Arg::with_name("bios")
+                .short("b")
+                .long("bios")
+                .value_name("/path/to/disksys.rom")
+                .help("BIOS of the Famicom Disk System, needed to run FDS disk images"))
+            .arg(Arg::with_name("steps")
+                .short("s")
+                .long("steps")
+                .value_name("N")
+                .help("Amount of instructions to run before exiting"))
+            .arg(Arg::with_name("trace")
+                .short("t")
+                .long("trace")
+                .help("Prints the CPU state before every instruction, in the format of nestest.log")))
+        .subcommand(SubCommand::with_name("play-nsf")
+            .about("Renders a song of an NSF or NSFe file to a WAV file")
+            .version("1.0")
+            .arg(Arg::with_name("file")
+                .short("f")
+                .long("file")
+                .value_name("/path/to/file")
+                .required(true)
+                .help("Path to the NSF or NSFe file you want to play"))
+            .arg(entry_arg())
+            .arg(patch_arg())
+            .arg(Arg::with_name("track")
+                .short("t")
+                .long("track")
+                .value_name("N")
+                .help("Song to play, counting from 1, defaults to the starting song of the file"))
+            .arg(Arg::with_name("seconds")
+                .short("s")
+                .long("seconds")
+                .value_name("S")
+                .help("How long to play for, defaults to the length of the song for NSFe files and 120 seconds otherwise"))
+            .arg(Arg::with_name("out")
+                .short("o")
+                .long("out")
+                .value_name("/path/to/file.wav")
+                .required(true)
+                .help("Path to write the WAV file to")))
         .get_matches();
 
+    // A database that can't be loaded is reported, rather than silently leaving headers uncorrected
+    let database = input.value_of_os("database").map(PathBuf::from)
+        .or_else(|| env::var_os(DATABASE_VARIABLE).map(PathBuf::from));
+    if let Some(path) = database {
+        if let Err(err) = Database::set_default_database(&path) {
+            fail(format!("Failed to load database {:?}: {}", path, err));
+        }
+    }
+
     match input.subcommand() {
         // Use the disassemble function of the emulator
         ("disassemble", Some(file_input)) => {
-            let path_str = file_input.value_of("file").unwrap();
-            let path = Path::new(path_str);
-
-            match File::open(path) {
-                Ok(mut file) => {
-                    let rom = rom::load_from_file(&mut file).unwrap();
+            match load_rom(file_input) {
+                Ok((_, rom)) => {
+                    for warning in &rom.warnings {
+                        eprintln!("{} {}", Yellow.bold().paint("warning:"), warning);
+                    }
                     disassembler::disassemble(rom);
                 }
-                Err(err) => eprintln!("{} Invalid path {:?} specified!", Red.bold().paint("error:"), path)
+                Err(err) => fail(err)
+            }
+        }
+
+        // Show everything known about the ROM or disk image
+        ("info", Some(info_input)) => {
+            match info(info_input) {
+                Ok(text) => println!("{}", text),
+                Err(err) => fail(err)
+            }
+        }
+
+        // Rewrite the header of a ROM
+        ("fix-header", Some(fix_input)) => {
+            if let Err(err) = fix_header(fix_input) {
+                fail(err);
+            }
+        }
+
+        // Diff two ROMs into a patch
+        ("create-patch", Some(patch_input)) => {
+            if let Err(err) = create_patch(patch_input) {
+                fail(err);
+            }
+        }
+
+        // Run a ROM on the emulated system
+        ("run", Some(run_input)) => {
+            if let Err(err) = run(run_input) {
+                fail(err);
+            }
+        }
+
+        // Render a song of an NSF to a WAV file
+        ("play-nsf", Some(nsf_input)) => {
+            if let Err(err) = play_nsf(nsf_input) {
+                fail(err);
             }
         }
 
         _ => ()
     }
 }
+
+// Reports an error and exits with a failure status, so scripts can tell the command didn't succeed
+fn fail(err: String) -> ! {
+    eprintln!("{} {}", Red.bold().paint("error:"), err);
+    process::exit(1);
+}
+
+// Option to apply a patch to the ROM before using it
+fn patch_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("patch")
+        .short("p")
+        .long("patch")
+        .value_name("/path/to/patch")
+        .help("IPS, UPS or BPS patch to apply to the ROM")
+}
+
+// Option to pick a file from a zip archive
+fn entry_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("entry")
+        .long("entry")
+        .value_name("name")
+        .help("File to load from a zip archive, defaults to the first .nes, .unf or .fds file")
+}
+
+// Reads the file given by --file, taken from an archive when it's compressed, and the patch given by --patch
+fn read_input(input: &ArgMatches) -> Result<(Vec<u8>, Option<Vec<u8>>), String> {
+    let path = Path::new(input.value_of("file").unwrap());
+    let buf = fs::read(path).map_err(|err| format!("Invalid path {:?} specified: {}", path, err))?;
+    let buf = archive::extract(&buf, input.value_of("entry"))
+        .map_err(|err| format!("Failed to extract {:?}: {}", path, err))?
+        .into_owned();
+    let patch = match input.value_of("patch") {
+        Some(patch_path) => Some(fs::read(patch_path).map_err(|err| format!("Failed to read patch {:?}: {}", patch_path, err))?),
+        None => None
+    };
+
+    Ok((buf, patch))
+}
+
+// Loads the ROM given by --file with the patch given by --patch applied, returns both the uncompressed file and the ROM
+fn load_rom(input: &ArgMatches) -> Result<(Vec<u8>, ROM), String> {
+    let path = input.value_of("file").unwrap();
+    let (buf, patch) = read_input(input)?;
+    let rom = rom::load_with_patch(&buf, patch.as_ref().map(|patch| &patch[..]))
+        .map_err(|err| format!("Failed to load ROM {:?}: {}", path, err))?;
+    Ok((buf, rom))
+}
+
+// Describes the ROM or FDS disk image given by --file, as text or JSON
+// The patch is applied up front, so the file checksums are the ones of the image that was actually loaded
+fn info(input: &ArgMatches) -> Result<String, String> {
+    let path = input.value_of("file").unwrap();
+    let (buf, patch) = read_input(input)?;
+    let buf = match patch {
+        Some(patch) => patch::apply(&buf, &patch).map_err(|err| format!("Failed to patch {:?}: {}", path, err))?,
+        None => buf
+    };
+    let json = input.is_present("json");
+
+    if fds::is_disk(&buf) {
+        let disk = fds::load(&buf).map_err(|err| format!("Failed to load disk image {:?}: {}", path, err))?;
+        Ok(if json { serde_json::to_string_pretty(&info::disk_json(&buf, &disk)).unwrap() } else { info::disk_text(&buf, &disk) })
+    } else {
+        let rom = rom::load(&buf).map_err(|err| format!("Failed to load ROM {:?}: {}", path, err))?;
+        Ok(if json { serde_json::to_string_pretty(&info::json(&buf, &rom)).unwrap() } else { info::text(&buf, &rom) })
+    }
+}
+
+// Loads a ROM, corrects its header with the database and then the given values, and writes it to the output
+fn fix_header(input: &ArgMatches) -> Result<(), String> {
+    let (_, mut rom) = load_rom(input)?;
+
+    // iNES headers were already corrected while loading, NES 2.0 headers are only corrected on request like here
+    let mut warnings = rom.warnings.clone();
+    if let Some(entry) = Database::default_database().find(&rom.prg_rom, &rom.chr_rom) {
+        warnings.extend(entry.apply(&mut rom.header));
+    }
+
+    {
+        let header = &mut rom.header;
+        if let Some(mapper) = input.value_of("mapper") {
+            header.mapper = mapper.parse().map_err(|_| format!("Invalid mapper {:?}", mapper))?;
+        }
+        if let Some(submapper) = input.value_of("submapper") {
+            header.submapper = submapper.parse().map_err(|_| format!("Invalid submapper {:?}", submapper))?;
+        }
+        if let Some(prg_ram) = input.value_of("prg-ram") {
+            header.prg_ram_size = prg_ram.parse().map_err(|_| format!("Invalid PRG-RAM size {:?}", prg_ram))?;
+        }
+        if let Some(chr_ram) = input.value_of("chr-ram") {
+            header.chr_ram_size = chr_ram.parse().map_err(|_| format!("Invalid CHR-RAM size {:?}", chr_ram))?;
+        }
+        match input.value_of("mirroring") {
+            Some("horizontal")  => header.screen_mode = ScreenMode::Horizontal,
+            Some("vertical")    => header.screen_mode = ScreenMode::Vertical,
+            Some("four-screen") => header.screen_mode = ScreenMode::FourScreen,
+            _ => ()
+        }
+        match input.value_of("region") {
+            Some("ntsc")  => header.region = Region::NTSC,
+            Some("pal")   => header.region = Region::PAL,
+            Some("multi") => header.region = Region::Multi,
+            Some("dendy") => header.region = Region::Dendy,
+            _ => ()
+        }
+        if let Some(battery) = input.value_of("battery") {
+            header.battery = battery == "yes";
+        }
+    }
+
+    let format = match input.value_of("format") {
+        Some("ines")  => Format::INES,
+        Some("nes20") => Format::NES20,
+        // UNIF can't be written, NES 2.0 is the only format that can hold everything it describes
+        _ if rom.header.format == Format::UNIF => Format::NES20,
+        _ => rom.header.format
+    };
+
+    let output = Path::new(input.value_of("output").unwrap());
+    let bytes = rom.to_bytes(format).map_err(|err| err.to_string())?;
+    fs::write(output, bytes).map_err(|err| format!("Failed to write {:?}: {}", output, err))?;
+
+    for warning in warnings {
+        eprintln!("{} {}", Yellow.bold().paint("warning:"), warning);
+    }
+
+    Ok(())
+}
+
+// Creates a patch that turns the source ROM into the target ROM
+fn create_patch(input: &ArgMatches) -> Result<(), String> {
+    let read = |name| {
+        let path = input.value_of(name).unwrap();
+        fs::read(path).map_err(|err| format!("Failed to read {:?}: {}", path, err))
+    };
+    let (source, target) = (read("source")?, read("target")?);
+    let output = Path::new(input.value_of("output").unwrap());
+
+    // Without an explicit format, a .bps extension makes a BPS patch and anything else an IPS patch
+    let format = match input.value_of("format") {
+        Some("bps") => PatchFormat::BPS,
+        Some(_) => PatchFormat::IPS,
+        None if output.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("bps")) => PatchFormat::BPS,
+        None => PatchFormat::IPS
+    };
+
+    let bytes = patch::create(&source, &target, format).map_err(|err| err.to_string())?;
+    fs::write(output, bytes).map_err(|err| format!("Failed to write {:?}: {}", output, err))
+}
+
+// Runs the ROM given by --file, which needs a mapper that's supported
+fn run(input: &ArgMatches) -> Result<(), String> {
+    let steps = match input.value_of("steps") {
+        Some(steps) => Some(steps.parse::<u64>().map_err(|_| format!("Invalid amount of steps {:?}", steps))?),
+        None => None
+    };
+
+    let path = input.value_of("file").unwrap();
+    let (buf, patch) = read_input(input)?;
+    let patch = patch.as_ref().map(|patch| &patch[..]);
+    let mut nes = if fds::is_disk(&buf) {
+        let disk = fds::load_with_patch(&buf, patch).map_err(|err| format!("Failed to load disk image {:?}: {}", path, err))?;
+        let bios = fds::load_bios(input.value_of("bios").map(Path::new)).map_err(|err| err.to_string())?;
+        NES::with_disk(&disk, bios)
+    } else {
+        let rom = rom::load_with_patch(&buf, patch).map_err(|err| format!("Failed to load ROM {:?}: {}", path, err))?;
+        NES::new(&rom).map_err(|err| format!("Failed to run {:?}: {}", path, err))?
+    };
+    if input.is_present("trace") {
+        nes.set_trace(Some(Box::new(io::stdout())));
+    }
+    nes.run(steps);
+    Ok(())
+}
+
+// Renders a song of an NSF or NSFe file to a WAV file
+fn play_nsf(input: &ArgMatches) -> Result<(), String> {
+    let path = input.value_of("file").unwrap();
+    let (buf, patch) = read_input(input)?;
+    let nsf = nsf::load_with_patch(&buf, patch.as_ref().map(|patch| &patch[..]))
+        .map_err(|err| format!("Failed to load NSF {:?}: {}", path, err))?;
+
+    let song = match input.value_of("track") {
+        Some(track) => match track.parse::<u8>() {
+            Ok(track) if track >= 1 && track <= nsf.songs => track - 1,
+            _ => return Err(format!("Invalid track {:?}, the file has {} songs", track, nsf.songs))
+        },
+        None => nsf.starting_song
+    };
+    let seconds = match input.value_of("seconds") {
+        Some(seconds) => seconds.parse::<f64>().ok().filter(|&seconds| seconds > 0.0).ok_or_else(|| format!("Invalid length {:?}", seconds))?,
+        None => nsf.track_times.get(song as usize).cloned().flatten().map_or(NSF_DEFAULT_SECONDS, |time| time as f64 / 1000.0)
+    };
+
+    if !nsf.expansion.is_empty() {
+        eprintln!("{} Expansion audio ({:?}) isn't emulated, only the 2A03 channels will be heard", Yellow.bold().paint("warning:"), nsf.expansion);
+    }
+    if nsf.expansion.intersects(ExpansionAudio::MMC5 | ExpansionAudio::N163 | ExpansionAudio::VT02) {
+        eprintln!("{} The extra RAM of the expansion chips isn't emulated either, the music may not play at all", Yellow.bold().paint("warning:"));
+    }
+    if nsf.flags.intersects(Nsf2Flags::IRQ | Nsf2Flags::NON_RETURNING_INIT) {
+        eprintln!("{} NSF2 IRQs and non-returning INIT routines aren't supported, the music may not play at all", Yellow.bold().paint("warning:"));
+    }
+
+    let samples = nsf_player::render(&nsf, song, seconds, NSF_SAMPLE_RATE);
+    let output = input.value_of("out").unwrap();
+    fs::write(output, wav::encode(&samples, NSF_SAMPLE_RATE)).map_err(|err| format!("Failed to write {:?}: {}", output, err))
+}
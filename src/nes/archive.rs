@@ -0,0 +1,298 @@
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::io::Read;
+
+use byteorder::{ByteOrder, LittleEndian};
+use flate2::read::{DeflateDecoder, GzDecoder};
+
+use nes::hash::crc32;
+
+const ZIP_LOCAL_HEADER: u32 = 0x0403_4B50;
+const ZIP_CENTRAL_HEADER: u32 = 0x0201_4B50;
+const ZIP_END_OF_DIRECTORY: u32 = 0x0605_4B50;
+const ZIP_LOCAL_HEADER_LENGTH: usize = 30;
+const ZIP_CENTRAL_HEADER_LENGTH: usize = 46;
+const ZIP_END_OF_DIRECTORY_LENGTH: usize = 22;
+const ZIP_STORED: u16 = 0;
+const ZIP_DEFLATED: u16 = 8;
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+
+// Extensions of the files that are picked from an archive when no entry was named
+const ROM_EXTENSIONS: [&str; 3] = [".nes", ".unf", ".fds"];
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Corrupt(&'static str),                          // The archive structure doesn't make sense
+    UnsupportedCompression(u16),                    // Zip compression method other than stored or deflate
+    NoRom,                                          // The archive doesn't contain any ROM
+    MissingEntry(String),                           // The archive doesn't contain the requested entry
+    Checksum { expected: u32, actual: u32 },
+    Decompression(String)
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ArchiveError::Corrupt(reason)                   => write!(f, "Archive is corrupt: {}", reason),
+            ArchiveError::UnsupportedCompression(method)    => write!(f, "Unsupported zip compression method {}", method),
+            ArchiveError::NoRom                             => write!(f, "Archive doesn't contain a .nes, .unf or .fds file"),
+            ArchiveError::MissingEntry(ref name)            => write!(f, "Archive doesn't contain {:?}", name),
+            ArchiveError::Checksum { expected, actual }     => write!(f, "Archive entry should have CRC32 {:08x}, but it is {:08x}", expected, actual),
+            ArchiveError::Decompression(ref reason)         => write!(f, "Failed to decompress: {}", reason)
+        }
+    }
+}
+
+impl Error for ArchiveError {}
+
+// Returns the ROM inside a zip or gzip file, or the file itself when it isn't compressed
+// From a zip, the named entry is picked, or otherwise the first .nes, .unf or .fds file
+pub fn extract<'a>(buf: &'a [u8], entry: Option<&str>) -> Result<Cow<'a, [u8]>, ArchiveError> {
+    if buf.len() >= 4 && LittleEndian::read_u32(buf) == ZIP_LOCAL_HEADER {
+        extract_zip(buf, entry).map(Cow::Owned)
+    } else if buf.starts_with(GZIP_MAGIC) {
+        let mut rom = vec![];
+        GzDecoder::new(buf).read_to_end(&mut rom).map_err(|err| ArchiveError::Decompression(err.to_string()))?;
+        Ok(Cow::Owned(rom))
+    } else {
+        Ok(Cow::Borrowed(buf))
+    }
+}
+
+// A file as listed in the central directory of a zip
+struct ZipEntry<'a> {
+    name: &'a str,
+    method: u16,
+    crc32: u32,
+    compressed_size: usize,
+    local_header: usize
+}
+
+// Ref: https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT
+fn extract_zip(buf: &[u8], name: Option<&str>) -> Result<Vec<u8>, ArchiveError> {
+    let entries = zip_entries(buf)?;
+    let entry = match name {
+        Some(name) => entries.iter()
+            .find(|entry| entry.name == name || entry.name.rsplit('/').next() == Some(name))
+            .ok_or_else(|| ArchiveError::MissingEntry(name.to_string()))?,
+        None => entries.iter()
+            .find(|entry| ROM_EXTENSIONS.iter().any(|extension| entry.name.to_lowercase().ends_with(extension)))
+            .ok_or(ArchiveError::NoRom)?
+    };
+
+    // The local header repeats the name and has its own extra field, so the data offset has to be read from it
+    let header = buf.get(entry.local_header..entry.local_header + ZIP_LOCAL_HEADER_LENGTH).ok_or(ArchiveError::Corrupt("local header out of bounds"))?;
+    if LittleEndian::read_u32(header) != ZIP_LOCAL_HEADER {
+        return Err(ArchiveError::Corrupt("bad local header signature"));
+    }
+    let start = entry.local_header + ZIP_LOCAL_HEADER_LENGTH + LittleEndian::read_u16(&header[26..]) as usize + LittleEndian::read_u16(&header[28..]) as usize;
+    let data = buf.get(start..start + entry.compressed_size).ok_or(ArchiveError::Corrupt("entry data out of bounds"))?;
+
+    let rom = match entry.method {
+        ZIP_STORED => data.to_vec(),
+        ZIP_DEFLATED => {
+            let mut rom = vec![];
+            DeflateDecoder::new(data).read_to_end(&mut rom).map_err(|err| ArchiveError::Decompression(err.to_string()))?;
+            rom
+        }
+        method => return Err(ArchiveError::UnsupportedCompression(method))
+    };
+
+    let actual = crc32(&rom);
+    if actual != entry.crc32 {
+        return Err(ArchiveError::Checksum { expected: entry.crc32, actual });
+    }
+    Ok(rom)
+}
+
+// Reads the central directory, which is found through the end of central directory record at the end of the file
+fn zip_entries(buf: &[u8]) -> Result<Vec<ZipEntry<'_>>, ArchiveError> {
+    // The record is followed by a comment of at most 64KB, so it has to be searched for from the end
+    let end = (0..buf.len().saturating_sub(ZIP_END_OF_DIRECTORY_LENGTH - 1)).rev()
+        .take(0x10000 + ZIP_END_OF_DIRECTORY_LENGTH)
+        .find(|&offset| LittleEndian::read_u32(&buf[offset..]) == ZIP_END_OF_DIRECTORY)
+        .ok_or(ArchiveError::Corrupt("no end of central directory record"))?;
+
+    let count = LittleEndian::read_u16(&buf[end + 10..]) as usize;
+    let mut offset = LittleEndian::read_u32(&buf[end + 16..]) as usize;
+    let mut entries = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        let header = buf.get(offset..offset + ZIP_CENTRAL_HEADER_LENGTH).ok_or(ArchiveError::Corrupt("central directory out of bounds"))?;
+        if LittleEndian::read_u32(header) != ZIP_CENTRAL_HEADER {
+            return Err(ArchiveError::Corrupt("bad central directory signature"));
+        }
+
+        let name_length = LittleEndian::read_u16(&header[28..]) as usize;
+        let extra_length = LittleEndian::read_u16(&header[30..]) as usize;
+        let comment_length = LittleEndian::read_u16(&header[32..]) as usize;
+        let name = buf.get(offset + ZIP_CENTRAL_HEADER_LENGTH..offset + ZIP_CENTRAL_HEADER_LENGTH + name_length)
+            .ok_or(ArchiveError::Corrupt("file name out of bounds"))?;
+
+        entries.push(ZipEntry {
+            name: ::std::str::from_utf8(name).map_err(|_| ArchiveError::Corrupt("file name is not UTF-8"))?,
+            method: LittleEndian::read_u16(&header[10..]),
+            crc32: LittleEndian::read_u32(&header[16..]),
+            compressed_size: LittleEndian::read_u32(&header[20..]) as usize,
+            local_header: LittleEndian::read_u32(&header[42..]) as usize
+        });
+
+        offset += ZIP_CENTRAL_HEADER_LENGTH + name_length + extra_length + comment_length;
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    use byteorder::WriteBytesExt;
+    use flate2::Compression;
+    use flate2::write::{DeflateEncoder, GzEncoder};
+
+    // A file to put in a test zip, with the CRC32 the central directory claims it has
+    struct File<'a> {
+        name: &'a str,
+        data: &'a [u8],
+        deflate: bool,
+        crc32: u32
+    }
+
+    fn file<'a>(name: &'a str, data: &'a [u8], deflate: bool) -> File<'a> {
+        File { name, data, deflate, crc32: crc32(data) }
+    }
+
+    // Builds a zip with local headers, a central directory and an end of central directory record with a comment
+    fn zip(files: &[File], comment: &[u8]) -> Vec<u8> {
+        let mut zip = vec![];
+        let mut directory = vec![];
+
+        for file in files {
+            let (method, data) = if file.deflate {
+                let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+                encoder.write_all(file.data).unwrap();
+                (ZIP_DEFLATED, encoder.finish().unwrap())
+            } else {
+                (ZIP_STORED, file.data.to_vec())
+            };
+            let local_header = zip.len() as u32;
+
+            zip.write_u32::<LittleEndian>(ZIP_LOCAL_HEADER).unwrap();
+            zip.extend_from_slice(&[20, 0, 0, 0]);                      // Version needed and flags
+            zip.write_u16::<LittleEndian>(method).unwrap();
+            zip.extend_from_slice(&[0; 4]);                             // Modification time and date
+            zip.write_u32::<LittleEndian>(file.crc32).unwrap();
+            zip.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+            zip.write_u32::<LittleEndian>(file.data.len() as u32).unwrap();
+            zip.write_u16::<LittleEndian>(file.name.len() as u16).unwrap();
+            zip.write_u16::<LittleEndian>(4).unwrap();                  // An extra field the central directory doesn't have
+            zip.extend_from_slice(file.name.as_bytes());
+            zip.extend_from_slice(&[0xFF; 4]);
+            zip.extend_from_slice(&data);
+
+            directory.write_u32::<LittleEndian>(ZIP_CENTRAL_HEADER).unwrap();
+            directory.extend_from_slice(&[20, 0, 20, 0, 0, 0]);         // Versions and flags
+            directory.write_u16::<LittleEndian>(method).unwrap();
+            directory.extend_from_slice(&[0; 4]);
+            directory.write_u32::<LittleEndian>(file.crc32).unwrap();
+            directory.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+            directory.write_u32::<LittleEndian>(file.data.len() as u32).unwrap();
+            directory.write_u16::<LittleEndian>(file.name.len() as u16).unwrap();
+            directory.extend_from_slice(&[0; 12]);                      // Extra field and comment lengths, disk and attributes
+            directory.write_u32::<LittleEndian>(local_header).unwrap();
+            directory.extend_from_slice(file.name.as_bytes());
+        }
+
+        let directory_offset = zip.len() as u32;
+        zip.extend_from_slice(&directory);
+        zip.write_u32::<LittleEndian>(ZIP_END_OF_DIRECTORY).unwrap();
+        zip.extend_from_slice(&[0; 4]);                                 // Disk numbers
+        zip.write_u16::<LittleEndian>(files.len() as u16).unwrap();
+        zip.write_u16::<LittleEndian>(files.len() as u16).unwrap();
+        zip.write_u32::<LittleEndian>(directory.len() as u32).unwrap();
+        zip.write_u32::<LittleEndian>(directory_offset).unwrap();
+        zip.write_u16::<LittleEndian>(comment.len() as u16).unwrap();
+        zip.extend_from_slice(comment);
+        zip
+    }
+
+    #[test]
+    fn uncompressed_files_are_left_alone() {
+        let rom = b"NES\x1a";
+        match extract(rom, None).unwrap() {
+            Cow::Borrowed(data) => assert_eq!(data, rom),
+            Cow::Owned(_) => panic!("Expected the file itself")
+        }
+    }
+
+    #[test]
+    fn zip_stored_and_deflated() {
+        let rom: Vec<u8> = (0..4096).map(|i| (i % 13) as u8).collect();
+        for &deflate in &[false, true] {
+            let zip = zip(&[file("readme.txt", b"Not a ROM", false), file("Game.NES", &rom, deflate)], b"");
+            assert_eq!(extract(&zip, None).unwrap().into_owned(), rom);
+        }
+    }
+
+    #[test]
+    fn zip_with_comment() {
+        // The end of central directory record has to be searched for from the end, past the comment
+        let zip = zip(&[file("game.nes", b"ROM", false)], &[b'x'; 1000]);
+        assert_eq!(extract(&zip, None).unwrap().into_owned(), b"ROM");
+
+        match extract(&zip[..zip.len() - 1000 - 1], None) {
+            Err(ArchiveError::Corrupt(_)) => (),
+            result => panic!("Expected a corrupt archive, got {:?}", result.map(|rom| rom.into_owned()))
+        }
+    }
+
+    #[test]
+    fn zip_entry_selection() {
+        let zip = zip(&[file("side1.fds", b"FDS", true), file("roms/game.nes", b"NES", true), file("game.unf", b"UNIF", false)], b"");
+        assert_eq!(extract(&zip, None).unwrap().into_owned(), b"FDS");
+        assert_eq!(extract(&zip, Some("roms/game.nes")).unwrap().into_owned(), b"NES");
+        assert_eq!(extract(&zip, Some("game.nes")).unwrap().into_owned(), b"NES");
+        assert_eq!(extract(&zip, Some("game.unf")).unwrap().into_owned(), b"UNIF");
+
+        match extract(&zip, Some("missing.nes")) {
+            Err(ArchiveError::MissingEntry(ref name)) if name == "missing.nes" => (),
+            result => panic!("Expected a missing entry, got {:?}", result.map(|rom| rom.into_owned()))
+        }
+    }
+
+    #[test]
+    fn zip_without_rom() {
+        let zip = zip(&[file("readme.txt", b"Not a ROM", false)], b"");
+        match extract(&zip, None) {
+            Err(ArchiveError::NoRom) => (),
+            result => panic!("Expected no ROM, got {:?}", result.map(|rom| rom.into_owned()))
+        }
+    }
+
+    #[test]
+    fn zip_checksum_mismatch() {
+        let mut damaged = file("game.nes", b"ROM", true);
+        damaged.crc32 ^= 1;
+        let zip = zip(&[damaged], b"");
+        match extract(&zip, None) {
+            Err(ArchiveError::Checksum { expected, actual }) => assert_eq!(expected ^ 1, actual),
+            result => panic!("Expected a checksum mismatch, got {:?}", result.map(|rom| rom.into_owned()))
+        }
+    }
+
+    #[test]
+    fn gzip() {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(b"NES\x1a").unwrap();
+        let gzip = encoder.finish().unwrap();
+        assert_eq!(extract(&gzip, None).unwrap().into_owned(), b"NES\x1a");
+
+        match extract(&gzip[..gzip.len() - 8], None) {
+            Err(ArchiveError::Decompression(_)) => (),
+            result => panic!("Expected a decompression error, got {:?}", result.map(|rom| rom.into_owned()))
+        }
+    }
+}
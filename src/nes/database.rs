@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use nes::hash::{crc32, sha1};
+use nes::rom::{Header, Region, ScreenMode};
+
+// Environment variable pointing to the database to use when none was given on the command line
+pub const DATABASE_VARIABLE: &str = "MUDKIP_DATABASE";
+
+static DEFAULT_DATABASE: OnceLock<Database> = OnceLock::new();
+
+// The correct header values of a known dump, only the values the database knows about are set
+#[derive(Debug, Default, Clone)]
+pub struct Entry {
+    pub crc32: Option<u32>,
+    pub sha1: Option<String>,
+    pub mapper: Option<u16>,
+    pub submapper: Option<u8>,
+    pub screen_mode: Option<ScreenMode>,
+    pub region: Option<Region>,
+    pub battery: Option<bool>,
+    pub prg_ram_size: Option<usize>,
+    pub prg_nvram_size: Option<usize>,
+    pub chr_ram_size: Option<usize>,
+    pub chr_nvram_size: Option<usize>
+}
+
+// Game database keyed by the CRC32 and SHA-1 of the PRG-ROM followed by the CHR-ROM
+// Reads either Nestopia's NstDatabase.xml or the nes20db.xml from the NES 2.0 header database
+// Ref: https://github.com/0ldsk00l/nestopia/blob/master/NstDatabase.xml
+// Ref: https://forums.nesdev.com/viewtopic.php?t=19940
+#[derive(Debug, Default)]
+pub struct Database {
+    entries: Vec<Entry>,
+    by_crc32: HashMap<u32, usize>,
+    by_sha1: HashMap<String, usize>
+}
+
+impl Database {
+    pub fn from_file(path: &Path) -> io::Result<Database> {
+        Ok(Database::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn parse(xml: &str) -> Database {
+        let mut database = Database::default();
+        let mut current: Option<Entry> = None;
+
+        for tag in Tags::new(xml) {
+            match (tag.name, tag.closing) {
+                // NstDatabase has a <cartridge> per dump inside each <game>, nes20db has a <game> per dump
+                ("game", false) | ("cartridge", false) => {
+                    let mut entry = Entry::default();
+                    entry.read_tag(&tag);
+                    current = Some(entry);
+                }
+                ("game", true) | ("cartridge", true) => {
+                    if let Some(entry) = current.take() {
+                        database.insert(entry);
+                    }
+                }
+                (_, false) => {
+                    if let Some(ref mut entry) = current {
+                        entry.read_tag(&tag);
+                    }
+                }
+                _ => ()
+            }
+        }
+
+        database
+    }
+
+    // The database given by set_default_database, without one the database is empty so no headers get corrected
+    pub fn default_database() -> &'static Database {
+        DEFAULT_DATABASE.get_or_init(Database::default)
+    }
+
+    // Loads the database at the given path as the default database, which only works before it was first used
+    pub fn set_default_database(path: &Path) -> io::Result<()> {
+        let database = Database::from_file(path)?;
+        DEFAULT_DATABASE.set(database)
+            .map_err(|_| io::Error::other("The default database was already loaded"))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Looks up a dump by its PRG and CHR-ROM, SHA-1 matches are preferred over CRC32 matches
+    pub fn find(&self, prg_rom: &[u8], chr_rom: &[u8]) -> Option<&Entry> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let data = [prg_rom, chr_rom].concat();
+        self.by_sha1.get(&sha1(&data))
+            .or_else(|| self.by_crc32.get(&crc32(&data)))
+            .map(|&index| &self.entries[index])
+    }
+
+    fn insert(&mut self, entry: Entry) {
+        let index = self.entries.len();
+        if let Some(crc32) = entry.crc32 {
+            self.by_crc32.insert(crc32, index);
+        }
+        if let Some(ref sha1) = entry.sha1 {
+            self.by_sha1.insert(sha1.clone(), index);
+        }
+        if entry.crc32.is_some() || entry.sha1.is_some() {
+            self.entries.push(entry);
+        }
+    }
+}
+
+impl Entry {
+    // Overwrites the header values that differ from the database, returning a description of each correction
+    pub fn apply(&self, header: &mut Header) -> Vec<String> {
+        let mut corrections = vec![];
+        correct("mapper", &mut header.mapper, self.mapper, &mut corrections);
+        correct("submapper", &mut header.submapper, self.submapper, &mut corrections);
+        correct("mirroring", &mut header.screen_mode, self.screen_mode, &mut corrections);
+        correct("region", &mut header.region, self.region, &mut corrections);
+        correct("battery", &mut header.battery, self.battery, &mut corrections);
+        correct("PRG-RAM size", &mut header.prg_ram_size, self.prg_ram_size, &mut corrections);
+        correct("PRG-NVRAM size", &mut header.prg_nvram_size, self.prg_nvram_size, &mut corrections);
+        correct("CHR-RAM size", &mut header.chr_ram_size, self.chr_ram_size, &mut corrections);
+        correct("CHR-NVRAM size", &mut header.chr_nvram_size, self.chr_nvram_size, &mut corrections);
+        corrections
+    }
+
+    fn read_tag(&mut self, tag: &Tag) {
+        match tag.name {
+            // NstDatabase
+            "board" => self.mapper = tag.number("mapper").map(|mapper| mapper as u16).or(self.mapper),
+            "wram" => {
+                let size = tag.size("size");
+                if tag.attribute("battery") == Some("1") {
+                    self.battery = Some(true);
+                    self.prg_nvram_size = add_size(self.prg_nvram_size, size);
+                } else {
+                    self.prg_ram_size = add_size(self.prg_ram_size, size);
+                }
+            }
+            "vram" => self.chr_ram_size = add_size(self.chr_ram_size, tag.size("size")),
+            "pad" => {
+                // Soldering the H pad arranges the nametables horizontally, which means vertical mirroring
+                self.screen_mode = match (tag.attribute("h"), tag.attribute("v")) {
+                    (Some("1"), _) => Some(ScreenMode::Vertical),
+                    (_, Some("1")) => Some(ScreenMode::Horizontal),
+                    _ => self.screen_mode
+                };
+            }
+            "cartridge" => {
+                // The hashes and region of an NstDatabase dump are attributes of the <cartridge> tag itself
+                self.crc32 = tag.attribute("crc").and_then(|crc| u32::from_str_radix(crc, 16).ok());
+                self.sha1 = tag.attribute("sha1").map(|sha1| sha1.trim_start_matches("SHA1:").to_lowercase());
+                self.region = tag.attribute("system").map(|system| {
+                    if system.contains("DENDY") {
+                        Region::Dendy
+                    } else if system.contains("PAL") {
+                        Region::PAL
+                    } else {
+                        Region::NTSC
+                    }
+                });
+            }
+
+            // nes20db
+            "rom" => {
+                self.crc32 = tag.attribute("crc32").and_then(|crc| u32::from_str_radix(crc, 16).ok());
+                self.sha1 = tag.attribute("sha1").map(|sha1| sha1.to_lowercase());
+            }
+            "pcb" => {
+                self.mapper = tag.number("mapper").map(|mapper| mapper as u16);
+                self.submapper = tag.number("submapper").map(|submapper| submapper as u8);
+                self.battery = tag.attribute("battery").map(|battery| battery == "1");
+                self.screen_mode = match tag.attribute("mirroring") {
+                    Some("H") => Some(ScreenMode::Horizontal),
+                    Some("V") => Some(ScreenMode::Vertical),
+                    Some("4") => Some(ScreenMode::FourScreen),
+                    _ => None
+                };
+            }
+            "console" => {
+                self.region = match tag.number("region") {
+                    Some(0) => Some(Region::NTSC),
+                    Some(1) => Some(Region::PAL),
+                    Some(2) => Some(Region::Multi),
+                    Some(3) => Some(Region::Dendy),
+                    _ => None
+                };
+            }
+            "prgram" => self.prg_ram_size = tag.size("size"),
+            "prgnvram" => self.prg_nvram_size = tag.size("size"),
+            "chrram" => self.chr_ram_size = tag.size("size"),
+            "chrnvram" => self.chr_nvram_size = tag.size("size"),
+            _ => ()
+        }
+    }
+}
+
+fn correct<T: PartialEq + Debug + Copy>(name: &str, field: &mut T, value: Option<T>, corrections: &mut Vec<String>) {
+    if let Some(value) = value {
+        if *field != value {
+            corrections.push(format!("Database corrected {} from {:?} to {:?}", name, field, value));
+            *field = value;
+        }
+    }
+}
+
+// NstDatabase can list several RAM chips for one board, their sizes add up
+fn add_size(total: Option<usize>, size: Option<usize>) -> Option<usize> {
+    match (total, size) {
+        (Some(total), Some(size)) => Some(total + size),
+        (total, size) => total.or(size)
+    }
+}
+
+// A single XML tag with its attributes, which is all the databases need
+struct Tag<'a> {
+    name: &'a str,
+    closing: bool,
+    attributes: Vec<(&'a str, &'a str)>
+}
+
+impl<'a> Tag<'a> {
+    fn attribute(&self, name: &str) -> Option<&'a str> {
+        self.attributes.iter().find(|&&(key, _)| key == name).map(|&(_, value)| value)
+    }
+
+    fn number(&self, name: &str) -> Option<usize> {
+        self.attribute(name).and_then(|value| value.parse().ok())
+    }
+
+    // Sizes are given in bytes, or in kilobytes when suffixed with a k as NstDatabase does
+    fn size(&self, name: &str) -> Option<usize> {
+        self.attribute(name).and_then(|value| match value.strip_suffix('k') {
+            Some(kilobytes) => kilobytes.parse::<usize>().ok().map(|size| size * 1024),
+            None => value.parse().ok()
+        })
+    }
+}
+
+// Scans through a document tag by tag, skipping over text, comments and declarations
+struct Tags<'a> {
+    xml: &'a str
+}
+
+impl<'a> Tags<'a> {
+    fn new(xml: &'a str) -> Tags<'a> {
+        Tags { xml }
+    }
+}
+
+impl<'a> Iterator for Tags<'a> {
+    type Item = Tag<'a>;
+
+    fn next(&mut self) -> Option<Tag<'a>> {
+        loop {
+            let start = self.xml.find('<')?;
+            let end = start + self.xml[start..].find('>')?;
+            let content = &self.xml[start + 1..end];
+            self.xml = &self.xml[end + 1..];
+
+            if content.starts_with('?') || content.starts_with('!') {
+                continue;
+            }
+
+            let closing = content.starts_with('/');
+            let content = content.trim_start_matches('/').trim_end_matches('/');
+            let name_end = content.find(char::is_whitespace).unwrap_or(content.len());
+
+            return Some(Tag { name: &content[..name_end], closing, attributes: attributes(&content[name_end..]) });
+        }
+    }
+}
+
+// Parses name="value" pairs, values can be quoted with either ' or "
+fn attributes(mut content: &str) -> Vec<(&str, &str)> {
+    let mut attributes = vec![];
+
+    while let Some(equals) = content.find('=') {
+        let name = content[..equals].trim();
+        let rest = content[equals + 1..].trim_start();
+        let quote = match rest.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => quote,
+            _ => break
+        };
+        let value_end = match rest[1..].find(quote) {
+            Some(value_end) => value_end + 1,
+            None => break
+        };
+
+        attributes.push((name, &rest[1..value_end]));
+        content = &rest[value_end + 1..];
+    }
+
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes::rom::RomBuilder;
+
+    const PRG_ROM: [u8; 4] = [0xA9, 0x00, 0x8D, 0x00];
+    const CHR_ROM: [u8; 2] = [0xFF, 0x00];
+
+    fn data() -> Vec<u8> {
+        [&PRG_ROM[..], &CHR_ROM[..]].concat()
+    }
+
+    // An iNES header for NROM with horizontal mirroring and the usual 8KB of PRG-RAM
+    fn header() -> Header {
+        RomBuilder::new(0).ines().build().header
+    }
+
+    #[test]
+    fn nes20db_lookup_by_crc32() {
+        let xml = format!(r#"<?xml version="1.0"?>
+            <nes20db>
+            <game>
+              <!-- Made up -->
+              <prgrom size="4" crc32="00000000"/>
+              <rom size="6" crc32="{:08X}"/>
+              <pcb mapper="1" submapper="5" mirroring="V" battery="1"/>
+              <prgnvram size="8192"/>
+              <console type="0" region="1"/>
+            </game>
+            </nes20db>"#, crc32(&data()));
+
+        let database = Database::parse(&xml);
+        assert_eq!(database.len(), 1);
+
+        let entry = database.find(&PRG_ROM, &CHR_ROM).unwrap();
+        assert_eq!(entry.mapper, Some(1));
+        assert_eq!(entry.submapper, Some(5));
+        assert_eq!(entry.screen_mode, Some(ScreenMode::Vertical));
+        assert_eq!(entry.region, Some(Region::PAL));
+        assert_eq!(entry.battery, Some(true));
+        assert_eq!(entry.prg_nvram_size, Some(8192));
+        assert_eq!(entry.prg_ram_size, None);
+
+        assert!(database.find(&PRG_ROM, &[]).is_none());
+    }
+
+    #[test]
+    fn sha1_is_preferred_over_crc32() {
+        let xml = format!(r#"<nes20db>
+            <game><rom crc32="{:08X}"/><pcb mapper="2"/></game>
+            <game><rom crc32="12345678" sha1="{}"/><pcb mapper="3"/></game>
+            </nes20db>"#, crc32(&data()), sha1(&data()).to_uppercase());
+
+        let database = Database::parse(&xml);
+        assert_eq!(database.find(&PRG_ROM, &CHR_ROM).unwrap().mapper, Some(3));
+    }
+
+    #[test]
+    fn nstdatabase_lookup() {
+        let xml = format!(r#"<database version="1.0">
+            <game>
+              <cartridge system="NES-PAL" crc="{:08X}" sha1="SHA1:{}" dump="ok">
+                <board type="NES-SOROM" mapper="1">
+                  <prg size="256k"/>
+                  <wram size="8k"/>
+                  <wram size="8k" battery="1"/>
+                  <vram size="8k"/>
+                  <pad h="1" v="0"/>
+                </board>
+              </cartridge>
+            </game>
+            </database>"#, crc32(&data()), sha1(&data()).to_uppercase());
+
+        let database = Database::parse(&xml);
+        let entry = database.find(&PRG_ROM, &CHR_ROM).unwrap();
+        assert_eq!(entry.mapper, Some(1));
+        assert_eq!(entry.region, Some(Region::PAL));
+        assert_eq!(entry.screen_mode, Some(ScreenMode::Vertical));
+        assert_eq!(entry.battery, Some(true));
+        assert_eq!(entry.prg_ram_size, Some(8192));
+        assert_eq!(entry.prg_nvram_size, Some(8192));
+        assert_eq!(entry.chr_ram_size, Some(8192));
+    }
+
+    #[test]
+    fn corrects_headers() {
+        let entry = Entry {
+            mapper: Some(4),
+            screen_mode: Some(ScreenMode::Horizontal),
+            battery: Some(true),
+            prg_ram_size: Some(0),
+            prg_nvram_size: Some(8192),
+            ..Entry::default()
+        };
+
+        let mut header = header();
+        let corrections = entry.apply(&mut header);
+        assert_eq!(header.mapper, 4);
+        assert_eq!(header.screen_mode, ScreenMode::Horizontal);
+        assert!(header.battery);
+        assert_eq!(header.prg_ram_size, 0);
+        assert_eq!(header.prg_nvram_size, 8192);
+
+        // Values that were already right aren't mentioned
+        assert_eq!(corrections, vec![
+            "Database corrected mapper from 0 to 4",
+            "Database corrected battery from false to true",
+            "Database corrected PRG-RAM size from 8192 to 0",
+            "Database corrected PRG-NVRAM size from 0 to 8192"
+        ]);
+    }
+}
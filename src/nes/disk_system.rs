@@ -0,0 +1,501 @@
+use cpu::Address;
+use cpu::memory::Memory;
+use nes::fds::Disk;
+use nes::mapper::Mapper;
+use nes::rom::ScreenMode;
+
+const PRG_RAM_LENGTH: usize = 0x8000;
+const CHR_RAM_LENGTH: usize = 0x2000;
+
+// The drive transfers a byte about every 150 CPU cycles (96.4 kbit/s), and takes a while to get the head back to the
+// start of the disk once it reached the end
+const BYTE_CYCLES: u32 = 150;
+const REWIND_CYCLES: u32 = 50000;
+
+// Structure for the FDS control register at $4025
+// Ref: https://wiki.nesdev.com/w/index.php/Family_Computer_Disk_System#FDS_control_.28.244025.29
+bitflags! {
+    struct Control: u8 {
+        const MOTOR_ON =        0b0000_0001;
+        const TRANSFER_RESET =  0b0000_0010;
+        const READ_MODE =       0b0000_0100;
+        const HORIZONTAL =      0b0000_1000;
+        const CRC_CONTROL =     0b0001_0000;
+        const TRANSFER_START =  0b0100_0000;
+        const DISK_IRQ =        0b1000_0000;
+    }
+}
+
+// The Famicom Disk System RAM adapter, which plugs into the cartridge slot and connects the disk drive
+// $4020-$4026  Timer IRQ, disk drive and I/O registers (write)
+// $4030-$4033  Disk drive and I/O status registers (read)
+// $6000-$DFFF  32KB PRG-RAM, where the BIOS loads the files from the disk
+// $E000-$FFFF  8KB BIOS ROM
+// The PPU side only has 8KB of CHR-RAM, the sound registers at $4040-$4097 are ignored as the APU doesn't have the
+// FDS wavetable channel.
+// The drive works on the disk as a stream of bytes, with the gaps and block marks that are on the actual disk. A byte
+// is transferred every BYTE_CYCLES while the motor is on, raising an IRQ when the BIOS asked for one.
+// Ref: https://wiki.nesdev.com/w/index.php/Family_Computer_Disk_System
+pub struct DiskSystem {
+    bios: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr_ram: Vec<u8>,
+
+    sides: Vec<Vec<u8>>,
+    side: Option<usize>,                // The inserted side, if any
+
+    disk_registers_enabled: bool,
+    control: Control,
+    timer_reload: u16,
+    timer_counter: u16,
+    timer_enabled: bool,
+    timer_repeat: bool,
+    timer_irq: bool,
+
+    disk_irq: bool,
+    read_data: u8,
+    write_data: u8,
+    transfer_complete: bool,            // Set every time a byte was transferred, until it's read or written
+    position: usize,                    // Position of the head on the current side
+    delay: u32,                         // Cycles until the next byte passes the head
+    end_of_head: bool,                  // Set when the head reached the end of the disk, until it's back at the start
+    scanning: bool,                     // Whether the head is moving over the disk
+    gap_ended: bool,                    // Whether the block start mark has passed the head since the transfer started
+    external: u8                        // Output of the expansion port at $4026
+}
+
+impl DiskSystem {
+    // The first side is inserted, as if the disk was put in the drive before turning on the system
+    pub fn new(disk: &Disk, bios: Vec<u8>) -> DiskSystem {
+        let sides = disk.gapped_sides();
+        let side = if sides.is_empty() { None } else { Some(0) };
+
+        DiskSystem {
+            bios,
+            prg_ram: vec![0; PRG_RAM_LENGTH],
+            chr_ram: vec![0; CHR_RAM_LENGTH],
+            sides,
+            side,
+            disk_registers_enabled: true,
+            control: Control::empty(),
+            timer_reload: 0,
+            timer_counter: 0,
+            timer_enabled: false,
+            timer_repeat: false,
+            timer_irq: false,
+            disk_irq: false,
+            read_data: 0,
+            write_data: 0,
+            transfer_complete: false,
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            external: 0
+        }
+    }
+
+    pub fn side_count(&self) -> usize {
+        self.sides.len()
+    }
+
+    pub fn side(&self) -> Option<usize> {
+        self.side
+    }
+
+    // Puts a side in the drive, games wait for the disk to be ejected before asking for another side
+    pub fn insert(&mut self, side: usize) {
+        if side < self.sides.len() {
+            self.side = Some(side);
+            self.end_of_head = true;
+        }
+    }
+
+    pub fn eject(&mut self) {
+        self.side = None;
+        self.scanning = false;
+    }
+
+    // The sides as they are now, including anything the game wrote to them
+    pub fn sides(&self) -> &[Vec<u8>] {
+        &self.sides
+    }
+
+    fn read_register(&mut self, address: Address) -> u8 {
+        match address {
+            // Disk status, reading it acknowledges both IRQs
+            0x4030 => {
+                let mut value = 0;
+                if self.timer_irq { value |= 0b0000_0001; }
+                if self.transfer_complete { value |= 0b0000_0010; }
+                if self.end_of_head { value |= 0b0100_0000; }
+
+                self.transfer_complete = false;
+                self.timer_irq = false;
+                self.disk_irq = false;
+                value
+            }
+
+            // Read data, which acknowledges the disk IRQ
+            0x4031 => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+                self.read_data
+            }
+
+            // Drive status: no disk, not ready and write protected, a drive without a disk reports all three
+            0x4032 => {
+                let mut value = 0b0100_0000;
+                if self.side.is_none() { value |= 0b0000_0101; }
+                if self.side.is_none() || !self.scanning { value |= 0b0000_0010; }
+                value
+            }
+
+            // Expansion port input, bit 7 reports the battery is good
+            0x4033 => 0b1000_0000 | (self.external & 0x7F),
+
+            _ => 0
+        }
+    }
+
+    fn write_register(&mut self, address: Address, value: u8) {
+        if !self.disk_registers_enabled && address != 0x4023 {
+            return;
+        }
+
+        match address {
+            0x4020 => self.timer_reload = (self.timer_reload & 0xFF00) | value as u16,
+            0x4021 => self.timer_reload = (self.timer_reload & 0x00FF) | ((value as u16) << 8),
+
+            // Timer IRQ control, enabling the timer reloads the counter and disabling it acknowledges the IRQ
+            0x4022 => {
+                self.timer_repeat = value & 0b0000_0001 != 0;
+                self.timer_enabled = value & 0b0000_0010 != 0;
+                if self.timer_enabled {
+                    self.timer_counter = self.timer_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+
+            // Master I/O enable, bit 1 enables the sound registers which aren't emulated
+            0x4023 => {
+                self.disk_registers_enabled = value & 0b0000_0001 != 0;
+                if !self.disk_registers_enabled {
+                    self.timer_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+
+            // Write data, which acknowledges the disk IRQ
+            0x4024 => {
+                self.write_data = value;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+
+            0x4025 => {
+                self.control = Control::from_bits_truncate(value);
+                self.disk_irq = false;
+            }
+
+            0x4026 => self.external = value,
+
+            _ => ()
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.timer_enabled {
+            return;
+        }
+
+        if self.timer_counter == 0 {
+            self.timer_irq = true;
+            self.timer_counter = self.timer_reload;
+            self.timer_enabled = self.timer_repeat;
+        } else {
+            self.timer_counter -= 1;
+        }
+    }
+
+    // Moves the disk along under the head, transferring a byte whenever one has passed
+    // Ref: https://wiki.nesdev.com/w/index.php/Family_Computer_Disk_System#Disk_transfer
+    fn clock_drive(&mut self) {
+        let side = match self.side {
+            Some(side) if self.control.contains(Control::MOTOR_ON) => side,
+            _ => {
+                self.end_of_head = true;
+                self.scanning = false;
+                return;
+            }
+        };
+
+        // The transfer reset bit holds the head at the start of the disk
+        if self.control.contains(Control::TRANSFER_RESET) && !self.scanning {
+            return;
+        }
+
+        if self.end_of_head {
+            self.end_of_head = false;
+            self.position = 0;
+            self.delay = REWIND_CYCLES;
+            self.gap_ended = false;
+            return;
+        }
+
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let irq = self.control.contains(Control::DISK_IRQ);
+
+        if self.control.contains(Control::READ_MODE) {
+            let value = self.sides[side][self.position];
+
+            // Bytes are only handed to the BIOS once it's waiting for a block and the start mark has passed
+            if !self.control.contains(Control::TRANSFER_START) {
+                self.gap_ended = false;
+            } else if value != 0 && !self.gap_ended {
+                self.gap_ended = true;
+            } else if self.gap_ended {
+                self.read_data = value;
+                self.transfer_complete = true;
+                self.disk_irq |= irq;
+            }
+        } else {
+            // Nothing is written while the BIOS is still writing the gap before a block
+            // The CRC isn't calculated, so when the BIOS asks for it the bytes on the disk are left alone
+            let value = if self.control.contains(Control::TRANSFER_START) { self.write_data } else { 0 };
+            if !self.control.contains(Control::CRC_CONTROL) {
+                self.sides[side][self.position] = value;
+            }
+            self.transfer_complete = true;
+            self.disk_irq |= irq;
+            self.gap_ended = false;
+        }
+
+        self.position += 1;
+        if self.position >= self.sides[side].len() {
+            self.control.remove(Control::MOTOR_ON);
+            self.end_of_head = true;
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+}
+
+impl Memory for DiskSystem {
+    fn read(&mut self, address: Address) -> u8 {
+        match address {
+            0x4030..=0x4033 if self.disk_registers_enabled => self.read_register(address),
+            _ => self.peek(address)
+        }
+    }
+
+    fn write(&mut self, address: Address, value: u8) {
+        match address {
+            0x4020..=0x4026 => self.write_register(address, value),
+            0x6000..=0xDFFF => self.prg_ram[(address - 0x6000) as usize] = value,
+            _ => ()
+        }
+    }
+
+    fn peek(&self, address: Address) -> u8 {
+        match address {
+            0x6000..=0xDFFF => self.prg_ram[(address - 0x6000) as usize],
+            0xE000..=0xFFFF => self.bios.get((address - 0xE000) as usize).cloned().unwrap_or(0),
+            _ => 0
+        }
+    }
+
+    fn clock(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            self.clock_timer();
+            self.clock_drive();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+}
+
+impl Mapper for DiskSystem {
+    fn read_chr(&mut self, address: Address) -> u8 {
+        self.chr_ram[(address as usize) & (CHR_RAM_LENGTH - 1)]
+    }
+
+    fn write_chr(&mut self, address: Address, value: u8) {
+        self.chr_ram[(address as usize) & (CHR_RAM_LENGTH - 1)] = value;
+    }
+
+    // Nametable mirroring is controlled through $4025
+    fn screen_mode(&self) -> ScreenMode {
+        if self.control.contains(Control::HORIZONTAL) {
+            ScreenMode::Horizontal
+        } else {
+            ScreenMode::Vertical
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes::fds::{parse_fds, BIOS_LENGTH, SIDE_LENGTH};
+
+    // A disk with the given amount of empty sides, which only hold their disk info and file amount blocks
+    fn disk_system(sides: usize) -> DiskSystem {
+        let mut side = b"\x01*NINTENDO-HVC*".to_vec();
+        side.resize(56, 0);
+        side.extend_from_slice(&[2, 0]);
+        side.resize(SIDE_LENGTH, 0);
+
+        let disk = parse_fds(&side.repeat(sides)).unwrap();
+        let mut bios = vec![0; BIOS_LENGTH];
+        bios[0x1FFC] = 0x24;
+        DiskSystem::new(&disk, bios)
+    }
+
+    // Clocks the drive until it raises an IRQ for the next byte, which is then read like the BIOS does
+    fn next_byte(system: &mut DiskSystem) -> u8 {
+        for _ in 0..1_000_000 {
+            system.clock(1);
+            if system.irq() {
+                return system.read(0x4031);
+            }
+        }
+        panic!("The drive didn't transfer a byte");
+    }
+
+    #[test]
+    fn memory() {
+        let mut system = disk_system(1);
+        system.write(0x6000, 0x12);
+        system.write(0xDFFF, 0x34);
+        system.write(0xFFFC, 0x56);
+        assert_eq!((system.read(0x6000), system.read(0xDFFF)), (0x12, 0x34));
+        assert_eq!(system.read(0xFFFC), 0x24);
+
+        system.write_chr(0x1FFF, 0x78);
+        assert_eq!(system.read_chr(0x1FFF), 0x78);
+
+        assert_eq!(system.screen_mode(), ScreenMode::Vertical);
+        system.write(0x4025, Control::HORIZONTAL.bits());
+        assert_eq!(system.screen_mode(), ScreenMode::Horizontal);
+    }
+
+    #[test]
+    fn timer_irq() {
+        let mut system = disk_system(1);
+        system.write(0x4020, 10);
+        system.write(0x4021, 0);
+        system.write(0x4022, 0b10);
+
+        // The counter counts down to 0 and fires on the next cycle
+        system.clock(10);
+        assert!(!system.irq());
+        system.clock(1);
+        assert!(system.irq());
+        assert_eq!(system.read(0x4030) & 0b1, 0b1);
+        assert!(!system.irq());
+
+        // Without repeat the timer stops after firing once
+        system.clock(100);
+        assert!(!system.irq());
+
+        // With repeat it keeps firing every reload + 1 cycles, until disabling it acknowledges the IRQ
+        system.write(0x4022, 0b11);
+        system.clock(11);
+        assert!(system.irq());
+        system.read(0x4030);
+        system.clock(11);
+        assert!(system.irq());
+        system.write(0x4022, 0);
+        assert!(!system.irq());
+        system.clock(100);
+        assert!(!system.irq());
+    }
+
+    #[test]
+    fn disabling_disk_registers() {
+        let mut system = disk_system(1);
+        system.write(0x4022, 0b11);
+        system.clock(1);
+        assert!(system.irq());
+
+        // Disabling the disk registers stops the timer, and its registers can't be written until they're enabled again
+        system.write(0x4023, 0);
+        assert!(!system.irq());
+        system.write(0x4022, 0b11);
+        system.clock(10);
+        assert!(!system.irq());
+
+        system.write(0x4023, 1);
+        system.write(0x4022, 0b11);
+        system.clock(1);
+        assert!(system.irq());
+    }
+
+    #[test]
+    fn reading_the_disk() {
+        let mut system = disk_system(1);
+        assert_eq!(system.read(0x4032) & 0b111, 0b010);     // Disk inserted, but not ready
+
+        // The BIOS waits for the block start mark after the lead-in gap, the first byte it gets is the block code
+        system.write(0x4025, (Control::MOTOR_ON | Control::READ_MODE | Control::TRANSFER_START | Control::DISK_IRQ).bits());
+        assert_eq!(next_byte(&mut system), 0x01);
+        assert_eq!(system.read(0x4032) & 0b111, 0b000);     // Ready
+        assert_eq!(system.read(0x4030) & 0b0100_0000, 0);   // Not at the end of the disk
+
+        let verification: Vec<u8> = (0..14).map(|_| next_byte(&mut system)).collect();
+        assert_eq!(verification, b"*NINTENDO-HVC*");
+
+        // Without an IRQ the transfer can still be polled through $4030
+        system.write(0x4025, (Control::MOTOR_ON | Control::READ_MODE | Control::TRANSFER_START).bits());
+        system.clock(200);
+        assert!(!system.irq());
+        assert_eq!(system.read(0x4030) & 0b10, 0b10);
+    }
+
+    #[test]
+    fn writing_the_disk() {
+        let mut system = disk_system(1);
+        system.write(0x4024, 0x42);
+        system.write(0x4025, (Control::MOTOR_ON | Control::TRANSFER_START | Control::DISK_IRQ).bits());
+        next_byte(&mut system);
+        assert_eq!(system.sides()[0][0], 0x42);
+
+        // While the CRC is being written the disk is left alone
+        system.write(0x4024, 0x43);
+        system.write(0x4025, (Control::MOTOR_ON | Control::TRANSFER_START | Control::CRC_CONTROL | Control::DISK_IRQ).bits());
+        next_byte(&mut system);
+        assert_eq!(system.sides()[0][1], 0);
+    }
+
+    #[test]
+    fn switching_sides() {
+        let mut system = disk_system(2);
+        assert_eq!((system.side_count(), system.side()), (2, Some(0)));
+
+        system.eject();
+        assert_eq!(system.side(), None);
+        assert_eq!(system.read(0x4032) & 0b111, 0b111);     // No disk, not ready and write protected
+
+        system.insert(1);
+        assert_eq!(system.side(), Some(1));
+        system.insert(2);
+        assert_eq!(system.side(), Some(1));
+
+        // Without a disk the motor doesn't get anywhere
+        system.eject();
+        system.write(0x4025, (Control::MOTOR_ON | Control::READ_MODE | Control::TRANSFER_START | Control::DISK_IRQ).bits());
+        system.clock(255);
+        assert_eq!(system.read(0x4030) & 0b0100_0000, 0b0100_0000);
+    }
+}
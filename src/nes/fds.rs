@@ -0,0 +1,476 @@
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use nes::patch;
+use nes::patch::PatchError;
+
+pub const FDS_MAGIC: &[u8] = b"FDS\x1a";
+pub const FDS_HEADER_LENGTH: usize = 16;
+pub const SIDE_LENGTH: usize = 65500;
+pub const BIOS_LENGTH: usize = 8192;
+
+// Every side starts with the disk info block, which is its block code followed by this verification string
+const DISK_INFO_MAGIC: &[u8] = b"\x01*NINTENDO-HVC*";
+const DISK_INFO_LENGTH: usize = 56;
+const FILE_AMOUNT_LENGTH: usize = 2;
+const FILE_HEADER_LENGTH: usize = 16;
+
+// The kinds of blocks that follow the disk info block, each block starts with its code
+// Ref: https://wiki.nesdev.com/w/index.php/FDS_disk_format
+const FILE_AMOUNT_BLOCK: u8 = 2;
+const FILE_HEADER_BLOCK: u8 = 3;
+const FILE_DATA_BLOCK: u8 = 4;
+
+#[derive(Debug)]
+pub enum FdsError {
+    BadMagic,                                                       // Neither an fwNES header nor a disk info block
+    NoSides,                                                        // The image doesn't contain a single side
+    BadDiskInfo(usize),                                             // The side doesn't start with a disk info block
+    BadBlock { side: usize, offset: usize, expected: u8, actual: u8 },
+    TruncatedFile { side: usize, file: usize },
+    BadBios(usize),                                                 // The BIOS isn't 8KB
+    MissingBios(String),
+    Patch(PatchError),
+    Io(io::Error)
+}
+
+impl fmt::Display for FdsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FdsError::BadMagic                                      => write!(f, "Not an FDS disk image"),
+            FdsError::NoSides                                       => write!(f, "Disk image doesn't contain any sides"),
+            FdsError::BadDiskInfo(side)                             => write!(f, "Side {} doesn't start with a disk info block", side),
+            FdsError::BadBlock { side, offset, expected, actual }   => write!(f, "Side {} should have block {} at offset {}, but found {}", side, expected, offset, actual),
+            FdsError::TruncatedFile { side, file }                  => write!(f, "File {} on side {} runs past the end of the side", file, side),
+            FdsError::BadBios(length)                               => write!(f, "BIOS should be {} bytes, but it is {} bytes", BIOS_LENGTH, length),
+            FdsError::MissingBios(ref reason)                       => write!(f, "Failed to read the FDS BIOS: {}", reason),
+            FdsError::Patch(ref err)                                => write!(f, "{}", err),
+            FdsError::Io(ref err)                                   => write!(f, "{}", err)
+        }
+    }
+}
+
+impl Error for FdsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            FdsError::Patch(ref err) => Some(err),
+            FdsError::Io(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<PatchError> for FdsError {
+    fn from(err: PatchError) -> FdsError {
+        FdsError::Patch(err)
+    }
+}
+
+impl From<io::Error> for FdsError {
+    fn from(err: io::Error) -> FdsError {
+        FdsError::Io(err)
+    }
+}
+
+// A Famicom Disk System image, which holds one or more disk sides
+pub struct Disk {
+    pub header: bool,               // Whether the image had an fwNES header
+    pub sides: Vec<Side>,
+    pub warnings: Vec<String>
+}
+
+// A single side of a disk, the raw data is kept around since that's what the disk drive reads
+pub struct Side {
+    pub data: Vec<u8>,
+    pub info: DiskInfo,
+    pub file_amount: u8,            // Files beyond this amount are hidden, they're only loaded by the game itself
+    pub files: Vec<DiskFile>
+}
+
+// The disk info block, which describes the game and which files are loaded at boot
+// Ref: https://wiki.nesdev.com/w/index.php/FDS_disk_format#Disk_info_block_.28block_1.29
+pub struct DiskInfo {
+    pub manufacturer: u8,
+    pub game_name: String,
+    pub game_type: u8,
+    pub revision: u8,
+    pub side_number: u8,            // 0 for side A, 1 for side B
+    pub disk_number: u8,
+    pub boot_file: u8               // Every file with an ID up to this one is loaded by the BIOS at boot
+}
+
+// A file header block and the file data block that follows it
+// Ref: https://wiki.nesdev.com/w/index.php/FDS_disk_format#File_header_block_.28block_3.29
+pub struct DiskFile {
+    pub number: u8,
+    pub id: u8,
+    pub name: String,
+    pub address: u16,               // Where the BIOS loads the file to, in CPU or PPU memory depending on the kind
+    pub kind: FileKind,
+    pub data: Vec<u8>
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum FileKind {
+    PRG,
+    CHR,
+    Nametable,
+    Unknown(u8)
+}
+
+impl From<u8> for FileKind {
+    fn from(value: u8) -> FileKind {
+        match value {
+            0 => FileKind::PRG,
+            1 => FileKind::CHR,
+            2 => FileKind::Nametable,
+            _ => FileKind::Unknown(value)
+        }
+    }
+}
+
+// Whether a file looks like a disk image, with or without the fwNES header
+pub fn is_disk(buf: &[u8]) -> bool {
+    buf.starts_with(FDS_MAGIC) || buf.starts_with(DISK_INFO_MAGIC)
+}
+
+pub fn load(buf: &[u8]) -> Result<Disk, FdsError> {
+    load_with_patch(buf, None)
+}
+
+// Loads a disk image after applying an IPS, UPS or BPS patch to the file
+// Archives have to be extracted beforehand, see archive::extract
+pub fn load_with_patch(buf: &[u8], patch: Option<&[u8]>) -> Result<Disk, FdsError> {
+    let buf = match patch {
+        Some(patch) => Cow::Owned(patch::apply(buf, patch)?),
+        None => Cow::Borrowed(buf)
+    };
+
+    parse_fds(&buf)
+}
+
+// Disk images are a list of 65500 byte sides, optionally preceded by the 16 byte fwNES header, which holds the amount
+// of sides. The sides are stored without the gaps and CRCs that are on the actual disk.
+// Ref: https://wiki.nesdev.com/w/index.php/FDS_file_format
+pub fn parse_fds(buf: &[u8]) -> Result<Disk, FdsError> {
+    let mut warnings = vec![];
+
+    let (header, data, header_sides) = if buf.starts_with(FDS_MAGIC) {
+        let sides = buf.get(4).cloned().unwrap_or(0) as usize;
+        (true, buf.get(FDS_HEADER_LENGTH..).unwrap_or(&[]), Some(sides))
+    } else if buf.starts_with(DISK_INFO_MAGIC) {
+        (false, buf, None)
+    } else {
+        return Err(FdsError::BadMagic);
+    };
+
+    // Trailing zeroes are often trimmed from the last side, the missing part is simply unused space on the disk
+    let mut chunks: Vec<Vec<u8>> = data.chunks(SIDE_LENGTH).map(|chunk| chunk.to_vec()).collect();
+    let count = chunks.len();
+    if let Some(last) = chunks.last_mut() {
+        if last.len() < SIDE_LENGTH {
+            warnings.push(format!("Side {} is {} bytes short, it was padded with zeroes", count - 1, SIDE_LENGTH - last.len()));
+            last.resize(SIDE_LENGTH, 0);
+        }
+    }
+
+    if let Some(header_sides) = header_sides {
+        if header_sides != chunks.len() {
+            warnings.push(format!("Header says the disk has {} sides, but the image holds {}", header_sides, chunks.len()));
+        }
+    }
+    if chunks.is_empty() {
+        return Err(FdsError::NoSides);
+    }
+
+    let sides = chunks.into_iter()
+        .enumerate()
+        .map(|(number, data)| parse_side(number, data))
+        .collect::<Result<Vec<Side>, FdsError>>()?;
+
+    Ok(Disk { header, sides, warnings })
+}
+
+// A side holds a disk info block and a file amount block, followed by a file header and a file data block per file
+fn parse_side(number: usize, data: Vec<u8>) -> Result<Side, FdsError> {
+    if !data.starts_with(DISK_INFO_MAGIC) {
+        return Err(FdsError::BadDiskInfo(number));
+    }
+
+    let info = DiskInfo {
+        manufacturer: data[15],
+        game_name: String::from_utf8_lossy(&data[16..19]).into_owned(),
+        game_type: data[19],
+        revision: data[20],
+        side_number: data[21],
+        disk_number: data[22],
+        boot_file: data[25]
+    };
+
+    let mut offset = DISK_INFO_LENGTH;
+    expect_block(&data, number, offset, FILE_AMOUNT_BLOCK)?;
+    let file_amount = data[offset + 1];
+    offset += FILE_AMOUNT_LENGTH;
+
+    // The file amount can't be trusted, some games hide files from the BIOS by setting it too low
+    let mut files = vec![];
+    while offset + FILE_HEADER_LENGTH <= data.len() && data[offset] == FILE_HEADER_BLOCK {
+        let header = &data[offset..offset + FILE_HEADER_LENGTH];
+        let size = LittleEndian::read_u16(&header[13..]) as usize;
+        offset += FILE_HEADER_LENGTH;
+
+        expect_block(&data, number, offset, FILE_DATA_BLOCK)?;
+        let file_data = data.get(offset + 1..offset + 1 + size).ok_or(FdsError::TruncatedFile { side: number, file: files.len() })?;
+        offset += 1 + size;
+
+        files.push(DiskFile {
+            number: header[1],
+            id: header[2],
+            name: String::from_utf8_lossy(&header[3..11]).into_owned(),
+            address: LittleEndian::read_u16(&header[11..]),
+            kind: FileKind::from(header[15]),
+            data: file_data.to_vec()
+        });
+    }
+
+    Ok(Side { data, info, file_amount, files })
+}
+
+fn expect_block(data: &[u8], side: usize, offset: usize, expected: u8) -> Result<(), FdsError> {
+    match data.get(offset) {
+        Some(&actual) if actual == expected => Ok(()),
+        actual => Err(FdsError::BadBlock { side, offset, expected, actual: actual.cloned().unwrap_or(0) })
+    }
+}
+
+// The BIOS of the RAM adapter, which is copyrighted so it isn't distributed with Mudkip and has to be given
+pub fn load_bios(path: Option<&Path>) -> Result<Vec<u8>, FdsError> {
+    let path = path.ok_or_else(|| FdsError::MissingBios("no BIOS was given".to_string()))?;
+    let bios = fs::read(path).map_err(|err| FdsError::MissingBios(format!("{:?}: {}", path, err)))?;
+    if bios.len() != BIOS_LENGTH {
+        return Err(FdsError::BadBios(bios.len()));
+    }
+    Ok(bios)
+}
+
+impl Disk {
+    // The sides as they are on the disk, which is what the drive reads from and writes to
+    // A side starts with a lead-in gap of zeroes, after which every block starts with a $80 mark and ends with its
+    // CRC and another gap. The emulated drive never reports CRC errors, so the CRCs are simply left zero.
+    // Ref: https://wiki.nesdev.com/w/index.php/FDS_disk_format#Physical_format
+    pub fn gapped_sides(&self) -> Vec<Vec<u8>> {
+        self.sides.iter().map(|side| side.gapped()).collect()
+    }
+}
+
+impl Side {
+    fn gapped(&self) -> Vec<u8> {
+        const LEAD_IN_LENGTH: usize = 28300 / 8;
+        const GAP_LENGTH: usize = 976 / 8;
+        const BLOCK_START: u8 = 0x80;
+
+        let mut disk = vec![0; LEAD_IN_LENGTH];
+        let block = |disk: &mut Vec<u8>, data: &[u8]| {
+            disk.push(BLOCK_START);
+            disk.extend_from_slice(data);
+            disk.extend_from_slice(&[0, 0]);
+            disk.extend_from_slice(&[0; GAP_LENGTH]);
+        };
+
+        block(&mut disk, &self.data[..DISK_INFO_LENGTH]);
+        block(&mut disk, &self.data[DISK_INFO_LENGTH..DISK_INFO_LENGTH + FILE_AMOUNT_LENGTH]);
+
+        let mut offset = DISK_INFO_LENGTH + FILE_AMOUNT_LENGTH;
+        for file in &self.files {
+            block(&mut disk, &self.data[offset..offset + FILE_HEADER_LENGTH]);
+            offset += FILE_HEADER_LENGTH;
+            block(&mut disk, &self.data[offset..offset + 1 + file.data.len()]);
+            offset += 1 + file.data.len();
+        }
+
+        // The rest of the side is unused, but games can still write new files to it
+        let length = disk.len().max(SIDE_LENGTH + LEAD_IN_LENGTH);
+        disk.resize(length, 0);
+        disk
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    // Builds an unpadded side with a disk info block for "TST", the file amount and a header and data block per file
+    fn side(side_number: u8, file_amount: u8, files: &[(u8, &str, u8, &[u8])]) -> Vec<u8> {
+        let mut bytes = DISK_INFO_MAGIC.to_vec();
+        bytes.extend_from_slice(&[0xA4, b'T', b'S', b'T', 0x20, 1, side_number, 0, 0, 0, 1]);
+        bytes.resize(DISK_INFO_LENGTH, 0);
+        bytes.extend_from_slice(&[FILE_AMOUNT_BLOCK, file_amount]);
+
+        for (number, &(id, name, kind, data)) in files.iter().enumerate() {
+            let mut header = vec![FILE_HEADER_BLOCK, number as u8, id];
+            header.extend_from_slice(format!("{:<8}", name).as_bytes());
+            header.extend_from_slice(&[0; 4]);
+            LittleEndian::write_u16(&mut header[11..], 0x6000 + number as u16 * 0x100);
+            LittleEndian::write_u16(&mut header[13..], data.len() as u16);
+            header.push(kind);
+
+            bytes.extend_from_slice(&header);
+            bytes.push(FILE_DATA_BLOCK);
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    fn padded(mut side: Vec<u8>) -> Vec<u8> {
+        side.resize(SIDE_LENGTH, 0);
+        side
+    }
+
+    #[test]
+    fn fds_with_header() {
+        let mut bytes = FDS_MAGIC.to_vec();
+        bytes.push(2);
+        bytes.resize(FDS_HEADER_LENGTH, 0);
+        bytes.extend(padded(side(0, 2, &[(0, "KYODAKU-", 2, &[1; 224]), (1, "MAIN", 0, &[2, 3, 4])])));
+        bytes.extend(padded(side(1, 0, &[(5, "HIDDEN", 1, &[5; 16])])));
+
+        let disk = parse_fds(&bytes).unwrap();
+        assert!(disk.header);
+        assert!(disk.warnings.is_empty());
+        assert_eq!(disk.sides.len(), 2);
+
+        let side_a = &disk.sides[0];
+        assert_eq!(side_a.data.len(), SIDE_LENGTH);
+        assert_eq!((side_a.info.manufacturer, &side_a.info.game_name[..], side_a.info.game_type), (0xA4, "TST", 0x20));
+        assert_eq!((side_a.info.revision, side_a.info.side_number, side_a.info.disk_number, side_a.info.boot_file), (1, 0, 0, 1));
+        assert_eq!(side_a.file_amount, 2);
+        assert_eq!(side_a.files.len(), 2);
+        assert_eq!((&side_a.files[0].name[..], side_a.files[0].kind, side_a.files[0].data.len()), ("KYODAKU-", FileKind::Nametable, 224));
+        let main = &side_a.files[1];
+        assert_eq!((main.number, main.id, &main.name[..], main.address, main.kind), (1, 1, "MAIN    ", 0x6100, FileKind::PRG));
+        assert_eq!(main.data, [2, 3, 4]);
+
+        // Files past the file amount are still read, the game loads them itself
+        let side_b = &disk.sides[1];
+        assert_eq!((side_b.info.side_number, side_b.file_amount), (1, 0));
+        assert_eq!(side_b.files.len(), 1);
+        assert_eq!((side_b.files[0].id, side_b.files[0].kind, &side_b.files[0].data[..]), (5, FileKind::CHR, &[5; 16][..]));
+    }
+
+    #[test]
+    fn fds_without_header() {
+        // The last side is trimmed, which is padded again
+        let mut bytes = padded(side(0, 1, &[(0, "A", 0, &[1])]));
+        bytes.extend(side(1, 1, &[(0, "B", 3, &[2])]));
+
+        let disk = parse_fds(&bytes).unwrap();
+        assert!(!disk.header);
+        assert_eq!(disk.sides.len(), 2);
+        assert_eq!(disk.sides[1].data.len(), SIDE_LENGTH);
+        assert_eq!(disk.sides[1].files[0].kind, FileKind::Unknown(3));
+        assert_eq!(disk.warnings.len(), 1);
+        assert!(disk.warnings[0].starts_with("Side 1 is "));
+
+        // A header with the wrong amount of sides is only a warning
+        let mut bytes = FDS_MAGIC.to_vec();
+        bytes.push(3);
+        bytes.resize(FDS_HEADER_LENGTH, 0);
+        bytes.extend(padded(side(0, 0, &[])));
+        let disk = parse_fds(&bytes).unwrap();
+        assert_eq!(disk.warnings, vec!["Header says the disk has 3 sides, but the image holds 1".to_string()]);
+    }
+
+    #[test]
+    fn fds_errors() {
+        match parse_fds(b"NES\x1a") {
+            Err(FdsError::BadMagic) => (),
+            result => panic!("Expected bad magic, got {:?}", result.err())
+        }
+        match parse_fds(&[FDS_MAGIC, &[0; 12]].concat()) {
+            Err(FdsError::NoSides) => (),
+            result => panic!("Expected no sides, got {:?}", result.err())
+        }
+
+        let mut bytes = padded(side(0, 0, &[]));
+        bytes.extend(padded(vec![0xFF]));
+        match parse_fds(&bytes) {
+            Err(FdsError::BadDiskInfo(1)) => (),
+            result => panic!("Expected a bad disk info block, got {:?}", result.err())
+        }
+
+        // A file header that isn't followed by a file data block
+        let mut bytes = side(0, 1, &[(0, "A", 0, &[1])]);
+        let data_block = bytes.len() - 2;
+        bytes[data_block] = FILE_HEADER_BLOCK;
+        match parse_fds(&bytes) {
+            Err(FdsError::BadBlock { side: 0, offset, expected: FILE_DATA_BLOCK, actual: FILE_HEADER_BLOCK }) => assert_eq!(offset, data_block),
+            result => panic!("Expected a bad block, got {:?}", result.err())
+        }
+
+        // A file that's larger than what's left of the side
+        let mut bytes = side(0, 1, &[(0, "A", 0, &[1])]);
+        let size = DISK_INFO_LENGTH + FILE_AMOUNT_LENGTH + 13;
+        LittleEndian::write_u16(&mut bytes[size..], 0xFFFF);
+        match parse_fds(&bytes) {
+            Err(FdsError::TruncatedFile { side: 0, file: 0 }) => (),
+            result => panic!("Expected a truncated file, got {:?}", result.err())
+        }
+    }
+
+    #[test]
+    fn gapped_sides() {
+        let disk = parse_fds(&side(0, 1, &[(0, "A", 0, &[0x11, 0x22])])).unwrap();
+        let sides = disk.gapped_sides();
+        assert_eq!(sides.len(), 1);
+
+        let gapped = &sides[0];
+        let lead_in = 28300 / 8;
+        let gap = 976 / 8;
+        assert_eq!(gapped.len(), SIDE_LENGTH + lead_in);
+        assert!(gapped[..lead_in].iter().all(|&byte| byte == 0));
+
+        // Every block is preceded by the start mark and followed by a zeroed CRC and a gap
+        let mut offset = lead_in;
+        let raw = &disk.sides[0].data;
+        for &(start, length) in &[(0, DISK_INFO_LENGTH), (56, FILE_AMOUNT_LENGTH), (58, FILE_HEADER_LENGTH), (74, 3)] {
+            assert_eq!(gapped[offset], 0x80);
+            assert_eq!(&gapped[offset + 1..offset + 1 + length], &raw[start..start + length]);
+            offset += 1 + length;
+            assert!(gapped[offset..offset + 2 + gap].iter().all(|&byte| byte == 0));
+            offset += 2 + gap;
+        }
+        assert!(gapped[offset..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn load_bios_needs_an_8kb_bios() {
+        match load_bios(None) {
+            Err(FdsError::MissingBios(_)) => (),
+            result => panic!("Expected a missing BIOS, got {:?}", result.err())
+        }
+
+        let path = env::temp_dir().join(format!("mudkip-bios-{}.rom", std::process::id()));
+        fs::write(&path, vec![0; 0x1000]).unwrap();
+        let short = load_bios(Some(&path));
+        fs::write(&path, vec![0; BIOS_LENGTH]).unwrap();
+        let bios = load_bios(Some(&path));
+        fs::remove_file(&path).unwrap();
+
+        match short {
+            Err(FdsError::BadBios(0x1000)) => (),
+            result => panic!("Expected a bad BIOS, got {:?}", result.err())
+        }
+        assert_eq!(bios.unwrap().len(), BIOS_LENGTH);
+        match load_bios(Some(&path)) {
+            Err(FdsError::MissingBios(_)) => (),
+            result => panic!("Expected a missing BIOS, got {:?}", result.err())
+        }
+    }
+}
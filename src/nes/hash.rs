@@ -0,0 +1,25 @@
+use crc32fast::Hasher;
+use md5::Md5;
+use sha1::{Digest, Sha1};
+
+// Checksums used to identify dumps, as listed by the game databases and No-Intro
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+// MD5 in lowercase hexadecimal
+pub fn md5(data: &[u8]) -> String {
+    to_hex(&Md5::digest(data))
+}
+
+// SHA-1 in lowercase hexadecimal
+pub fn sha1(data: &[u8]) -> String {
+    to_hex(&Sha1::digest(data))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,309 @@
+use serde_json::Value;
+
+use nes::fds::{Disk, Side};
+use nes::hash::{crc32, md5, sha1};
+use nes::rom::{Format, ROM};
+
+// Names of the most common mappers, by iNES mapper number
+// Ref: https://wiki.nesdev.com/w/index.php/Mapper
+pub fn mapper_name(mapper: u16) -> Option<&'static str> {
+    match mapper {
+        0   => Some("NROM"),
+        1   => Some("MMC1"),
+        2   => Some("UxROM"),
+        3   => Some("CNROM"),
+        4   => Some("MMC3"),
+        5   => Some("MMC5"),
+        7   => Some("AxROM"),
+        9   => Some("MMC2"),
+        10  => Some("MMC4"),
+        11  => Some("Color Dreams"),
+        13  => Some("CPROM"),
+        16  => Some("Bandai FCG"),
+        18  => Some("Jaleco SS88006"),
+        19  => Some("Namco 163"),
+        21  => Some("VRC4a/VRC4c"),
+        22  => Some("VRC2a"),
+        23  => Some("VRC2b/VRC4e"),
+        24  => Some("VRC6a"),
+        25  => Some("VRC4b/VRC4d"),
+        26  => Some("VRC6b"),
+        34  => Some("BNROM/NINA-001"),
+        48  => Some("Taito TC0690"),
+        64  => Some("RAMBO-1"),
+        66  => Some("GxROM"),
+        69  => Some("Sunsoft FME-7"),
+        71  => Some("Camerica"),
+        85  => Some("VRC7"),
+        206 => Some("DxROM"),
+        _   => None
+    }
+}
+
+// Names of the submappers of the mappers above, submapper 0 is the default behaviour of the mapper
+// Ref: https://wiki.nesdev.com/w/index.php/NES_2.0_submappers
+pub fn submapper_name(mapper: u16, submapper: u8) -> Option<&'static str> {
+    match (mapper, submapper) {
+        (1, 5)              => Some("SEROM/SHROM/SH1ROM"),
+        (2, 1) | (3, 1) | (7, 1) => Some("No bus conflicts"),
+        (2, 2) | (3, 2) | (7, 2) => Some("Bus conflicts"),
+        (4, 1)              => Some("MMC6"),
+        (4, 3)              => Some("MC-ACC"),
+        (4, 4)              => Some("MMC3A"),
+        (16, 4)             => Some("FCG-1/FCG-2"),
+        (16, 5)             => Some("LZ93D50"),
+        (21, 1)             => Some("VRC4a"),
+        (21, 2)             => Some("VRC4c"),
+        (23, 1)             => Some("VRC4f"),
+        (23, 2)             => Some("VRC4e"),
+        (23, 3)             => Some("VRC2b"),
+        (25, 1)             => Some("VRC4b"),
+        (25, 2)             => Some("VRC4d"),
+        (25, 3)             => Some("VRC2c"),
+        (34, 1)             => Some("NINA-001"),
+        (34, 2)             => Some("BNROM"),
+        (71, 1)             => Some("Fire Hawk"),
+        _                   => None
+    }
+}
+
+// Describes a loaded ROM and the checksums of the file it was loaded from, after any patch was applied, as plain text
+pub fn text(file: &[u8], rom: &ROM) -> String {
+    let header = &rom.header;
+    let mut lines = vec![
+        format!("Format:           {}", format_name(header.format)),
+        format!("Board:            {}", header.board.as_ref().map_or("unknown", |board| board.as_str())),
+        format!("Mapper:           {} ({})", header.mapper, mapper_name(header.mapper).unwrap_or("unknown")),
+        match submapper_name(header.mapper, header.submapper) {
+            Some(name) => format!("Submapper:        {} ({})", header.submapper, name),
+            None => format!("Submapper:        {}", header.submapper)
+        },
+        format!("PRG-ROM:          {}", size_str(header.prg_size)),
+        format!("CHR-ROM:          {}", size_str(header.chr_size)),
+        format!("PRG-RAM:          {}", size_str(header.prg_ram_size)),
+        format!("PRG-NVRAM:        {}", size_str(header.prg_nvram_size)),
+        format!("CHR-RAM:          {}", size_str(header.chr_ram_size)),
+        format!("CHR-NVRAM:        {}", size_str(header.chr_nvram_size)),
+        format!("Mirroring:        {:?}", header.screen_mode),
+        format!("System:           {:?}", header.system),
+        format!("Region:           {:?}", header.region),
+        format!("Battery:          {}", yes_no(header.battery)),
+        format!("Trainer:          {}", yes_no(header.trainer))
+    ];
+
+    if let (Some(ppu), Some(hardware)) = (header.vs_ppu, header.vs_hardware) {
+        lines.push(format!("Vs. PPU:          {:?}", ppu));
+        lines.push(format!("Vs. hardware:     {:?}", hardware));
+    }
+    if header.format == Format::NES20 {
+        lines.push(format!("Misc. ROMs:       {}", header.misc_roms));
+        lines.push(format!("Expansion device: {}", header.expansion_device));
+    }
+
+    for &(name, data) in &[("File", file), ("PRG", &rom.prg_rom[..]), ("CHR", &rom.chr_rom[..])] {
+        lines.push(format!("{:<17} {:08x}", format!("{} CRC32:", name), crc32(data)));
+        lines.push(format!("{:<17} {}", format!("{} MD5:", name), md5(data)));
+        lines.push(format!("{:<17} {}", format!("{} SHA-1:", name), sha1(data)));
+    }
+
+    for warning in &rom.warnings {
+        lines.push(format!("Warning:          {}", warning));
+    }
+
+    lines.join("\n")
+}
+
+// The same information as text, as a JSON object
+pub fn json(file: &[u8], rom: &ROM) -> Value {
+    let header = &rom.header;
+
+    json!({
+        "format": format_name(header.format),
+        "board": header.board,
+        "mapper": header.mapper,
+        "mapper_name": mapper_name(header.mapper),
+        "submapper": header.submapper,
+        "submapper_name": submapper_name(header.mapper, header.submapper),
+        "prg_rom_size": header.prg_size,
+        "chr_rom_size": header.chr_size,
+        "prg_ram_size": header.prg_ram_size,
+        "prg_nvram_size": header.prg_nvram_size,
+        "chr_ram_size": header.chr_ram_size,
+        "chr_nvram_size": header.chr_nvram_size,
+        "mirroring": format!("{:?}", header.screen_mode),
+        "system": format!("{:?}", header.system),
+        "region": format!("{:?}", header.region),
+        "battery": header.battery,
+        "trainer": header.trainer,
+        "vs_ppu": header.vs_ppu.map(|ppu| format!("{:?}", ppu)),
+        "vs_hardware": header.vs_hardware.map(|hardware| format!("{:?}", hardware)),
+        "misc_roms": header.misc_roms,
+        "expansion_device": header.expansion_device,
+        "file": checksums(file),
+        "prg_rom": checksums(&rom.prg_rom),
+        "chr_rom": checksums(&rom.chr_rom),
+        "warnings": rom.warnings
+    })
+}
+
+// Describes an FDS disk image, every side with the files on it, and the checksums of the file it was loaded from
+pub fn disk_text(file: &[u8], disk: &Disk) -> String {
+    let mut lines = vec![
+        format!("Format:           FDS{}", if disk.header { " (fwNES header)" } else { "" }),
+        format!("Sides:            {}", disk.sides.len())
+    ];
+
+    for (number, side) in disk.sides.iter().enumerate() {
+        let info = &side.info;
+        lines.push(format!("Side {}:           {} disk {} side {}, revision {}, manufacturer ${:02X}", number, info.game_name,
+                           info.disk_number + 1, side_name(side), info.revision, info.manufacturer));
+        lines.push(format!("  Files:          {} ({} hidden), boot file ID {}", side.files.len(),
+                           side.files.len().saturating_sub(side.file_amount as usize), info.boot_file));
+        for file in &side.files {
+            lines.push(format!("  {:>3} {:<8} ID {:>3}  {:<9} ${:04X} {}", file.number, file.name, file.id,
+                               format!("{:?}", file.kind), file.address, size_str(file.data.len())));
+        }
+    }
+
+    lines.push(format!("{:<17} {:08x}", "File CRC32:", crc32(file)));
+    lines.push(format!("{:<17} {}", "File MD5:", md5(file)));
+    lines.push(format!("{:<17} {}", "File SHA-1:", sha1(file)));
+
+    for warning in &disk.warnings {
+        lines.push(format!("Warning:          {}", warning));
+    }
+
+    lines.join("\n")
+}
+
+// The same information as disk_text, as a JSON object
+pub fn disk_json(file: &[u8], disk: &Disk) -> Value {
+    let sides: Vec<Value> = disk.sides.iter().map(|side| {
+        let files: Vec<Value> = side.files.iter().map(|file| json!({
+            "number": file.number,
+            "id": file.id,
+            "name": file.name,
+            "kind": format!("{:?}", file.kind),
+            "address": file.address,
+            "size": file.data.len()
+        })).collect();
+
+        json!({
+            "game_name": side.info.game_name,
+            "manufacturer": side.info.manufacturer,
+            "game_type": side.info.game_type,
+            "revision": side.info.revision,
+            "disk_number": side.info.disk_number,
+            "side": side_name(side),
+            "boot_file": side.info.boot_file,
+            "file_amount": side.file_amount,
+            "files": files,
+            "checksums": checksums(&side.data)
+        })
+    }).collect();
+
+    json!({
+        "format": "FDS",
+        "fwnes_header": disk.header,
+        "sides": sides,
+        "file": checksums(file),
+        "warnings": disk.warnings
+    })
+}
+
+fn side_name(side: &Side) -> &'static str {
+    if side.info.side_number == 0 { "A" } else { "B" }
+}
+
+fn checksums(data: &[u8]) -> Value {
+    json!({
+        "crc32": format!("{:08x}", crc32(data)),
+        "md5": md5(data),
+        "sha1": sha1(data)
+    })
+}
+
+fn format_name(format: Format) -> &'static str {
+    match format {
+        Format::INES  => "iNES",
+        Format::NES20 => "NES 2.0",
+        Format::UNIF  => "UNIF"
+    }
+}
+
+fn size_str(size: usize) -> String {
+    if size >= 1024 && size.is_multiple_of(1024) {
+        format!("{} KB ({} bytes)", size / 1024, size)
+    } else {
+        format!("{} bytes", size)
+    }
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value { "yes" } else { "no" }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes::rom::RomBuilder;
+
+    // MMC6 with 1KB of battery-backed RAM
+    fn rom() -> (Vec<u8>, ROM) {
+        let mut rom = RomBuilder::new(4).prg_rom(2).prg_ram(0, 0x400).build();
+        rom.header.submapper = 1;
+        (rom.to_bytes(Format::NES20).unwrap(), rom)
+    }
+
+    #[test]
+    fn json_keys_and_values() {
+        let (file, rom) = rom();
+        assert_eq!(json(&file, &rom), json!({
+            "format": "NES 2.0",
+            "board": null,
+            "mapper": 4,
+            "mapper_name": "MMC3",
+            "submapper": 1,
+            "submapper_name": "MMC6",
+            "prg_rom_size": 32768,
+            "chr_rom_size": 8192,
+            "prg_ram_size": 0,
+            "prg_nvram_size": 1024,
+            "chr_ram_size": 0,
+            "chr_nvram_size": 0,
+            "mirroring": "Horizontal",
+            "system": "NES",
+            "region": "NTSC",
+            "battery": true,
+            "trainer": false,
+            "vs_ppu": null,
+            "vs_hardware": null,
+            "misc_roms": 0,
+            "expansion_device": 0,
+            "file": {
+                "crc32": "1375f2f7",
+                "md5": "fa56ac21878b90899844e614dbdd49c7",
+                "sha1": "5a4cf0aa632fba57c5560711d2813b285a4ec547"
+            },
+            "prg_rom": {
+                "crc32": "1263ede8",
+                "md5": "47290bef1c6855b5eb606694c6f30cc0",
+                "sha1": "ef8c0c5a00cc32d99c5900367e0228d08ffbcfe6"
+            },
+            "chr_rom": {
+                "crc32": "a9ddbbad",
+                "md5": "961e7d93dae49b076afe274fa4fb926b",
+                "sha1": "93e013399dd50453273d16e33efbca60fc07085e"
+            },
+            "warnings": []
+        }));
+    }
+
+    #[test]
+    fn submapper_names() {
+        let (file, rom) = rom();
+        assert!(text(&file, &rom).contains("Submapper:        1 (MMC6)\n"));
+        assert_eq!(submapper_name(4, 0), None);
+        assert_eq!(submapper_name(34, 2), Some("BNROM"));
+    }
+}
@@ -0,0 +1,356 @@
+use cpu::Address;
+use cpu::memory::Memory;
+use nes::mapper::Mapper;
+use nes::rom::{ROM, ScreenMode, CHR_ROM_PAGE_LENGTH, PRG_RAM_PAGE_LENGTH, PRG_ROM_PAGE_LENGTH};
+
+const CHR_BANK_LENGTH: usize = 0x1000;
+
+// Boards with more than 256KB of PRG-ROM select the 256KB half through the CHR bank registers
+const PRG_OUTER_BANK_LENGTH: usize = 0x40000;
+
+// Nintendo MMC1, as found on the SxROM boards
+// $6000-$7FFF  8KB PRG-RAM bank, which can be disabled
+// $8000-$BFFF  16KB PRG-ROM bank, switchable or fixed to the first bank
+// $C000-$FFFF  16KB PRG-ROM bank, switchable or fixed to the last bank
+// $8000-$FFFF  Serial port to the load register (write)
+// The registers are written one bit at a time through a 5-bit shift register, the fifth write copies it into the register
+// picked by bits 13 and 14 of the address:
+// $8000-$9FFF  Control: CPPMM, CHR banking mode, PRG banking mode and mirroring
+// $A000-$BFFF  CHR bank 0, 4KB at PPU $0000, or 8KB at PPU $0000 when the low bit is ignored
+// $C000-$DFFF  CHR bank 1, 4KB at PPU $1000, ignored in 8KB mode
+// $E000-$FFFF  PRG bank: RPPPP, PRG-RAM disable and the 16KB PRG-ROM bank
+// Ref: https://wiki.nesdev.com/w/index.php/MMC1
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+
+    shift: u8,
+    shift_count: u8,
+    written: bool,                      // Whether the serial port was written during the current instruction
+    control: u8,
+    chr_bank0: u8,
+    chr_bank1: u8,
+    prg_bank: u8,
+    chr_high: bool,                     // Whether the PPU last fetched from the pattern table at $1000
+
+    // The SNROM, SOROM, SUROM and SXROM boards use the upper CHR bank lines for other things, as they only have 8KB of
+    // CHR-RAM. The board is recognized by the sizes of its PRG-ROM and RAM.
+    prg_ram_disable_line: bool,         // SNROM: bit 4 disables the PRG-RAM
+    prg_outer_bank_line: bool,          // SUROM, SXROM: bit 4 selects the 256KB PRG-ROM half
+    prg_ram_bank_shift: Option<u8>      // SOROM: bit 3, SXROM: bits 2-3 select the 8KB PRG-RAM bank
+}
+
+impl Mmc1 {
+    pub fn new(rom: &ROM) -> Mmc1 {
+        let header = &rom.header;
+        let chr_ram = header.chr_size == 0;
+        let chr = if chr_ram {
+            vec![0; (header.chr_ram_size + header.chr_nvram_size).max(CHR_ROM_PAGE_LENGTH)]
+        } else {
+            rom.chr_rom.clone()
+        };
+        let prg_ram_size = header.prg_ram_size + header.prg_nvram_size;
+
+        Mmc1 {
+            prg_rom: rom.prg_rom.clone(),
+            prg_ram: vec![0; prg_ram_size],
+            chr,
+            chr_ram,
+            shift: 0,
+            shift_count: 0,
+            written: false,
+            control: 0b0_1100,
+            chr_bank0: 0,
+            chr_bank1: 0,
+            prg_bank: 0,
+            chr_high: false,
+            prg_ram_disable_line: chr_ram && header.chr_ram_size + header.chr_nvram_size <= CHR_ROM_PAGE_LENGTH
+                && rom.prg_rom.len() <= PRG_OUTER_BANK_LENGTH && prg_ram_size == PRG_RAM_PAGE_LENGTH,
+            prg_outer_bank_line: rom.prg_rom.len() > PRG_OUTER_BANK_LENGTH,
+            prg_ram_bank_shift: match prg_ram_size / PRG_RAM_PAGE_LENGTH {
+                2 => Some(3),
+                4 => Some(2),
+                _ => None
+            }
+        }
+    }
+
+    // A write with bit 7 set resets the shift register and goes back to PRG mode 3, other writes shift in bit 0
+    fn write_serial(&mut self, address: Address, value: u8) {
+        if value & 0b1000_0000 != 0 {
+            self.shift = 0;
+            self.shift_count = 0;
+            self.control |= 0b0_1100;
+            return;
+        }
+
+        self.shift |= (value & 1) << self.shift_count;
+        self.shift_count += 1;
+
+        if self.shift_count == 5 {
+            match address {
+                0x8000..=0x9FFF => self.control = self.shift,
+                0xA000..=0xBFFF => self.chr_bank0 = self.shift,
+                0xC000..=0xDFFF => self.chr_bank1 = self.shift,
+                _               => self.prg_bank = self.shift
+            }
+            self.shift = 0;
+            self.shift_count = 0;
+        }
+    }
+
+    // The CHR bank register that drives the CHR lines at the moment, which is the one for the pattern table the PPU
+    // fetched from last, or always the first one in 8KB mode
+    fn chr_lines(&self) -> u8 {
+        if self.control & 0b1_0000 != 0 && self.chr_high { self.chr_bank1 } else { self.chr_bank0 }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty()
+            && self.prg_bank & 0b1_0000 == 0
+            && !(self.prg_ram_disable_line && self.chr_lines() & 0b1_0000 != 0)
+    }
+
+    fn prg_ram_address(&self, address: Address) -> usize {
+        let bank = match self.prg_ram_bank_shift {
+            Some(shift) => (self.chr_lines() >> shift) as usize & (self.prg_ram.len() / PRG_RAM_PAGE_LENGTH - 1),
+            None => 0
+        };
+        (bank * PRG_RAM_PAGE_LENGTH + (address - 0x6000) as usize) % self.prg_ram.len()
+    }
+
+    // PRG mode 0 and 1 switch 32KB at once, mode 2 fixes the first bank at $8000 and mode 3 fixes the last at $C000
+    fn prg_rom_address(&self, address: Address) -> usize {
+        let outer = if self.prg_outer_bank_line { (self.chr_lines() >> 4) as usize & 1 } else { 0 };
+        let outer_banks = (self.prg_rom.len() / PRG_ROM_PAGE_LENGTH).clamp(1, PRG_OUTER_BANK_LENGTH / PRG_ROM_PAGE_LENGTH);
+        let bank = (self.prg_bank & 0b0_1111) as usize;
+        let high = address >= 0xC000;
+
+        let inner = match (self.control >> 2) & 0b11 {
+            0 | 1 => (bank & !1) | high as usize,
+            2 => if high { bank } else { 0 },
+            _ => if high { outer_banks - 1 } else { bank }
+        };
+
+        let page = outer * outer_banks + inner;
+        (page * PRG_ROM_PAGE_LENGTH + (address as usize & (PRG_ROM_PAGE_LENGTH - 1))) % self.prg_rom.len()
+    }
+
+    fn chr_address(&self, address: Address) -> usize {
+        let bank = if self.control & 0b1_0000 == 0 {
+            (self.chr_bank0 & !1) as usize | (address >> 12) as usize
+        } else if address < 0x1000 {
+            self.chr_bank0 as usize
+        } else {
+            self.chr_bank1 as usize
+        };
+        (bank * CHR_BANK_LENGTH + (address as usize & (CHR_BANK_LENGTH - 1))) % self.chr.len()
+    }
+}
+
+impl Memory for Mmc1 {
+    fn read(&mut self, address: Address) -> u8 {
+        self.peek(address)
+    }
+
+    // The serial port ignores a write on the cycle right after another one, which is what read-modify-write instructions
+    // do when they write the old value back before the new one. Only the first write of an instruction is taken.
+    fn write(&mut self, address: Address, value: u8) {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let address = self.prg_ram_address(address);
+                self.prg_ram[address] = value;
+            }
+            0x8000..=0xFFFF if !self.written => {
+                self.written = true;
+                self.write_serial(address, value);
+            }
+            _ => ()
+        }
+    }
+
+    fn peek(&self, address: Address) -> u8 {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.prg_ram[self.prg_ram_address(address)],
+            0x8000..=0xFFFF if !self.prg_rom.is_empty() => self.prg_rom[self.prg_rom_address(address)],
+            _ => 0
+        }
+    }
+
+    fn clock(&mut self, _cycles: u8) {
+        self.written = false;
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn read_chr(&mut self, address: Address) -> u8 {
+        self.chr_high = address & 0x1000 != 0;
+        if self.chr.is_empty() { 0 } else { self.chr[self.chr_address(address)] }
+    }
+
+    fn write_chr(&mut self, address: Address, value: u8) {
+        self.chr_high = address & 0x1000 != 0;
+        if self.chr_ram {
+            let address = self.chr_address(address);
+            self.chr[address] = value;
+        }
+    }
+
+    fn screen_mode(&self) -> ScreenMode {
+        match self.control & 0b0_0011 {
+            0 => ScreenMode::OneScreenLower,
+            1 => ScreenMode::OneScreenUpper,
+            2 => ScreenMode::Vertical,
+            _ => ScreenMode::Horizontal
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes::rom::RomBuilder;
+
+    // Mapper 1 with the given amount of 16KB PRG-ROM pages, 8KB CHR-ROM pages and RAM
+    fn mmc1(prg_pages: usize, chr_pages: usize, prg_ram: usize, chr_ram: usize) -> Mmc1 {
+        Mmc1::new(&RomBuilder::new(1).prg_rom(prg_pages).chr_rom(chr_pages).prg_ram(prg_ram, 0).chr_ram(chr_ram).build())
+    }
+
+    // Writes a register through the serial port, one instruction per bit
+    fn write_register(cartridge: &mut Mmc1, address: Address, value: u8) {
+        for bit in 0..5 {
+            cartridge.write(address, (value >> bit) & 1);
+            cartridge.clock(4);
+        }
+    }
+
+    #[test]
+    fn powers_on_with_the_last_bank_fixed() {
+        let mut cartridge = mmc1(8, 2, 0x2000, 0);
+        assert_eq!(cartridge.read(0x8000), 0);
+        assert_eq!(cartridge.read(0xC000), 7);
+    }
+
+    #[test]
+    fn serial_port_loads_registers() {
+        let mut cartridge = mmc1(8, 2, 0x2000, 0);
+        write_register(&mut cartridge, 0xE000, 3);
+        assert_eq!(cartridge.read(0x8000), 3);
+        assert_eq!(cartridge.read(0xC000), 7);
+
+        // Fixed first bank, switchable bank at $C000
+        write_register(&mut cartridge, 0x8000, 0b0_1000);
+        assert_eq!(cartridge.read(0x8000), 0);
+        assert_eq!(cartridge.read(0xC000), 3);
+
+        // 32KB mode ignores the low bit of the bank
+        write_register(&mut cartridge, 0x8000, 0b0_0000);
+        assert_eq!(cartridge.read(0x8000), 2);
+        assert_eq!(cartridge.read(0xC000), 3);
+    }
+
+    #[test]
+    fn bit_7_resets_the_shift_register() {
+        let mut cartridge = mmc1(8, 2, 0x2000, 0);
+        write_register(&mut cartridge, 0x8000, 0b0_0000);
+        cartridge.write(0xE000, 1);
+        cartridge.clock(4);
+        cartridge.write(0xE000, 0x80);
+        cartridge.clock(4);
+        write_register(&mut cartridge, 0xE000, 2);
+        assert_eq!(cartridge.read(0x8000), 2);
+        assert_eq!(cartridge.read(0xC000), 7);
+    }
+
+    #[test]
+    fn consecutive_writes_are_ignored() {
+        let mut cartridge = mmc1(8, 2, 0x2000, 0);
+        // Like INC $FFFF, which writes $FF and then $00 on the next cycle
+        cartridge.write(0xE000, 0xFF);
+        cartridge.write(0xE000, 0x00);
+        cartridge.clock(6);
+        write_register(&mut cartridge, 0xE000, 5);
+        assert_eq!(cartridge.read(0x8000), 5);
+    }
+
+    #[test]
+    fn mirroring_and_chr_banks() {
+        let mut cartridge = mmc1(8, 4, 0x2000, 0);
+        for &(control, screen_mode) in &[(0, ScreenMode::OneScreenLower), (1, ScreenMode::OneScreenUpper),
+                                          (2, ScreenMode::Vertical), (3, ScreenMode::Horizontal)] {
+            write_register(&mut cartridge, 0x8000, 0b1_1100 | control);
+            assert_eq!(cartridge.screen_mode(), screen_mode);
+        }
+
+        write_register(&mut cartridge, 0xA000, 5);
+        write_register(&mut cartridge, 0xC000, 2);
+        assert_eq!(cartridge.read_chr(0x0000), 5);
+        assert_eq!(cartridge.read_chr(0x1000), 2);
+
+        // 8KB mode ignores the low bit and CHR bank 1
+        write_register(&mut cartridge, 0x8000, 0b0_1100);
+        assert_eq!(cartridge.read_chr(0x0000), 4);
+        assert_eq!(cartridge.read_chr(0x1000), 5);
+    }
+
+    #[test]
+    fn prg_ram_can_be_disabled() {
+        let mut cartridge = mmc1(8, 2, 0x2000, 0);
+        cartridge.write(0x6000, 0x42);
+        cartridge.clock(4);
+        assert_eq!(cartridge.read(0x6000), 0x42);
+
+        write_register(&mut cartridge, 0xE000, 0b1_0000);
+        assert_eq!(cartridge.read(0x6000), 0);
+        write_register(&mut cartridge, 0xE000, 0b0_0000);
+        assert_eq!(cartridge.read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn snrom_disables_prg_ram_through_chr_lines() {
+        let mut cartridge = mmc1(16, 0, 0x2000, 0x2000);
+        cartridge.write(0x6000, 0x42);
+        cartridge.clock(4);
+        write_register(&mut cartridge, 0xA000, 0b1_0000);
+        assert_eq!(cartridge.read(0x6000), 0);
+        write_register(&mut cartridge, 0xA000, 0b0_0000);
+        assert_eq!(cartridge.read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn sorom_switches_prg_ram_banks() {
+        let mut cartridge = mmc1(16, 0, 0x4000, 0x2000);
+        cartridge.write(0x6000, 0x11);
+        cartridge.clock(4);
+        write_register(&mut cartridge, 0xA000, 0b0_1000);
+        assert_eq!(cartridge.read(0x6000), 0);
+        cartridge.write(0x6000, 0x22);
+        cartridge.clock(4);
+        write_register(&mut cartridge, 0xA000, 0b0_0000);
+        assert_eq!(cartridge.read(0x6000), 0x11);
+    }
+
+    #[test]
+    fn surom_switches_256kb_halves() {
+        let mut cartridge = mmc1(32, 0, 0x2000, 0x2000);
+        assert_eq!(cartridge.read(0xC000), 15);
+        write_register(&mut cartridge, 0xA000, 0b1_0000);
+        assert_eq!(cartridge.read(0xC000), 31);
+        write_register(&mut cartridge, 0xE000, 2);
+        assert_eq!(cartridge.read(0x8000), 18);
+    }
+
+    #[test]
+    fn sxrom_switches_prg_ram_banks() {
+        let mut cartridge = mmc1(32, 0, 0x8000, 0x2000);
+        write_register(&mut cartridge, 0xA000, 0b0_1100);
+        cartridge.write(0x6000, 0x33);
+        cartridge.clock(4);
+        write_register(&mut cartridge, 0xA000, 0b0_0000);
+        assert_eq!(cartridge.read(0x6000), 0);
+        write_register(&mut cartridge, 0xA000, 0b0_1100);
+        assert_eq!(cartridge.read(0x6000), 0x33);
+    }
+}
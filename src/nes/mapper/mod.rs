@@ -0,0 +1,45 @@
+pub mod mmc1;
+pub mod nrom;
+
+use cpu::Address;
+use cpu::memory::Memory;
+use nes::rom::{RomError, ROM, ScreenMode};
+use self::mmc1::Mmc1;
+use self::nrom::Nrom;
+
+// A cartridge as the rest of the system sees it: the mapper hardware together with the ROM and RAM chips it controls
+// The CPU side is the cartridge space of the CPU bus ($4020-$FFFF) and the IRQ line, which are part of Memory.
+// The PPU side is the pattern table space ($0000-$1FFF) and the arrangement of the nametables.
+// Ref: https://wiki.nesdev.com/w/index.php/Mapper
+pub trait Mapper: Memory {
+    // Reads take a mutable reference as some mappers switch banks when certain tiles are fetched
+    fn read_chr(&mut self, address: Address) -> u8;
+    fn write_chr(&mut self, address: Address, value: u8);
+
+    // How the two nametables inside the NES are arranged over the four nametable slots
+    fn screen_mode(&self) -> ScreenMode;
+}
+
+// Builds the cartridge for a ROM, by the mapper number in its header
+pub fn new(rom: &ROM) -> Result<Box<dyn Mapper>, RomError> {
+    match rom.header.mapper {
+        0 => Ok(Box::new(Nrom::new(rom))),
+        1 => Ok(Box::new(Mmc1::new(rom))),
+        mapper => Err(RomError::UnsupportedMapper(mapper))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes::rom::RomBuilder;
+
+    #[test]
+    fn unsupported_mapper() {
+        match new(&RomBuilder::new(4).build()) {
+            Err(err @ RomError::UnsupportedMapper(4)) => assert_eq!(err.to_string(), "Unsupported mapper 4"),
+            Err(err) => panic!("Expected an unsupported mapper, got {:?}", err),
+            Ok(_) => panic!("Expected an unsupported mapper")
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use cpu::Address;
+use cpu::memory::Memory;
+use nes::mapper::Mapper;
+use nes::rom::{ROM, ScreenMode, CHR_ROM_PAGE_LENGTH, PRG_RAM_PAGE_LENGTH};
+
+// Where a trainer is loaded to, in PRG-RAM at $7000-$71FF
+const TRAINER_OFFSET: usize = 0x1000;
+
+// Cartridges without any mapper hardware
+// $6000-$7FFF  PRG-RAM, only on the Family BASIC boards (2KB or 4KB, mirrored), or 8KB holding a trainer at $7000
+// $8000-$BFFF  First 16KB of PRG-ROM
+// $C000-$FFFF  Last 16KB of PRG-ROM, or a mirror of the first 16KB for NROM-128
+// The PPU side is 8KB of CHR-ROM, or CHR-RAM on boards that don't have any CHR-ROM. The nametable arrangement is fixed
+// by the solder pads on the board, which is what the header describes. The 8KB of PRG-RAM that iNES headers claim is
+// already dropped by the loader, see rom::load_with_patch.
+// Ref: https://wiki.nesdev.com/w/index.php/NROM
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    screen_mode: ScreenMode
+}
+
+impl Nrom {
+    pub fn new(rom: &ROM) -> Nrom {
+        let header = &rom.header;
+        let chr_ram = header.chr_size == 0;
+        let chr = if chr_ram {
+            vec![0; (header.chr_ram_size + header.chr_nvram_size).max(CHR_ROM_PAGE_LENGTH)]
+        } else {
+            rom.chr_rom.clone()
+        };
+
+        // The copiers that trainers were made for loaded them into 8KB of RAM, which is where they expect to be
+        let mut prg_ram = vec![0; header.prg_ram_size + header.prg_nvram_size];
+        if let Some(ref trainer) = rom.trainer {
+            let length = prg_ram.len().max(PRG_RAM_PAGE_LENGTH);
+            prg_ram.resize(length, 0);
+            prg_ram[TRAINER_OFFSET..TRAINER_OFFSET + trainer.len()].copy_from_slice(trainer);
+        }
+
+        Nrom {
+            prg_rom: rom.prg_rom.clone(),
+            prg_ram,
+            chr,
+            chr_ram,
+            screen_mode: header.screen_mode
+        }
+    }
+}
+
+impl Memory for Nrom {
+    fn read(&mut self, address: Address) -> u8 {
+        self.peek(address)
+    }
+
+    // Writes to ROM have no effect
+    fn write(&mut self, address: Address, value: u8) {
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let length = self.prg_ram.len();
+                self.prg_ram[(address - 0x6000) as usize % length] = value;
+            }
+            _ => ()
+        }
+    }
+
+    fn peek(&self, address: Address) -> u8 {
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => self.prg_ram[(address - 0x6000) as usize % self.prg_ram.len()],
+            0x8000..=0xFFFF if !self.prg_rom.is_empty() => self.prg_rom[(address - 0x8000) as usize % self.prg_rom.len()],
+            _ => 0
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn read_chr(&mut self, address: Address) -> u8 {
+        if self.chr.is_empty() { 0 } else { self.chr[address as usize % self.chr.len()] }
+    }
+
+    fn write_chr(&mut self, address: Address, value: u8) {
+        if self.chr_ram {
+            let length = self.chr.len();
+            self.chr[address as usize % length] = value;
+        }
+    }
+
+    fn screen_mode(&self) -> ScreenMode {
+        self.screen_mode
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes::rom::RomBuilder;
+
+    fn nrom(rom: RomBuilder) -> Nrom {
+        Nrom::new(&rom.build())
+    }
+
+    #[test]
+    fn nrom_128_is_mirrored() {
+        let mut cartridge = nrom(RomBuilder::new(0));
+        assert_eq!(cartridge.read(0x8000), 0);
+        assert_eq!(cartridge.read(0xC000), 0);
+        assert_eq!(cartridge.read(0x8123), cartridge.read(0xC123));
+        assert_eq!(cartridge.screen_mode(), ScreenMode::Horizontal);
+    }
+
+    #[test]
+    fn nrom_256_is_not_mirrored() {
+        let mut cartridge = nrom(RomBuilder::new(0).prg_rom(2).screen_mode(ScreenMode::Vertical));
+        assert_eq!(cartridge.read(0x8000), 0);
+        assert_eq!(cartridge.read(0xC000), 1);
+        assert_eq!(cartridge.screen_mode(), ScreenMode::Vertical);
+    }
+
+    #[test]
+    fn chr_rom_is_read_only() {
+        let mut cartridge = nrom(RomBuilder::new(0));
+        cartridge.write_chr(0x0001, 0xFF);
+        assert_eq!(cartridge.read_chr(0x0001), 11);
+    }
+
+    #[test]
+    fn chr_ram_without_chr_rom() {
+        let mut cartridge = nrom(RomBuilder::new(0).chr_rom(0).chr_ram(CHR_ROM_PAGE_LENGTH));
+        cartridge.write_chr(0x1FFF, 0xAB);
+        assert_eq!(cartridge.read_chr(0x1FFF), 0xAB);
+    }
+
+    #[test]
+    fn prg_ram_from_the_header() {
+        // 2KB, mirrored over $6000-$7FFF
+        let mut cartridge = nrom(RomBuilder::new(0).prg_rom(2).prg_ram(0x800, 0));
+        cartridge.write(0x6000, 0x42);
+        cartridge.write(0x67FF, 0x24);
+        assert_eq!(cartridge.read(0x6800), 0x42);
+        assert_eq!(cartridge.read(0x7FFF), 0x24);
+        cartridge.write(0x8000, 0x42);
+        assert_eq!(cartridge.read(0x8000), 0);
+
+        // 4KB of battery-backed RAM
+        let mut cartridge = nrom(RomBuilder::new(0).prg_rom(2).prg_ram(0, 0x1000));
+        cartridge.write(0x6FFF, 0x42);
+        assert_eq!(cartridge.read(0x7FFF), 0x42);
+        assert_eq!(cartridge.read(0x6000), 0);
+    }
+
+    #[test]
+    fn trainer_at_7000() {
+        let mut cartridge = nrom(RomBuilder::new(0).trainer());
+        assert_eq!(cartridge.read(0x7000), 0);
+        assert_eq!(cartridge.read(0x7001), 3);
+        assert_eq!(cartridge.read(0x71FF), (0x1FF * 3) as u8);
+        assert_eq!(cartridge.read(0x7200), 0);
+
+        // The rest of the 8KB is RAM, and so is the trainer itself
+        cartridge.write(0x6000, 0x42);
+        cartridge.write(0x7000, 0x24);
+        assert_eq!((cartridge.read(0x6000), cartridge.read(0x7000)), (0x42, 0x24));
+    }
+}
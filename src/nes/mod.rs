@@ -1,37 +1,65 @@
+pub mod archive;
+pub mod database;
+pub mod disk_system;
+pub mod fds;
+pub mod hash;
+pub mod info;
+pub mod mapper;
+pub mod nsf;
+pub mod nsf_player;
+pub mod patch;
 pub mod rom;
+pub mod unif;
 
-use std::fs::File;
-use std::io::stdin;
+use std::io::{stdin, Write};
 
 use cpu::Cpu;
+use cpu::memory::CpuBus;
 use ROM;
+use self::disk_system::DiskSystem;
+use self::fds::Disk;
+use self::rom::RomError;
 
 pub struct NES {
-    cpu: Cpu,
-    rom: ROM
+    cpu: Cpu
 }
 
 impl NES {
-    pub fn new(mut file: File) -> NES {
-        NES { cpu: Cpu::new(), rom: rom::load_from_file(&mut file).unwrap() }
+    // Inserts the cartridge of the given ROM, which fails when its mapper isn't supported
+    pub fn new(rom: &ROM) -> Result<NES, RomError> {
+        let bus = CpuBus::new(mapper::new(rom)?);
+        Ok(NES { cpu: Cpu::new(Box::new(bus)) })
     }
 
-    pub fn load_rom(&mut self, mut file: File) {
-        self.cpu = Cpu::new();
-        self.rom = rom::load_from_file(&mut file).unwrap();
+    // Plugs in the Famicom Disk System with the disk inserted, which needs the BIOS of the RAM adapter
+    pub fn with_disk(disk: &Disk, bios: Vec<u8>) -> NES {
+        let bus = CpuBus::new(Box::new(DiskSystem::new(disk, bios)));
+        NES { cpu: Cpu::new(Box::new(bus)) }
     }
 
-    pub fn run(mut self) {
-        self.cpu.program = self.rom.prg_rom;
+    // Writes a line in the nestest.log format for every executed instruction to the given output, see cpu/trace.rs
+    pub fn set_trace(&mut self, output: Option<Box<dyn Write>>) {
+        self.cpu.set_trace(output);
+    }
+
+    // Powers on and runs the given amount of instructions, or otherwise steps through the program one instruction
+    // every time enter is pressed
+    pub fn run(mut self, steps: Option<u64>) {
+        self.cpu.power_on();
 
-        println!("{:?}", self.cpu.program);
-        let mut guess = String::new();
+        if let Some(steps) = steps {
+            for _ in 0..steps {
+                self.cpu.step();
+            }
+            return;
+        }
 
+        let mut line = String::new();
         loop {
             self.cpu.step();
 
-            stdin().read_line(&mut guess)
+            stdin().read_line(&mut line)
                 .expect("Failed to read line");
         }
     }
-}
\ No newline at end of file
+}
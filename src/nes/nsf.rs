@@ -0,0 +1,517 @@
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use nes::patch;
+use nes::patch::PatchError;
+use nes::rom::Region;
+
+pub const NSF_MAGIC: &[u8] = b"NESM\x1a";
+pub const NSFE_MAGIC: &[u8] = b"NSFE";
+const NSF_HEADER_LENGTH: usize = 128;
+const CHUNK_HEADER_LENGTH: usize = 8;
+
+// Play rates in microseconds per call, for files that leave them out
+pub const DEFAULT_NTSC_SPEED: u16 = 16639;
+pub const DEFAULT_PAL_SPEED: u16 = 19997;
+
+// Sound chips from cartridges and the Disk System the music was written for
+// Ref: https://wiki.nesdev.com/w/index.php/NSF#Header_Overview
+bitflags! {
+    pub struct ExpansionAudio: u8 {
+        const VRC6 =        0b0000_0001;
+        const VRC7 =        0b0000_0010;
+        const FDS =         0b0000_0100;
+        const MMC5 =        0b0000_1000;
+        const N163 =        0b0001_0000;
+        const SUNSOFT_5B =  0b0010_0000;
+        const VT02 =        0b0100_0000;
+    }
+}
+
+// Playback features of NSF2, from byte $7C of the header or the NSF2 chunk of NSFe files
+// Ref: https://wiki.nesdev.com/w/index.php/NSF2
+bitflags! {
+    pub struct Nsf2Flags: u8 {
+        const IRQ =                 0b0001_0000;    // The music uses the IRQ of the player
+        const NON_RETURNING_INIT =  0b0010_0000;    // INIT doesn't return, it runs the music itself
+        const SUPPRESS_PLAY =       0b0100_0000;    // PLAY shouldn't be called
+        const MANDATORY_METADATA =  0b1000_0000;    // The appended NSFe metadata has chunks that have to be understood
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum NsfFormat {
+    NSF,
+    NSFE
+}
+
+#[derive(Debug)]
+pub enum NsfError {
+    BadMagic,
+    TruncatedHeader(usize),
+    TruncatedChunk { id: String, expected: usize, actual: usize },
+    MissingChunk(&'static str),                     // A chunk every NSFe file needs is missing
+    UnknownChunk(String),                           // A chunk the player is required to understand
+    NoSongs,
+    Patch(PatchError),
+    Io(io::Error)
+}
+
+impl fmt::Display for NsfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NsfError::BadMagic                                  => write!(f, "Not an NSF or NSFe file"),
+            NsfError::TruncatedHeader(length)                   => write!(f, "Header should be {} bytes, but the file is only {} bytes", NSF_HEADER_LENGTH, length),
+            NsfError::TruncatedChunk { ref id, expected, actual } => write!(f, "{} chunk should be {} bytes, but only {} bytes are left", id, expected, actual),
+            NsfError::MissingChunk(id)                          => write!(f, "File doesn't have an {} chunk", id),
+            NsfError::UnknownChunk(ref id)                      => write!(f, "Unknown required chunk {:?}", id),
+            NsfError::NoSongs                                   => write!(f, "File doesn't contain any songs"),
+            NsfError::Patch(ref err)                            => write!(f, "{}", err),
+            NsfError::Io(ref err)                               => write!(f, "{}", err)
+        }
+    }
+}
+
+impl Error for NsfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            NsfError::Patch(ref err) => Some(err),
+            NsfError::Io(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<PatchError> for NsfError {
+    fn from(err: PatchError) -> NsfError {
+        NsfError::Patch(err)
+    }
+}
+
+impl From<io::Error> for NsfError {
+    fn from(err: io::Error) -> NsfError {
+        NsfError::Io(err)
+    }
+}
+
+// A music rip, which is the sound code and data of a game with an INIT routine to start a song and a PLAY routine to
+// call at a steady rate
+pub struct Nsf {
+    pub format: NsfFormat,
+    pub version: u8,
+    pub songs: u8,
+    pub starting_song: u8,                  // Zero-based, unlike in NSF headers
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub ntsc_speed: u16,                    // Microseconds between PLAY calls
+    pub pal_speed: u16,
+    pub banks: Option<[u8; 8]>,             // Initial 4KB banks at $8000-$FFFF, for bankswitched files
+    pub region: Region,
+    pub expansion: ExpansionAudio,
+    pub flags: Nsf2Flags,
+    pub track_labels: Vec<String>,          // NSFe only
+    pub track_times: Vec<Option<u32>>,      // NSFe only, in milliseconds
+    pub data: Vec<u8>
+}
+
+pub fn is_nsf(buf: &[u8]) -> bool {
+    buf.starts_with(NSF_MAGIC) || buf.starts_with(NSFE_MAGIC)
+}
+
+pub fn load(buf: &[u8]) -> Result<Nsf, NsfError> {
+    load_with_patch(buf, None)
+}
+
+// Loads an NSF or NSFe file after applying an IPS, UPS or BPS patch to it
+// Archives have to be extracted beforehand, see archive::extract
+pub fn load_with_patch(buf: &[u8], patch: Option<&[u8]>) -> Result<Nsf, NsfError> {
+    let buf = match patch {
+        Some(patch) => Cow::Owned(patch::apply(buf, patch)?),
+        None => Cow::Borrowed(buf)
+    };
+
+    parse_nsf(&buf)
+}
+
+pub fn parse_nsf(buf: &[u8]) -> Result<Nsf, NsfError> {
+    let nsf = if buf.starts_with(NSF_MAGIC) {
+        parse_nsf_header(buf)?
+    } else if buf.starts_with(NSFE_MAGIC) {
+        parse_nsfe(buf)?
+    } else {
+        return Err(NsfError::BadMagic);
+    };
+
+    if nsf.songs == 0 {
+        return Err(NsfError::NoSongs);
+    }
+    Ok(nsf)
+}
+
+// Ref: https://wiki.nesdev.com/w/index.php/NSF
+fn parse_nsf_header(buf: &[u8]) -> Result<Nsf, NsfError> {
+    if buf.len() < NSF_HEADER_LENGTH {
+        return Err(NsfError::TruncatedHeader(buf.len()));
+    }
+
+    let banks = banks(&buf[0x70..0x78]);
+
+    Ok(Nsf {
+        format: NsfFormat::NSF,
+        version: buf[0x05],
+        songs: buf[0x06],
+        starting_song: buf[0x07].saturating_sub(1),
+        load_address: LittleEndian::read_u16(&buf[0x08..]),
+        init_address: LittleEndian::read_u16(&buf[0x0A..]),
+        play_address: LittleEndian::read_u16(&buf[0x0C..]),
+        title: string(&buf[0x0E..0x2E]),
+        artist: string(&buf[0x2E..0x4E]),
+        copyright: string(&buf[0x4E..0x6E]),
+        ntsc_speed: speed(LittleEndian::read_u16(&buf[0x6E..]), DEFAULT_NTSC_SPEED),
+        pal_speed: speed(LittleEndian::read_u16(&buf[0x78..]), DEFAULT_PAL_SPEED),
+        banks,
+        region: region(buf[0x7A]),
+        expansion: ExpansionAudio::from_bits_truncate(buf[0x7B]),
+        flags: if buf[0x05] >= 2 { Nsf2Flags::from_bits_truncate(buf[0x7C]) } else { Nsf2Flags::empty() },
+        track_labels: vec![],
+        track_times: vec![],
+        data: buf[NSF_HEADER_LENGTH..].to_vec()
+    })
+}
+
+// NSFe files are a list of chunks, each with a 32-bit length and a 4 character ID. Chunks with an upper case first
+// letter have to be understood, the others can be skipped.
+// Ref: https://wiki.nesdev.com/w/index.php/NSFe
+fn parse_nsfe(buf: &[u8]) -> Result<Nsf, NsfError> {
+    let mut nsf = Nsf {
+        format: NsfFormat::NSFE,
+        version: 0,
+        songs: 0,
+        starting_song: 0,
+        load_address: 0,
+        init_address: 0,
+        play_address: 0,
+        title: String::new(),
+        artist: String::new(),
+        copyright: String::new(),
+        ntsc_speed: DEFAULT_NTSC_SPEED,
+        pal_speed: DEFAULT_PAL_SPEED,
+        banks: None,
+        region: Region::NTSC,
+        expansion: ExpansionAudio::empty(),
+        flags: Nsf2Flags::empty(),
+        track_labels: vec![],
+        track_times: vec![],
+        data: vec![]
+    };
+    let (mut info, mut data) = (false, false);
+
+    let mut rest = &buf[NSFE_MAGIC.len()..];
+    while !rest.is_empty() {
+        if rest.len() < CHUNK_HEADER_LENGTH {
+            return Err(NsfError::TruncatedHeader(rest.len()));
+        }
+        let length = LittleEndian::read_u32(rest) as usize;
+        let id = &rest[4..8];
+        let chunk = rest.get(CHUNK_HEADER_LENGTH..CHUNK_HEADER_LENGTH + length)
+            .ok_or_else(|| NsfError::TruncatedChunk { id: string(id), expected: length, actual: rest.len() - CHUNK_HEADER_LENGTH })?;
+        rest = &rest[CHUNK_HEADER_LENGTH + length..];
+
+        match id {
+            b"INFO" => {
+                if chunk.len() < 9 {
+                    return Err(NsfError::TruncatedChunk { id: string(id), expected: 9, actual: chunk.len() });
+                }
+                nsf.load_address = LittleEndian::read_u16(chunk);
+                nsf.init_address = LittleEndian::read_u16(&chunk[2..]);
+                nsf.play_address = LittleEndian::read_u16(&chunk[4..]);
+                nsf.region = region(chunk[6]);
+                nsf.expansion = ExpansionAudio::from_bits_truncate(chunk[7]);
+                nsf.songs = chunk[8];
+                nsf.starting_song = chunk.get(9).cloned().unwrap_or(0);
+                info = true;
+            }
+            b"DATA" => {
+                nsf.data = chunk.to_vec();
+                data = true;
+            }
+            b"BANK" => {
+                let mut initial = [0; 8];
+                initial[..chunk.len().min(8)].copy_from_slice(&chunk[..chunk.len().min(8)]);
+                nsf.banks = Some(initial);
+            }
+            b"RATE" => {
+                if chunk.len() >= 2 {
+                    nsf.ntsc_speed = speed(LittleEndian::read_u16(chunk), DEFAULT_NTSC_SPEED);
+                }
+                if chunk.len() >= 4 {
+                    nsf.pal_speed = speed(LittleEndian::read_u16(&chunk[2..]), DEFAULT_PAL_SPEED);
+                }
+            }
+            b"auth" => {
+                let mut strings = chunk.split(|&byte| byte == 0).map(string);
+                nsf.title = strings.next().unwrap_or_default();
+                nsf.artist = strings.next().unwrap_or_default();
+                nsf.copyright = strings.next().unwrap_or_default();
+            }
+            b"tlbl" => nsf.track_labels = chunk.split(|&byte| byte == 0).map(string).take(nsf.songs as usize).collect(),
+            b"time" => nsf.track_times = chunk.chunks(4)
+                .filter(|time| time.len() == 4)
+                .map(|time| match LittleEndian::read_i32(time) {
+                    time if time >= 0 => Some(time as u32),
+                    _ => None
+                })
+                .collect(),
+            b"NSF2" => nsf.flags = Nsf2Flags::from_bits_truncate(chunk.first().cloned().unwrap_or(0)),
+            // Selects between the VRC7 and the YM2413 and can replace their instruments, neither is emulated so the
+            // chunk only marks the music as VRC7 music, which gets it the expansion audio warning
+            b"VRC7" => nsf.expansion |= ExpansionAudio::VRC7,
+            b"NEND" => break,
+            _ if id[0].is_ascii_uppercase() => return Err(NsfError::UnknownChunk(string(id))),
+            _ => ()
+        }
+    }
+
+    if !info {
+        return Err(NsfError::MissingChunk("INFO"));
+    }
+    if !data {
+        return Err(NsfError::MissingChunk("DATA"));
+    }
+    Ok(nsf)
+}
+
+// A file is bankswitched when any of its initial banks is set
+fn banks(raw: &[u8]) -> Option<[u8; 8]> {
+    if raw.iter().all(|&bank| bank == 0) {
+        return None;
+    }
+    let mut banks = [0; 8];
+    banks.copy_from_slice(raw);
+    Some(banks)
+}
+
+fn speed(speed: u16, default: u16) -> u16 {
+    if speed == 0 { default } else { speed }
+}
+
+// Bit 0 selects PAL, bit 1 means the music works on both
+fn region(flags: u8) -> Region {
+    match flags & 0b0000_0011 {
+        0 => Region::NTSC,
+        1 => Region::PAL,
+        _ => Region::Multi
+    }
+}
+
+fn string(data: &[u8]) -> String {
+    let end = data.iter().position(|&byte| byte == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A version 1 NSF with 3 songs starting at song 2, loaded at $8000 with INIT at $8003 and PLAY at $8006
+    fn nsf_header() -> Vec<u8> {
+        let mut buf = NSF_MAGIC.to_vec();
+        buf.resize(NSF_HEADER_LENGTH, 0);
+        buf[0x05] = 1;
+        buf[0x06] = 3;
+        buf[0x07] = 2;
+        LittleEndian::write_u16(&mut buf[0x08..], 0x8000);
+        LittleEndian::write_u16(&mut buf[0x0A..], 0x8003);
+        LittleEndian::write_u16(&mut buf[0x0C..], 0x8006);
+        buf[0x0E..0x13].copy_from_slice(b"Title");
+        buf[0x2E..0x34].copy_from_slice(b"Artist");
+        buf[0x4E..0x52].copy_from_slice(b"1986");
+        buf.extend_from_slice(&[0x60, 0x60, 0x60]);
+        buf
+    }
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0; 4];
+        LittleEndian::write_u32(&mut bytes, data.len() as u32);
+        bytes.extend_from_slice(id);
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    fn nsfe(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut buf = NSFE_MAGIC.to_vec();
+        for chunk in chunks {
+            buf.extend_from_slice(chunk);
+        }
+        buf
+    }
+
+    // Loaded at $8000 with INIT at $8003 and PLAY at $8006, PAL, VRC6 audio and 2 songs starting at the second one
+    fn info() -> Vec<u8> {
+        chunk(b"INFO", &[0x00, 0x80, 0x03, 0x80, 0x06, 0x80, 0x01, 0x01, 0x02, 0x01])
+    }
+
+    #[test]
+    fn nsf_header_fields() {
+        let nsf = parse_nsf(&nsf_header()).unwrap();
+        assert_eq!(nsf.format, NsfFormat::NSF);
+        assert_eq!(nsf.version, 1);
+        assert_eq!(nsf.songs, 3);
+        assert_eq!(nsf.starting_song, 1);
+        assert_eq!((nsf.load_address, nsf.init_address, nsf.play_address), (0x8000, 0x8003, 0x8006));
+        assert_eq!((&nsf.title[..], &nsf.artist[..], &nsf.copyright[..]), ("Title", "Artist", "1986"));
+        assert_eq!(nsf.banks, None);
+        assert_eq!(nsf.region, Region::NTSC);
+        assert_eq!(nsf.expansion, ExpansionAudio::empty());
+        assert_eq!(nsf.flags, Nsf2Flags::empty());
+        assert_eq!(nsf.data, vec![0x60, 0x60, 0x60]);
+        assert!(nsf.track_labels.is_empty() && nsf.track_times.is_empty());
+    }
+
+    #[test]
+    fn nsf_play_speeds() {
+        let nsf = parse_nsf(&nsf_header()).unwrap();
+        assert_eq!((nsf.ntsc_speed, nsf.pal_speed), (DEFAULT_NTSC_SPEED, DEFAULT_PAL_SPEED));
+
+        let mut buf = nsf_header();
+        LittleEndian::write_u16(&mut buf[0x6E..], 16666);
+        LittleEndian::write_u16(&mut buf[0x78..], 20000);
+        buf[0x7A] = 0b0000_0001;
+        let nsf = parse_nsf(&buf).unwrap();
+        assert_eq!((nsf.ntsc_speed, nsf.pal_speed), (16666, 20000));
+        assert_eq!(nsf.region, Region::PAL);
+
+        buf[0x7A] = 0b0000_0010;
+        assert_eq!(parse_nsf(&buf).unwrap().region, Region::Multi);
+    }
+
+    #[test]
+    fn nsf_bankswitch_bytes() {
+        let mut buf = nsf_header();
+        buf[0x70..0x78].copy_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7]);
+        buf[0x7B] = 0b0000_0101;
+        let nsf = parse_nsf(&buf).unwrap();
+        assert_eq!(nsf.banks, Some([0, 1, 2, 3, 4, 5, 6, 7]));
+        assert_eq!(nsf.expansion, ExpansionAudio::VRC6 | ExpansionAudio::FDS);
+    }
+
+    // Byte $7C only holds the NSF2 flags from version 2 on
+    #[test]
+    fn nsf2_flags() {
+        let mut buf = nsf_header();
+        buf[0x7C] = 0b0100_0000;
+        assert_eq!(parse_nsf(&buf).unwrap().flags, Nsf2Flags::empty());
+
+        buf[0x05] = 2;
+        assert_eq!(parse_nsf(&buf).unwrap().flags, Nsf2Flags::SUPPRESS_PLAY);
+    }
+
+    #[test]
+    fn nsf_errors() {
+        match parse_nsf(b"NESM\x1a\x01") {
+            Err(NsfError::TruncatedHeader(6)) => (),
+            result => panic!("Expected a truncated header, got {:?}", result.err())
+        }
+        match parse_nsf(b"NES\x1a") {
+            Err(NsfError::BadMagic) => (),
+            result => panic!("Expected a bad magic number, got {:?}", result.err())
+        }
+
+        let mut buf = nsf_header();
+        buf[0x06] = 0;
+        match parse_nsf(&buf) {
+            Err(NsfError::NoSongs) => (),
+            result => panic!("Expected no songs, got {:?}", result.err())
+        }
+    }
+
+    #[test]
+    fn nsfe_info_and_data() {
+        let nsf = parse_nsf(&nsfe(&[info(), chunk(b"DATA", &[0x60, 0x60, 0x60]), chunk(b"NEND", &[])])).unwrap();
+        assert_eq!(nsf.format, NsfFormat::NSFE);
+        assert_eq!((nsf.load_address, nsf.init_address, nsf.play_address), (0x8000, 0x8003, 0x8006));
+        assert_eq!(nsf.region, Region::PAL);
+        assert_eq!(nsf.expansion, ExpansionAudio::VRC6);
+        assert_eq!((nsf.songs, nsf.starting_song), (2, 1));
+        assert_eq!(nsf.data, vec![0x60, 0x60, 0x60]);
+        assert_eq!(nsf.banks, None);
+        assert_eq!((nsf.ntsc_speed, nsf.pal_speed), (DEFAULT_NTSC_SPEED, DEFAULT_PAL_SPEED));
+
+        // The starting song is optional
+        let short_info = chunk(b"INFO", &[0x00, 0x80, 0x03, 0x80, 0x06, 0x80, 0x00, 0x00, 0x02]);
+        assert_eq!(parse_nsf(&nsfe(&[short_info, chunk(b"DATA", &[0x60])])).unwrap().starting_song, 0);
+    }
+
+    #[test]
+    fn nsfe_bank_and_rate() {
+        let chunks = [info(), chunk(b"DATA", &[0x60]), chunk(b"BANK", &[1, 2, 3]), chunk(b"RATE", &[0x1A, 0x41, 0x20, 0x4E])];
+        let nsf = parse_nsf(&nsfe(&chunks)).unwrap();
+        assert_eq!(nsf.banks, Some([1, 2, 3, 0, 0, 0, 0, 0]));
+        assert_eq!((nsf.ntsc_speed, nsf.pal_speed), (16666, 20000));
+    }
+
+    #[test]
+    fn nsfe_metadata() {
+        let auth = chunk(b"auth", b"Title\0Artist\0Nintendo\0Ripper\0");
+        let tlbl = chunk(b"tlbl", b"Overworld\0Underworld\0Castle\0");
+        let mut times = vec![0; 8];
+        LittleEndian::write_i32(&mut times, 90_000);
+        LittleEndian::write_i32(&mut times[4..], -1);
+        let nsf = parse_nsf(&nsfe(&[info(), auth, tlbl, chunk(b"time", &times), chunk(b"DATA", &[0x60])])).unwrap();
+
+        assert_eq!((&nsf.title[..], &nsf.artist[..], &nsf.copyright[..]), ("Title", "Artist", "Nintendo"));
+        // There are only labels for as many songs as the INFO chunk says
+        assert_eq!(nsf.track_labels, vec!["Overworld".to_string(), "Underworld".to_string()]);
+        assert_eq!(nsf.track_times, vec![Some(90_000), None]);
+    }
+
+    #[test]
+    fn nsfe_expansion_chunks() {
+        let chunks = [info(), chunk(b"NSF2", &[0b0010_0000]), chunk(b"VRC7", &[0x01]), chunk(b"DATA", &[0x60])];
+        let nsf = parse_nsf(&nsfe(&chunks)).unwrap();
+        assert_eq!(nsf.flags, Nsf2Flags::NON_RETURNING_INIT);
+        assert_eq!(nsf.expansion, ExpansionAudio::VRC6 | ExpansionAudio::VRC7);
+    }
+
+    // Everything after NEND is ignored, as are unknown chunks starting with a lower case letter
+    #[test]
+    fn nsfe_skipped_chunks() {
+        let chunks = [info(), chunk(b"text", b"Notes"), chunk(b"DATA", &[0x60]), chunk(b"NEND", &[]), chunk(b"ABCD", &[])];
+        assert!(parse_nsf(&nsfe(&chunks)).is_ok());
+    }
+
+    #[test]
+    fn nsfe_errors() {
+        match parse_nsf(&nsfe(&[info(), chunk(b"ABCD", &[]), chunk(b"DATA", &[0x60])])) {
+            Err(NsfError::UnknownChunk(ref id)) if id == "ABCD" => (),
+            result => panic!("Expected an unknown chunk, got {:?}", result.err())
+        }
+        match parse_nsf(&nsfe(&[chunk(b"DATA", &[0x60])])) {
+            Err(NsfError::MissingChunk("INFO")) => (),
+            result => panic!("Expected a missing INFO chunk, got {:?}", result.err())
+        }
+        match parse_nsf(&nsfe(&[info()])) {
+            Err(NsfError::MissingChunk("DATA")) => (),
+            result => panic!("Expected a missing DATA chunk, got {:?}", result.err())
+        }
+        match parse_nsf(&nsfe(&[chunk(b"INFO", &[0x00, 0x80])])) {
+            Err(NsfError::TruncatedChunk { expected: 9, actual: 2, .. }) => (),
+            result => panic!("Expected a truncated INFO chunk, got {:?}", result.err())
+        }
+
+        let mut data = chunk(b"DATA", &[0x60, 0x60]);
+        data.pop();
+        match parse_nsf(&nsfe(&[info(), data])) {
+            Err(NsfError::TruncatedChunk { expected: 2, actual: 1, .. }) => (),
+            result => panic!("Expected a truncated DATA chunk, got {:?}", result.err())
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use apu::{Apu, NTSC_CLOCK_RATE, PAL_CLOCK_RATE};
+use cpu::{Address, Cpu};
+use cpu::memory::{Memory, WorkingMemory};
+use nes::nsf::{ExpansionAudio, Nsf, Nsf2Flags};
+use nes::rom::Region;
+
+// The driver lives in the unused space past the APU registers, INIT and PLAY return to it and the CPU idles in it
+// until the next PLAY call, by jumping to itself
+const DRIVER_ADDRESS: Address = 0x4100;
+const DRIVER_CODE: [u8; 3] = [0x4C, 0x00, 0x41];   // JMP $4100
+
+const BANK_LENGTH: usize = 0x1000;
+
+// The CPU memory map as an NSF player sets it up
+// Ref: https://wiki.nesdev.com/w/index.php/NSF#Bank_Switching
+// $0000-$07FF  2KB internal RAM, mirrored up to $1FFF
+// $4000-$4017  APU registers
+// $4100-$4102  The driver
+// $5FF6-$5FF7  Bank registers for $6000-$7FFF, only for music that uses the FDS
+// $5FF8-$5FFF  Bank registers for the eight 4KB banks at $8000-$FFFF
+// $6000-$7FFF  8KB RAM
+// $8000-$FFFF  The music, which is writable as well for music that uses the FDS
+// Banks are copied into place when they're switched in, which keeps the FDS case where they land in RAM simple
+struct NsfBus {
+    ram: WorkingMemory,
+    memory: Vec<u8>,                    // $6000-$FFFF
+    data: Vec<u8>,                      // The music, padded to start at a bank boundary when it's bankswitched
+    bankswitched: bool,
+    fds: bool,
+    apu: Apu,
+    samples: Rc<RefCell<Vec<i16>>>
+}
+
+impl NsfBus {
+    fn new(nsf: &Nsf, pal: bool, sample_rate: u32, samples: Rc<RefCell<Vec<i16>>>) -> NsfBus {
+        let mut bus = NsfBus {
+            ram: [0; 2048],
+            memory: vec![0; 0xA000],
+            data: vec![],
+            bankswitched: nsf.banks.is_some(),
+            fds: nsf.expansion.contains(ExpansionAudio::FDS),
+            apu: Apu::new(pal, sample_rate),
+            samples
+        };
+
+        match nsf.banks {
+            // The padding comes from the lower 12 bits of the load address
+            Some(banks) => {
+                bus.data = vec![0; (nsf.load_address as usize) & (BANK_LENGTH - 1)];
+                bus.data.extend_from_slice(&nsf.data);
+
+                // Music that uses the FDS gets the banks for $8000-$9FFF at $6000-$7FFF as well
+                if bus.fds {
+                    bus.switch_bank(0, banks[6]);
+                    bus.switch_bank(1, banks[7]);
+                }
+                for (slot, &bank) in banks.iter().enumerate() {
+                    bus.switch_bank(slot + 2, bank);
+                }
+            }
+            None => {
+                for (offset, &byte) in nsf.data.iter().enumerate() {
+                    match (nsf.load_address as usize + offset).checked_sub(0x6000) {
+                        Some(address) if address < bus.memory.len() => bus.memory[address] = byte,
+                        _ => ()
+                    }
+                }
+            }
+        }
+
+        bus
+    }
+
+    // Slots are the 4KB areas from $6000 up
+    fn switch_bank(&mut self, slot: usize, bank: u8) {
+        let start = bank as usize * BANK_LENGTH;
+        for offset in 0..BANK_LENGTH {
+            self.memory[slot * BANK_LENGTH + offset] = self.data.get(start + offset).cloned().unwrap_or(0);
+        }
+    }
+}
+
+impl Memory for NsfBus {
+    fn read(&mut self, address: Address) -> u8 {
+        match address {
+            0x4015 => self.apu.read(address),
+            _ => self.peek(address)
+        }
+    }
+
+    fn write(&mut self, address: Address, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram[(address & 0x07FF) as usize] = value,
+            0x4000..=0x4017 => self.apu.write(address, value),
+            0x5FF6..=0x5FF7 if self.fds => self.switch_bank((address - 0x5FF6) as usize, value),
+            0x5FF8..=0x5FFF if self.bankswitched => self.switch_bank((address - 0x5FF6) as usize, value),
+            0x6000..=0x7FFF => self.memory[(address - 0x6000) as usize] = value,
+            0x8000..=0xFFFF if self.fds => self.memory[(address - 0x6000) as usize] = value,
+            _ => ()
+        }
+    }
+
+    fn peek(&self, address: Address) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.ram[(address & 0x07FF) as usize],
+            0x4100..=0x4102 => DRIVER_CODE[(address - DRIVER_ADDRESS) as usize],
+            0x6000..=0xFFFF => self.memory[(address - 0x6000) as usize],
+            _ => 0
+        }
+    }
+
+    // The DMC fetches its samples in between, the cycles it steals from the CPU aren't emulated
+    fn clock(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            self.apu.clock();
+            if let Some(address) = self.apu.dmc_request() {
+                let value = self.peek(address);
+                self.apu.dmc_fill(value);
+            }
+        }
+        self.samples.borrow_mut().extend(self.apu.take_samples());
+    }
+
+    fn irq(&self) -> bool {
+        self.apu.irq()
+    }
+}
+
+// Plays a song (zero-based) for the given amount of seconds and returns the 16-bit mono samples
+// Only the 2A03 channels are played, expansion audio is silent. Music for both regions is played at NTSC speed.
+// Of the NSF2 features only suppressing PLAY is supported, a non-returning INIT never gives PLAY a chance to run.
+// Ref: https://wiki.nesdev.com/w/index.php/NSF#Initializing_a_tune
+pub fn render(nsf: &Nsf, song: u8, seconds: f64, sample_rate: u32) -> Vec<i16> {
+    let pal = nsf.region == Region::PAL;
+    let (clock_rate, speed) = if pal { (PAL_CLOCK_RATE, nsf.pal_speed) } else { (NTSC_CLOCK_RATE, nsf.ntsc_speed) };
+    let cycles_per_play = clock_rate * speed as f64 / 1_000_000.0;
+    let length = (seconds * sample_rate as f64) as usize;
+
+    let samples = Rc::new(RefCell::new(vec![]));
+    let mut cpu = Cpu::new(Box::new(NsfBus::new(nsf, pal, sample_rate, samples.clone())));
+    cpu.power_on();
+
+    // All channels but the DMC are enabled and the frame IRQ is turned off, which is what music rips expect
+    {
+        let bus = cpu.bus_mut();
+        bus.write(0x4015, 0x0F);
+        bus.write(0x4017, 0x40);
+    }
+
+    // INIT gets the song in A and the region in X, PLAY is called whenever the driver is idle and it's time for it
+    cpu.call(nsf.init_address, DRIVER_ADDRESS, song, pal as u8);
+    let mut next_play = 0.0;
+
+    while samples.borrow().len() < length {
+        if cpu.pc() == DRIVER_ADDRESS && cpu.cycles() as f64 >= next_play && !nsf.flags.contains(Nsf2Flags::SUPPRESS_PLAY) {
+            cpu.call(nsf.play_address, DRIVER_ADDRESS, 0, 0);
+            next_play = cpu.cycles() as f64 + cycles_per_play;
+        }
+        cpu.step();
+    }
+
+    let mut samples = samples.replace(vec![]);
+    samples.truncate(length);
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes::nsf::{NsfFormat, DEFAULT_NTSC_SPEED, DEFAULT_PAL_SPEED};
+
+    // INIT at $8000 plays a tone on the first pulse channel for song 2 in PAL mode, PLAY at $8008 always does
+    const PROGRAM: [u8; 24] = [
+        0xC9, 0x02,         // CMP #$02
+        0xD0, 0x13,         // BNE $8017
+        0xE0, 0x01,         // CPX #$01
+        0xD0, 0x0F,         // BNE $8017
+        0xA9, 0xBF,         // LDA #$BF, duty 2, halted length counter and a constant volume of 15
+        0x8D, 0x00, 0x40,   // STA $4000
+        0xA9, 0xFD,         // LDA #$FD
+        0x8D, 0x02, 0x40,   // STA $4002
+        0xA9, 0x00,         // LDA #$00
+        0x8D, 0x03, 0x40,   // STA $4003
+        0x60                // RTS
+    ];
+
+    fn nsf(load_address: u16, data: Vec<u8>, banks: Option<[u8; 8]>, expansion: ExpansionAudio) -> Nsf {
+        Nsf {
+            format: NsfFormat::NSF,
+            version: 1,
+            songs: 3,
+            starting_song: 0,
+            load_address,
+            init_address: 0x8000,
+            play_address: 0x8017,
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            ntsc_speed: DEFAULT_NTSC_SPEED,
+            pal_speed: DEFAULT_PAL_SPEED,
+            banks,
+            region: Region::NTSC,
+            expansion,
+            flags: Nsf2Flags::empty(),
+            track_labels: vec![],
+            track_times: vec![],
+            data
+        }
+    }
+
+    // Four 4KB banks, each filled with its own number
+    fn bank_data() -> Vec<u8> {
+        (0..4).flat_map(|bank| vec![bank; BANK_LENGTH]).collect()
+    }
+
+    fn bus(nsf: &Nsf) -> NsfBus {
+        NsfBus::new(nsf, false, 44100, Rc::new(RefCell::new(vec![])))
+    }
+
+    // Whether the second half of the samples is more than the DC offset the high-pass filter removes at the start
+    fn audible(samples: &[i16]) -> bool {
+        samples[samples.len() / 2..].iter().any(|&sample| sample.abs() > 1000)
+    }
+
+    #[test]
+    fn music_is_loaded_at_its_load_address() {
+        let bus = bus(&nsf(0x8010, vec![0x11, 0x22], None, ExpansionAudio::empty()));
+        assert_eq!((bus.peek(0x800F), bus.peek(0x8010), bus.peek(0x8011)), (0x00, 0x11, 0x22));
+        assert_eq!(bus.peek(0x4100), 0x4C);
+    }
+
+    #[test]
+    fn bank_registers() {
+        let mut bus = bus(&nsf(0x8000, bank_data(), Some([0, 1, 2, 3, 0, 1, 2, 3]), ExpansionAudio::empty()));
+        assert_eq!((bus.peek(0x8000), bus.peek(0x9FFF), bus.peek(0xC000), bus.peek(0xFFFF)), (0, 1, 0, 3));
+
+        bus.write(0x5FF8, 3);
+        bus.write(0x5FFF, 1);
+        assert_eq!((bus.peek(0x8000), bus.peek(0x8FFF), bus.peek(0xF000)), (3, 3, 1));
+
+        // Banks past the end of the music are empty, and the music can't be written to
+        bus.write(0x5FF9, 9);
+        bus.write(0x8000, 0xFF);
+        assert_eq!((bus.peek(0x9000), bus.peek(0x8000)), (0, 3));
+    }
+
+    // The lower 12 bits of the load address pad the first bank
+    #[test]
+    fn banks_are_padded_by_the_load_address() {
+        let bus = bus(&nsf(0x8010, bank_data(), Some([0, 1, 2, 3, 4, 5, 6, 7]), ExpansionAudio::empty()));
+        assert_eq!((bus.peek(0x800F), bus.peek(0x8010), bus.peek(0x9010)), (0, 0, 1));
+    }
+
+    #[test]
+    fn bank_registers_need_bankswitched_music() {
+        let mut bus = bus(&nsf(0x8000, bank_data(), None, ExpansionAudio::empty()));
+        bus.write(0x5FF8, 3);
+        assert_eq!(bus.peek(0x8000), 0);
+    }
+
+    // Music for the FDS starts with the banks for $8000-$9FFF at $6000-$7FFF, has two more bank registers for them
+    // and can write to its own banks
+    #[test]
+    fn fds_bank_registers() {
+        let mut bus = bus(&nsf(0x8000, bank_data(), Some([0, 1, 2, 3, 0, 1, 2, 3]), ExpansionAudio::FDS));
+        assert_eq!((bus.peek(0x6000), bus.peek(0x7000)), (2, 3));
+
+        bus.write(0x5FF6, 1);
+        bus.write(0x5FF7, 0);
+        assert_eq!((bus.peek(0x6000), bus.peek(0x7000)), (1, 0));
+
+        bus.write(0x8000, 0xFF);
+        assert_eq!(bus.peek(0x8000), 0xFF);
+
+        // Without the FDS the registers don't do anything
+        let mut bus = self::bus(&nsf(0x8000, bank_data(), Some([0, 1, 2, 3, 0, 1, 2, 3]), ExpansionAudio::empty()));
+        bus.write(0x5FF6, 1);
+        assert_eq!(bus.peek(0x6000), 0);
+    }
+
+    #[test]
+    fn init_gets_the_song_and_region() {
+        let mut music = nsf(0x8000, PROGRAM.to_vec(), None, ExpansionAudio::empty());
+        assert!(!audible(&render(&music, 2, 0.2, 44100)));
+
+        music.region = Region::PAL;
+        assert!(audible(&render(&music, 2, 0.2, 44100)));
+        assert!(!audible(&render(&music, 1, 0.2, 44100)));
+    }
+
+    #[test]
+    fn play_is_called_unless_suppressed() {
+        let mut music = nsf(0x8000, PROGRAM.to_vec(), None, ExpansionAudio::empty());
+        music.play_address = 0x8008;
+        assert!(audible(&render(&music, 0, 0.2, 44100)));
+
+        music.flags = Nsf2Flags::SUPPRESS_PLAY;
+        assert!(!audible(&render(&music, 0, 0.2, 44100)));
+    }
+}
@@ -0,0 +1,558 @@
+use std::error::Error;
+use std::fmt;
+
+use nes::hash::crc32;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const IPS_MAX_OFFSET: usize = 0xFF_FFFF;
+const IPS_MAX_RECORD: usize = 0xFFFF;
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+
+// UPS and BPS patches end with the CRC32 of the source, the target and the patch itself
+const FOOTER_LENGTH: usize = 12;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum PatchFormat {
+    IPS,
+    UPS,
+    BPS
+}
+
+#[derive(Debug)]
+pub enum PatchError {
+    UnknownFormat,                                  // The patch doesn't start with any of the known signatures
+    Truncated,                                      // The patch ends in the middle of a record
+    OutOfBounds,                                    // The patch reads outside of the source or target
+    SourceSize { expected: usize, actual: usize },
+    SourceChecksum { expected: u32, actual: u32 },  // The patch is meant for a different ROM
+    TargetChecksum { expected: u32, actual: u32 },
+    PatchChecksum { expected: u32, actual: u32 },   // The patch file itself is damaged
+    Unrepresentable(&'static str)                   // The difference between two files can't be expressed in the format
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PatchError::UnknownFormat                           => write!(f, "Not an IPS, UPS or BPS patch"),
+            PatchError::Truncated                               => write!(f, "Patch is truncated"),
+            PatchError::OutOfBounds                             => write!(f, "Patch refers to data outside of the ROM"),
+            PatchError::SourceSize { expected, actual }         => write!(f, "Patch expects a ROM of {} bytes, but it is {} bytes", expected, actual),
+            PatchError::SourceChecksum { expected, actual }     => write!(f, "Patch expects a ROM with CRC32 {:08x}, but it is {:08x}", expected, actual),
+            PatchError::TargetChecksum { expected, actual }     => write!(f, "Patched ROM should have CRC32 {:08x}, but it is {:08x}", expected, actual),
+            PatchError::PatchChecksum { expected, actual }      => write!(f, "Patch should have CRC32 {:08x}, but it is {:08x}", expected, actual),
+            PatchError::Unrepresentable(reason)                 => write!(f, "Patch can't be created: {}", reason)
+        }
+    }
+}
+
+impl Error for PatchError {}
+
+pub fn format(patch: &[u8]) -> Option<PatchFormat> {
+    if patch.starts_with(IPS_MAGIC) {
+        Some(PatchFormat::IPS)
+    } else if patch.starts_with(UPS_MAGIC) {
+        Some(PatchFormat::UPS)
+    } else if patch.starts_with(BPS_MAGIC) {
+        Some(PatchFormat::BPS)
+    } else {
+        None
+    }
+}
+
+// Applies a patch to a ROM file, the format is detected from the patch
+pub fn apply(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    match format(patch) {
+        Some(PatchFormat::IPS) => apply_ips(source, patch),
+        Some(PatchFormat::UPS) => apply_ups(source, patch),
+        Some(PatchFormat::BPS) => apply_bps(source, patch),
+        None => Err(PatchError::UnknownFormat)
+    }
+}
+
+// Creates a patch that turns the source into the target
+pub fn create(source: &[u8], target: &[u8], format: PatchFormat) -> Result<Vec<u8>, PatchError> {
+    match format {
+        PatchFormat::IPS => create_ips(source, target),
+        PatchFormat::UPS => Err(PatchError::Unrepresentable("creating UPS patches is not supported")),
+        PatchFormat::BPS => Ok(create_bps(source, target))
+    }
+}
+
+// IPS is a list of records that each overwrite a part of the file, with 3-byte offsets and 2-byte lengths
+// A length of 0 marks an RLE record, which repeats a single byte. After the EOF marker, an
+// optional 3-byte length truncates the file, which some tools use for patches that shrink a ROM
+// Ref: http://fileformats.archiveteam.org/wiki/IPS_(binary_patch_format)
+fn apply_ips(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut target = source.to_vec();
+    let mut reader = Reader::new(&patch[IPS_MAGIC.len()..]);
+
+    loop {
+        if reader.peek(IPS_EOF.len()) == Some(IPS_EOF) {
+            reader.skip(IPS_EOF.len());
+            break;
+        }
+
+        let offset = reader.u24()?;
+        let (length, data) = match reader.u16()? as usize {
+            0 => {
+                let length = reader.u16()? as usize;
+                (length, vec![reader.u8()?; length])
+            }
+            length => (length, reader.bytes(length)?.to_vec())
+        };
+
+        if target.len() < offset + length {
+            target.resize(offset + length, 0);
+        }
+        target[offset..offset + length].copy_from_slice(&data);
+    }
+
+    if let Ok(length) = reader.u24() {
+        target.truncate(length);
+    }
+
+    Ok(target)
+}
+
+fn create_ips(source: &[u8], target: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if target.len() > IPS_MAX_OFFSET {
+        return Err(PatchError::Unrepresentable("IPS can't address files larger than 16MB"));
+    }
+
+    let mut patch = IPS_MAGIC.to_vec();
+    let mut offset = 0;
+
+    while offset < target.len() {
+        if source.get(offset) == Some(&target[offset]) {
+            offset += 1;
+            continue;
+        }
+
+        // An offset that spells out "EOF" would be taken for the end of the patch, start a byte earlier instead
+        let start = if offset == 0x45_4F46 { offset - 1 } else { offset };
+        let mut end = offset;
+        while end < target.len() && end - start < IPS_MAX_RECORD && source.get(end) != Some(&target[end]) {
+            end += 1;
+        }
+
+        patch.extend_from_slice(&[(start >> 16) as u8, (start >> 8) as u8, start as u8]);
+        patch.extend_from_slice(&[((end - start) >> 8) as u8, (end - start) as u8]);
+        patch.extend_from_slice(&target[start..end]);
+        offset = end;
+    }
+
+    patch.extend_from_slice(IPS_EOF);
+    if target.len() < source.len() {
+        let length = target.len();
+        patch.extend_from_slice(&[(length >> 16) as u8, (length >> 8) as u8, length as u8]);
+    }
+
+    Ok(patch)
+}
+
+// UPS stores the sizes of both files followed by hunks of bytes that are XORed with the source
+// Ref: http://individual.utoronto.ca/dmeunier/ups-spec.pdf
+fn apply_ups(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let (body, source_crc, target_crc) = verify_footer(patch)?;
+    let mut reader = Reader::new(&body[UPS_MAGIC.len()..]);
+
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    if source_size != source.len() {
+        return Err(PatchError::SourceSize { expected: source_size, actual: source.len() });
+    }
+    check_source(source, source_crc)?;
+
+    let mut target = source.to_vec();
+    target.resize(target_size, 0);
+    let mut offset: usize = 0;
+
+    while !reader.is_empty() {
+        offset = offset.checked_add(reader.varint()?).ok_or(PatchError::OutOfBounds)?;
+        loop {
+            let value = reader.u8()?;
+            if value == 0 {
+                offset += 1;
+                break;
+            }
+            *target.get_mut(offset).ok_or(PatchError::OutOfBounds)? ^= value;
+            offset += 1;
+        }
+    }
+
+    check_target(&target, target_crc)?;
+    Ok(target)
+}
+
+// BPS builds the target from commands that copy from the source, the patch or the target itself
+// Ref: https://www.romhacking.net/documents/746/
+fn apply_bps(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let (body, source_crc, target_crc) = verify_footer(patch)?;
+    let mut reader = Reader::new(&body[BPS_MAGIC.len()..]);
+
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    let metadata_size = reader.varint()?;
+    reader.bytes(metadata_size)?;
+    if source_size != source.len() {
+        return Err(PatchError::SourceSize { expected: source_size, actual: source.len() });
+    }
+    check_source(source, source_crc)?;
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+
+    while !reader.is_empty() {
+        let command = reader.varint()?;
+        let length = (command >> 2) + 1;
+
+        match command & 0b11 {
+            // SourceRead, copies from the source at the current output position
+            0 => {
+                let start = target.len();
+                target.extend_from_slice(source.get(start..start + length).ok_or(PatchError::OutOfBounds)?);
+            }
+            // TargetRead, copies from the patch
+            1 => target.extend_from_slice(reader.bytes(length)?),
+            // SourceCopy, copies from anywhere in the source
+            2 => {
+                source_offset = relative_offset(source_offset, reader.varint()?)?;
+                target.extend_from_slice(source.get(source_offset..source_offset + length).ok_or(PatchError::OutOfBounds)?);
+                source_offset += length;
+            }
+            // TargetCopy, copies from earlier in the target, byte by byte as the ranges may overlap
+            _ => {
+                target_offset = relative_offset(target_offset, reader.varint()?)?;
+                for _ in 0..length {
+                    let value = *target.get(target_offset).ok_or(PatchError::OutOfBounds)?;
+                    target.push(value);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(PatchError::OutOfBounds);
+    }
+    check_target(&target, target_crc)?;
+    Ok(target)
+}
+
+// A straightforward BPS encoder, which reads unchanged runs from the source and changed runs from the patch
+fn create_bps(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch = BPS_MAGIC.to_vec();
+    write_varint(&mut patch, source.len());
+    write_varint(&mut patch, target.len());
+    write_varint(&mut patch, 0);
+
+    let unchanged = |offset: usize| source.get(offset) == Some(&target[offset]);
+    let mut offset = 0;
+
+    while offset < target.len() {
+        let same = unchanged(offset);
+        let mut end = offset;
+        while end < target.len() && unchanged(end) == same {
+            end += 1;
+        }
+
+        let length = end - offset;
+        write_varint(&mut patch, ((length - 1) << 2) | if same { 0 } else { 1 });
+        if !same {
+            patch.extend_from_slice(&target[offset..end]);
+        }
+        offset = end;
+    }
+
+    patch.extend_from_slice(&crc32(source).to_le_bytes());
+    patch.extend_from_slice(&crc32(target).to_le_bytes());
+    let patch_crc = crc32(&patch);
+    patch.extend_from_slice(&patch_crc.to_le_bytes());
+    patch
+}
+
+// Checks the CRC32 of the patch, and returns the patch without its footer along with the source and target CRC32
+fn verify_footer(patch: &[u8]) -> Result<(&[u8], u32, u32), PatchError> {
+    if patch.len() < UPS_MAGIC.len() + FOOTER_LENGTH {
+        return Err(PatchError::Truncated);
+    }
+
+    let (body, footer) = patch.split_at(patch.len() - FOOTER_LENGTH);
+    let mut reader = Reader::new(footer);
+    let (source_crc, target_crc, patch_crc) = (reader.u32_le()?, reader.u32_le()?, reader.u32_le()?);
+
+    let actual = crc32(&patch[..patch.len() - 4]);
+    if actual != patch_crc {
+        return Err(PatchError::PatchChecksum { expected: patch_crc, actual });
+    }
+    Ok((body, source_crc, target_crc))
+}
+
+fn check_source(source: &[u8], expected: u32) -> Result<(), PatchError> {
+    let actual = crc32(source);
+    if actual != expected {
+        return Err(PatchError::SourceChecksum { expected, actual });
+    }
+    Ok(())
+}
+
+fn check_target(target: &[u8], expected: u32) -> Result<(), PatchError> {
+    let actual = crc32(target);
+    if actual != expected {
+        return Err(PatchError::TargetChecksum { expected, actual });
+    }
+    Ok(())
+}
+
+// BPS offsets are relative to the previous copy, with the sign in the lowest bit
+fn relative_offset(offset: usize, data: usize) -> Result<usize, PatchError> {
+    let distance = data >> 1;
+    if data & 1 == 0 {
+        offset.checked_add(distance)
+    } else {
+        offset.checked_sub(distance)
+    }.ok_or(PatchError::OutOfBounds)
+}
+
+// Variable-length numbers as used by UPS and BPS, 7 bits per byte with the last byte flagged by bit 7
+fn write_varint(patch: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let bits = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            patch.push(0x80 | bits);
+            break;
+        }
+        patch.push(bits);
+        value -= 1;
+    }
+}
+
+// Reads the fields of a patch, running out of data means the patch is truncated
+struct Reader<'a> {
+    data: &'a [u8]
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn peek(&self, length: usize) -> Option<&'a [u8]> {
+        self.data.get(..length)
+    }
+
+    fn skip(&mut self, length: usize) {
+        self.data = &self.data[length.min(self.data.len())..];
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], PatchError> {
+        let bytes = self.peek(length).ok_or(PatchError::Truncated)?;
+        self.skip(length);
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, PatchError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, PatchError> {
+        let bytes = self.bytes(2)?;
+        Ok(((bytes[0] as u16) << 8) | bytes[1] as u16)
+    }
+
+    fn u24(&mut self) -> Result<usize, PatchError> {
+        let bytes = self.bytes(3)?;
+        Ok(((bytes[0] as usize) << 16) | ((bytes[1] as usize) << 8) | bytes[2] as usize)
+    }
+
+    fn u32_le(&mut self) -> Result<u32, PatchError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn varint(&mut self) -> Result<usize, PatchError> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.u8()?;
+            value = ((byte & 0x7F) as usize).checked_mul(shift).and_then(|bits| value.checked_add(bits)).ok_or(PatchError::OutOfBounds)?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_shl(7).ok_or(PatchError::OutOfBounds)?;
+            value = value.checked_add(shift).ok_or(PatchError::OutOfBounds)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Appends the UPS/BPS footer with the CRC32 of the source, the target and the patch
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        let patch_crc = crc32(&patch);
+        patch.extend_from_slice(&patch_crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn ips_records() {
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x02, 0xAA, 0xBB]);           // 2 bytes at $02
+        patch.extend_from_slice(&[0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x03, 0xCC]);     // RLE, 3 times $CC at $05
+        patch.extend_from_slice(&[0x00, 0x00, 0x0A, 0x00, 0x01, 0xDD]);                 // Past the end of the file
+        patch.extend_from_slice(IPS_EOF);
+
+        assert_eq!(apply(&[0; 8], &patch).unwrap(), vec![0, 0, 0xAA, 0xBB, 0, 0xCC, 0xCC, 0xCC, 0, 0, 0xDD]);
+    }
+
+    #[test]
+    fn ips_truncation() {
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend_from_slice(IPS_EOF);
+        patch.extend_from_slice(&[0x00, 0x00, 0x04]);
+
+        assert_eq!(apply(&[1, 2, 3, 4, 5, 6], &patch).unwrap(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn ips_truncated_record() {
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x04, 0xAA]);
+
+        match apply(&[0; 8], &patch) {
+            Err(PatchError::Truncated) => (),
+            result => panic!("Expected a truncated patch, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn unknown_format() {
+        match apply(&[0; 8], b"NOT A PATCH") {
+            Err(PatchError::UnknownFormat) => (),
+            result => panic!("Expected an unknown format, got {:?}", result)
+        }
+    }
+
+    // Turns 1, 2, 3, 4 into 1, 9, 3, 4, 5 with two hunks
+    fn ups_patch(source: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut patch = UPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0x84, 0x85]);                 // Source and target size
+        patch.extend_from_slice(&[0x81, 2 ^ 9, 0x00]);          // Skip 1 byte, XOR 1 byte
+        patch.extend_from_slice(&[0x81, 5, 0x00]);              // Skip 1 more byte past the terminator, XOR the new byte
+        with_footer(patch, source, target)
+    }
+
+    #[test]
+    fn ups_hunks() {
+        let (source, target) = ([1, 2, 3, 4], [1, 9, 3, 4, 5]);
+        assert_eq!(apply(&source, &ups_patch(&source, &target)).unwrap(), target.to_vec());
+    }
+
+    #[test]
+    fn ups_checksums() {
+        let (source, target) = ([1, 2, 3, 4], [1, 9, 3, 4, 5]);
+
+        match apply(&[1, 2, 3], &ups_patch(&source, &target)) {
+            Err(PatchError::SourceSize { expected: 4, actual: 3 }) => (),
+            result => panic!("Expected a source size mismatch, got {:?}", result)
+        }
+        match apply(&[4, 3, 2, 1], &ups_patch(&source, &target)) {
+            Err(PatchError::SourceChecksum { .. }) => (),
+            result => panic!("Expected a source checksum mismatch, got {:?}", result)
+        }
+        match apply(&source, &ups_patch(&source, &[1, 2, 3, 4, 5])) {
+            Err(PatchError::TargetChecksum { .. }) => (),
+            result => panic!("Expected a target checksum mismatch, got {:?}", result)
+        }
+
+        let mut damaged = ups_patch(&source, &target);
+        damaged[6] ^= 0xFF;
+        match apply(&source, &damaged) {
+            Err(PatchError::PatchChecksum { .. }) => (),
+            result => panic!("Expected a patch checksum mismatch, got {:?}", result)
+        }
+    }
+
+    // Uses every BPS command, with a source copy going backwards and a target copy that overlaps itself
+    fn bps_patch(source: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0x84, 0x89, 0x80]);           // Source size, target size and no metadata
+        patch.extend_from_slice(&[0x84]);                       // SourceRead 2 bytes: 10, 20
+        patch.extend_from_slice(&[0x81, 99]);                   // TargetRead 1 byte: 99
+        patch.extend_from_slice(&[0x86, 0x84]);                 // SourceCopy 2 bytes from +2: 30, 40
+        patch.extend_from_slice(&[0x82, 0x89]);                 // SourceCopy 1 byte from -4: 10
+        patch.extend_from_slice(&[0x8B, 0x88]);                 // TargetCopy 3 bytes from +4: 40, 10, 40
+        with_footer(patch, source, target)
+    }
+
+    #[test]
+    fn bps_commands() {
+        let source = [10, 20, 30, 40];
+        let target = [10, 20, 99, 30, 40, 10, 40, 10, 40];
+        assert_eq!(apply(&source, &bps_patch(&source, &target)).unwrap(), target.to_vec());
+    }
+
+    #[test]
+    fn bps_checksums() {
+        let source = [10, 20, 30, 40];
+        let target = [10, 20, 99, 30, 40, 10, 40, 10, 40];
+
+        match apply(&[40, 30, 20, 10], &bps_patch(&source, &target)) {
+            Err(PatchError::SourceChecksum { .. }) => (),
+            result => panic!("Expected a source checksum mismatch, got {:?}", result)
+        }
+        match apply(&source, &bps_patch(&source, &[0; 9])) {
+            Err(PatchError::TargetChecksum { .. }) => (),
+            result => panic!("Expected a target checksum mismatch, got {:?}", result)
+        }
+
+        let mut damaged = bps_patch(&source, &target);
+        damaged[8] ^= 0xFF;
+        match apply(&source, &damaged) {
+            Err(PatchError::PatchChecksum { .. }) => (),
+            result => panic!("Expected a patch checksum mismatch, got {:?}", result)
+        }
+    }
+
+    fn assert_round_trip(source: &[u8], target: &[u8], format: PatchFormat) {
+        let patch = create(source, target, format).unwrap();
+        assert_eq!(self::format(&patch), Some(format));
+        assert_eq!(apply(source, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn round_trips() {
+        let source: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+        let mut changed = source.clone();
+        changed[0] = 0xFF;
+        changed[500..520].iter_mut().for_each(|byte| *byte = 0);
+        let mut grown = changed.clone();
+        grown.extend_from_slice(&[1, 2, 3]);
+
+        for &format in &[PatchFormat::IPS, PatchFormat::BPS] {
+            assert_round_trip(&source, &changed, format);
+            assert_round_trip(&source, &grown, format);
+            assert_round_trip(&source, &source[..900], format);
+        }
+    }
+
+    #[test]
+    fn ips_avoids_eof_offset() {
+        let source = vec![0; 0x45_4F50];
+        let mut target = source.clone();
+        target[0x45_4F46] = 1;
+        assert_round_trip(&source, &target, PatchFormat::IPS);
+    }
+}
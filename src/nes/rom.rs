@@ -1,19 +1,40 @@
-use std::str;
-use std::fs::File;
-use std::io::Cursor;
-use std::io::Read;
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::io;
 
-use nom::{IResult, be_u8};
+use nom::IResult;
 
+use nes::database::Database;
+use nes::patch;
+use nes::patch::PatchError;
+use nes::unif::{parse_unif, UNIF_MAGIC};
+
+pub const HEADER_LENGTH: usize = 16;
 pub const TRAINER_LENGTH: usize = 512;
 pub const PRG_ROM_PAGE_LENGTH: usize = 16384;
 pub const CHR_ROM_PAGE_LENGTH: usize = 8192;
+pub const PRG_RAM_PAGE_LENGTH: usize = 8192;
+
+// NES 2.0 has no flag for bus conflicts, the discrete mappers that may or may not have them use a submapper instead
+// Ref: https://wiki.nesdev.com/w/index.php/NES_2.0_submappers#002.2C_003.2C_007:_UxROM.2C_CNROM.2C_AxROM
+const BUS_CONFLICT_MAPPERS: [u16; 3] = [2, 3, 7];
+const BUS_CONFLICTS_SUBMAPPER: u8 = 2;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Format {
+    INES,
+    NES20,
+    UNIF
+}
 
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub enum ScreenMode {
     Horizontal,
     Vertical,
     FourScreen,
+    OneScreenLower,     // Every nametable shows the first 1KB of VRAM
+    OneScreenUpper,     // Every nametable shows the second 1KB of VRAM
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
@@ -21,12 +42,49 @@ pub enum System {
     NES,
     VsUnisystem,
     PlayChoice10,
+    Extended(u8),   // NES 2.0 extended console type, eg: Famiclone with decimal mode
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub enum Region {
     PAL,
-    NTSC
+    NTSC,
+    Multi,          // Runs on both NTSC and PAL machines
+    Dendy
+}
+
+// The PPU found in a Vs. System cabinet, these differ in their palettes and register layout
+// Ref: https://wiki.nesdev.com/w/index.php/NES_2.0#Vs._System_Type
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum VsPpu {
+    RP2C03B,
+    RP2C03G,
+    RP2C04_0001,
+    RP2C04_0002,
+    RP2C04_0003,
+    RP2C04_0004,
+    RC2C03B,
+    RC2C03C,
+    RC2C05_01,
+    RC2C05_02,
+    RC2C05_03,
+    RC2C05_04,
+    RC2C05_05,
+    Reserved(u8)
+}
+
+// The Vs. System hardware and the copy protection it uses
+// Ref: https://wiki.nesdev.com/w/index.php/NES_2.0#Vs._System_Type
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum VsHardware {
+    Unisystem,
+    UnisystemRbiBaseball,
+    UnisystemTkoBoxing,
+    UnisystemSuperXevious,
+    UnisystemIceClimber,
+    DualSystem,
+    DualSystemRaidOnBungelingBay,
+    Reserved(u8)
 }
 
 // Structure for the Flags6 bitflags:
@@ -49,6 +107,7 @@ bitflags! {
         const VS_UNISYSTEM =    0b0000_0001;
         const PLAYCHOICE_10 =   0b0000_0010;
         const NES_20 =          0b0000_1100;
+        const UPPER_NYBBLE =    0b1111_0000;
     }
 }
 
@@ -60,6 +119,17 @@ bitflags! {
     }
 }
 
+// Structure for the Flags10 bitflags, which are unofficial and rarely used
+// Ref: https://wiki.nesdev.com/w/index.php/INES#Flags_10
+bitflags! {
+    struct Flags10: u8 {
+        const DUAL_COMPATIBLE = 0b0000_0001;    // Runs on both NTSC and PAL machines
+        const PAL =             0b0000_0010;    // Only when it isn't dual compatible
+        const NO_PRG_RAM =      0b0001_0000;    // (0: PRG-RAM at $6000-$7FFF present; 1: not present)
+        const BUS_CONFLICTS =   0b0010_0000;
+    }
+}
+
 impl Into<ScreenMode> for Flags6 {
     fn into(self) -> ScreenMode {
         if self.contains(Flags6::FOUR_SCREEN) {
@@ -94,89 +164,941 @@ impl Into<Region> for Flags9 {
     }
 }
 
-// Note: this is currently only supports the iNES format, not the NES2.0 format
+// Supports the iNES and the NES 2.0 format, and UNIF through unif.rs
 // Ref: https://wiki.nesdev.com/w/index.php/INES
+// Ref: https://wiki.nesdev.com/w/index.php/NES_2.0
 #[derive(Debug)]
 pub struct ROM {
     pub header: Header,
     pub prg_rom: Vec<u8>,
-    pub chr_rom: Vec<u8>
+    pub chr_rom: Vec<u8>,
+    pub trainer: Option<Vec<u8>>,   // 512 bytes meant to be loaded at $7000-$71FF
+    pub warnings: Vec<String>       // Heuristics that had to be applied to make sense of the header
 }
 
 impl ROM {
-    fn new(header: Header, prg_rom: Vec<u8>, chr_rom: Vec<u8>) -> ROM {
-        ROM { header: header, prg_rom: prg_rom, chr_rom: chr_rom }
+    pub fn new(header: Header, prg_rom: Vec<u8>, chr_rom: Vec<u8>, trainer: Option<Vec<u8>>, warnings: Vec<String>) -> ROM {
+        ROM { header: header, prg_rom: prg_rom, chr_rom: chr_rom, trainer: trainer, warnings: warnings }
+    }
+
+    // Serializes the ROM as a file in the given format, the inverse of load
+    pub fn to_bytes(&self, format: Format) -> Result<Vec<u8>, RomError> {
+        let mut bytes = self.header.to_bytes(format)?.to_vec();
+        if let Some(ref trainer) = self.trainer {
+            bytes.extend_from_slice(trainer);
+        }
+        bytes.extend_from_slice(&self.prg_rom);
+        bytes.extend_from_slice(&self.chr_rom);
+        Ok(bytes)
     }
 }
 
 #[derive(Debug)]
 pub struct Header {
-    pub prg_size: usize,
-    pub chr_size: usize,
+    pub format: Format,
+    pub prg_size: usize,                // Size of the PRG-ROM in bytes
+    pub chr_size: usize,                // Size of the CHR-ROM in bytes
     pub trainer: bool,
+    pub battery: bool,                  // Whether the PRG-RAM, or NVRAM for NES 2.0, is battery-backed and should be saved
     pub screen_mode: ScreenMode,
     pub system: System,
     pub region: Region,
-    pub mapper: u8
+    pub mapper: u16,
+    pub submapper: u8,                  // Only specified by NES 2.0, 0 otherwise
+    pub bus_conflicts: bool,            // From the unofficial iNES flags 10, NES 2.0 uses submapper 2 of mappers 2, 3 and 7
+    pub prg_ram_size: usize,            // Sizes of the volatile and battery-backed RAM in bytes, iNES only knows the PRG-RAM size
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub vs_ppu: Option<VsPpu>,          // Only set for Vs. System NES 2.0 ROMs
+    pub vs_hardware: Option<VsHardware>,
+    pub misc_roms: u8,                  // Number of miscellaneous ROMs following the CHR-ROM
+    pub expansion_device: u8,           // Default expansion device, see https://wiki.nesdev.com/w/index.php/NES_2.0#Default_Expansion_Device
+    pub board: Option<String>           // Board name, only known for UNIF
+}
+
+// Everything that can go wrong while loading a ROM
+#[derive(Debug)]
+pub enum RomError {
+    BadMagic,                                           // The file doesn't start with "NES<EOF>"
+    TruncatedHeader(usize),                             // The file ends within the 16 byte header
+    TruncatedTrainer(usize),                            // The file ends within the 512 byte trainer
+    TruncatedPrg { expected: usize, actual: usize },    // The file ends before the end of the PRG-ROM
+    TruncatedChr { expected: usize, actual: usize },    // The file ends before the end of the CHR-ROM
+    TruncatedChunk { id: String, expected: usize, actual: usize },  // The file ends within a UNIF chunk
+    MissingBoard,                                       // A UNIF file without a MAPR chunk
+    UnknownBoard(String),                               // A UNIF board that can't be mapped to a mapper
+    UnsupportedMapper(u16),
+    ReservedBits { byte: usize, value: u8 },            // A header byte has bits set that should be 0
+    Unrepresentable(&'static str),                      // The header can't be written in the requested format
+    Patch(PatchError),
+    Io(io::Error)
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RomError::BadMagic                              => write!(f, "Not an iNES, NES 2.0 or UNIF file, the header does not start with \"NES<EOF>\" or \"UNIF\""),
+            RomError::TruncatedHeader(actual)               => write!(f, "Header is truncated, expected {} bytes but found {}", HEADER_LENGTH, actual),
+            RomError::TruncatedTrainer(actual)              => write!(f, "Trainer is truncated, expected {} bytes but found {}", TRAINER_LENGTH, actual),
+            RomError::TruncatedPrg { expected, actual }     => write!(f, "PRG-ROM is truncated, expected {} bytes but found {}", expected, actual),
+            RomError::TruncatedChr { expected, actual }     => write!(f, "CHR-ROM is truncated, expected {} bytes but found {}", expected, actual),
+            RomError::TruncatedChunk { ref id, expected, actual } => write!(f, "{} chunk is truncated, expected {} bytes but found {}", id, expected, actual),
+            RomError::MissingBoard                          => write!(f, "UNIF file doesn't name its board"),
+            RomError::UnknownBoard(ref board)               => write!(f, "Unknown UNIF board {:?}", board),
+            RomError::UnsupportedMapper(mapper)             => write!(f, "Unsupported mapper {}", mapper),
+            RomError::ReservedBits { byte, value }          => write!(f, "Header byte {} (${:02X}) has reserved bits set", byte, value),
+            RomError::Unrepresentable(reason)               => write!(f, "Header can't be written in this format: {}", reason),
+            RomError::Patch(ref err)                        => write!(f, "Failed to apply patch: {}", err),
+            RomError::Io(ref err)                           => write!(f, "Failed to read ROM: {}", err)
+        }
+    }
+}
+
+impl Error for RomError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            RomError::Patch(ref err) => Some(err),
+            RomError::Io(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<PatchError> for RomError {
+    fn from(err: PatchError) -> RomError {
+        RomError::Patch(err)
+    }
+}
+
+impl From<io::Error> for RomError {
+    fn from(err: io::Error) -> RomError {
+        RomError::Io(err)
+    }
 }
 
-pub fn load_from_file(file: &mut File) -> Result<ROM, &'static str> {
-    let mut buf: Vec<u8> = vec![];
-    file.read_to_end(&mut buf);
-    load(&mut buf)
+// Loads a ROM, correcting iNES headers with the game database when the dump is known
+// NES 2.0 headers are trusted as they are
+pub fn load(buf: &[u8]) -> Result<ROM, RomError> {
+    load_with_patch(buf, None)
 }
 
-pub fn load(buf: &mut Vec<u8>) -> Result<ROM, &'static str> {
-    match parse_ines(&buf) {
-        IResult::Incomplete(needed) => Err("Failed to parse ROM file"),
-        IResult::Done(_, val)       => Ok(val),
-        _                           => Err("Failed to parse ROM file")
+// Loads a ROM after applying an IPS, UPS or BPS patch to the file
+// Archives have to be extracted beforehand, see archive::extract
+pub fn load_with_patch(buf: &[u8], patch: Option<&[u8]>) -> Result<ROM, RomError> {
+    let buf = match patch {
+        Some(patch) => Cow::Owned(patch::apply(buf, patch)?),
+        None => Cow::Borrowed(buf)
+    };
+
+    let mut rom = if buf.starts_with(UNIF_MAGIC) { parse_unif(&buf)? } else { parse_ines(&buf)? };
+
+    if rom.header.format == Format::INES {
+        correct_ines(&mut rom, Database::default_database());
+    }
+
+    Ok(rom)
+}
+
+// iNES headers leave out too much to be trusted, so they're corrected with the database entry of the dump
+// NROM boards don't have PRG-RAM apart from Family BASIC, but iNES headers always claim 8KB of it. So it's only kept
+// when the database gives its size, the Family BASIC versions have 2KB or 4KB which is usually battery-backed.
+fn correct_ines(rom: &mut ROM, database: &Database) {
+    let entry = database.find(&rom.prg_rom, &rom.chr_rom);
+    if let Some(entry) = entry {
+        let corrections = entry.apply(&mut rom.header);
+        rom.warnings.extend(corrections);
+    }
+
+    if rom.header.mapper == 0 && entry.and_then(|entry| entry.prg_ram_size).is_none() {
+        rom.header.prg_ram_size = 0;
     }
 }
 
 impl Header {
-    fn new(prg_size: usize, chr_size: usize, flags6: u8, flags7: u8, prg_ram: u8, flags9: u8, flags10: u8) -> Header {
-        let flg6 = Flags6::from_bits(flags6).expect("Failed to parse bit-flags from ROM"); // Parse the u8 into a Flags6 bitflag structure
-        let flg7 = Flags7::from_bits(flags7).expect("Failed to parse bit-flags from ROM"); // Parse the u8 into a Flags7 bitflag structure
-        let flg9 = Flags9::from_bits(flags9).expect("Failed to parse bit-flags from ROM"); // Parse the u8 into a Flags9 bitflag structure
+    fn new(raw: &[u8], warnings: &mut Vec<String>) -> Result<Header, RomError> {
+        if raw[7] & 0b0000_1100 == 0b0000_1000 {
+            Ok(Header::nes20(raw))
+        } else {
+            Header::ines(raw, warnings)
+        }
+    }
 
-        Header {
-            prg_size: prg_size,
-            chr_size: chr_size,
+    fn ines(raw: &[u8], warnings: &mut Vec<String>) -> Result<Header, RomError> {
+        let (flags6, mut flags7, mut prg_ram, mut flags9, mut flags10) = (raw[6], raw[7], raw[8], raw[9], raw[10]);
+
+        // Bytes 12-15 are always 0 in a proper iNES header. Old tools wrote their name over bytes 7-15,
+        // so when they're not, bytes 7-15 can't be trusted and the mapper is limited to the lower nibble
+        // Ref: https://wiki.nesdev.com/w/index.php/INES#Variant_comparison
+        if raw[12..16].iter().any(|&byte| byte != 0) {
+            if &raw[7..16] == b"DiskDude!" {
+                warnings.push("Header contains the \"DiskDude!\" signature, ignored bytes 7-15 and used a 4-bit mapper number".to_string());
+            } else {
+                warnings.push("Header bytes 12-15 are not zero, ignored bytes 7-15 and used a 4-bit mapper number".to_string());
+            }
+            flags7 = 0;
+            prg_ram = 0;
+            flags9 = 0;
+            flags10 = 0;
+        }
+
+        // Every bit of flags 6 and 7 has a meaning, only flags 9 has reserved bits
+        let flg6 = Flags6::from_bits_truncate(flags6);
+        let flg7 = Flags7::from_bits_truncate(flags7);
+        let flg9 = Flags9::from_bits(flags9).ok_or(RomError::ReservedBits { byte: 9, value: flags9 })?; // Parse the u8 into a Flags9 bitflag structure
+        let flg10 = Flags10::from_bits_truncate(flags10);
+
+        // Flags 10 can mark a ROM as PAL or as running on both as well, but flags 9 is the official one
+        let region = match flg9.into() {
+            Region::NTSC if flg10.contains(Flags10::DUAL_COMPATIBLE) => Region::Multi,
+            Region::NTSC if flg10.contains(Flags10::PAL) => Region::PAL,
+            region => region
+        };
+
+        // PRG-RAM is given in 8KB units, where 0 means 8KB for compatibility with dumps that predate the field
+        // Ref: https://wiki.nesdev.com/w/index.php/INES#Flags_8
+        let prg_ram_size = if flg10.contains(Flags10::NO_PRG_RAM) {
+            0
+        } else {
+            prg_ram.max(1) as usize * PRG_RAM_PAGE_LENGTH
+        };
+
+        Ok(Header {
+            format: Format::INES,
+            prg_size: raw[4] as usize * PRG_ROM_PAGE_LENGTH,
+            chr_size: raw[5] as usize * CHR_ROM_PAGE_LENGTH,
             trainer: flg6.contains(Flags6::TRAINER),
+            battery: flg6.contains(Flags6::SRAM),
             screen_mode: flg6.into(),
             system: flg7.into(),
-            region: flg9.into(),
-            mapper: (flags7 << 4) | flags6
+            region,
+            mapper: ((flags7 & 0xF0) | (flags6 >> 4)) as u16,
+            submapper: 0,
+            bus_conflicts: flg10.contains(Flags10::BUS_CONFLICTS),
+            prg_ram_size,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            vs_ppu: None,
+            vs_hardware: None,
+            misc_roms: 0,
+            expansion_device: 0,
+            board: None
+        })
+    }
+
+    // Serializes the header in the given format
+    // Fields iNES doesn't know about are dropped, but a header that would load as a different game is refused
+    pub fn to_bytes(&self, format: Format) -> Result<[u8; HEADER_LENGTH], RomError> {
+        let mut raw = [0; HEADER_LENGTH];
+        raw[0..4].copy_from_slice(b"NES\x1a");
+
+        // Boards with one-screen mirroring select the nametable themselves, there's no bit for it in the header
+        if self.screen_mode == ScreenMode::OneScreenLower || self.screen_mode == ScreenMode::OneScreenUpper {
+            return Err(RomError::Unrepresentable("one-screen mirroring has no header bit"));
+        }
+
+        let mut flg6 = Flags6::from_bits_truncate((self.mapper as u8) << 4);
+        flg6.set(Flags6::VERTICAL, self.screen_mode == ScreenMode::Vertical);
+        flg6.set(Flags6::SRAM, self.battery);
+        flg6.set(Flags6::TRAINER, self.trainer);
+        flg6.set(Flags6::FOUR_SCREEN, self.screen_mode == ScreenMode::FourScreen);
+        raw[6] = flg6.bits();
+        raw[7] = self.mapper as u8 & 0xF0;
+
+        match format {
+            Format::INES => {
+                if self.mapper > 0xFF {
+                    return Err(RomError::Unrepresentable("iNES mappers are limited to 8 bits"));
+                }
+                raw[4] = ines_pages(self.prg_size, PRG_ROM_PAGE_LENGTH).ok_or(RomError::Unrepresentable("PRG-ROM size is not a whole amount of 16KB pages"))?;
+                raw[5] = ines_pages(self.chr_size, CHR_ROM_PAGE_LENGTH).ok_or(RomError::Unrepresentable("CHR-ROM size is not a whole amount of 8KB pages"))?;
+                raw[7] |= match self.system {
+                    System::VsUnisystem  => Flags7::VS_UNISYSTEM.bits(),
+                    System::PlayChoice10 => Flags7::PLAYCHOICE_10.bits(),
+                    _                    => 0
+                };
+
+                // The size is written out, so 8KB becomes 1 rather than the 0 that older dumps use for it. The lack of
+                // PRG-RAM can only be written in flags 10.
+                let prg_ram_size = self.prg_ram_size + self.prg_nvram_size;
+                raw[8] = (prg_ram_size / PRG_RAM_PAGE_LENGTH).min(0xFF) as u8;
+                raw[9] = if self.region == Region::PAL || self.region == Region::Dendy { Flags9::TV_SYSTEM.bits() } else { 0 };
+
+                let mut flg10 = Flags10::empty();
+                flg10.set(Flags10::DUAL_COMPATIBLE, self.region == Region::Multi);
+                flg10.set(Flags10::NO_PRG_RAM, prg_ram_size == 0);
+                flg10.set(Flags10::BUS_CONFLICTS, self.bus_conflicts);
+                raw[10] = flg10.bits();
+            }
+            Format::NES20 => {
+                let (prg_lsb, prg_msb) = nes20_rom_size_bytes(self.prg_size, PRG_ROM_PAGE_LENGTH).ok_or(RomError::Unrepresentable("PRG-ROM size has no NES 2.0 encoding"))?;
+                let (chr_lsb, chr_msb) = nes20_rom_size_bytes(self.chr_size, CHR_ROM_PAGE_LENGTH).ok_or(RomError::Unrepresentable("CHR-ROM size has no NES 2.0 encoding"))?;
+                let ram_shift = |size| nes20_ram_shift(size).ok_or(RomError::Unrepresentable("RAM sizes must be 0 or a power of two of at least 128 bytes"));
+
+                raw[4] = prg_lsb;
+                raw[5] = chr_lsb;
+                raw[7] |= 0b0000_1000 | match self.system {
+                    System::NES          => 0,
+                    System::VsUnisystem  => 1,
+                    System::PlayChoice10 => 2,
+                    System::Extended(_)  => 3
+                };
+                let submapper = match (self.bus_conflicts, self.submapper) {
+                    (true, 0) if BUS_CONFLICT_MAPPERS.contains(&self.mapper) => BUS_CONFLICTS_SUBMAPPER,
+                    (true, submapper) if submapper != BUS_CONFLICTS_SUBMAPPER || !BUS_CONFLICT_MAPPERS.contains(&self.mapper) => {
+                        return Err(RomError::Unrepresentable("bus conflicts are only a submapper of mappers 2, 3 and 7"));
+                    }
+                    (_, submapper) => submapper
+                };
+                raw[8] = (submapper << 4) | (self.mapper >> 8) as u8 & 0x0F;
+                raw[9] = (chr_msb << 4) | prg_msb;
+                raw[10] = (ram_shift(self.prg_nvram_size)? << 4) | ram_shift(self.prg_ram_size)?;
+                raw[11] = (ram_shift(self.chr_nvram_size)? << 4) | ram_shift(self.chr_ram_size)?;
+                raw[12] = match self.region {
+                    Region::NTSC  => 0,
+                    Region::PAL   => 1,
+                    Region::Multi => 2,
+                    Region::Dendy => 3
+                };
+                raw[13] = match self.system {
+                    System::Extended(console) => console & 0x0F,
+                    _ => (u8::from(self.vs_hardware.unwrap_or(VsHardware::Unisystem)) << 4) | u8::from(self.vs_ppu.unwrap_or(VsPpu::RP2C03B))
+                };
+                raw[14] = self.misc_roms & 0b0000_0011;
+                raw[15] = self.expansion_device & 0b0011_1111;
+            }
+            Format::UNIF => return Err(RomError::Unrepresentable("ROMs can only be written as iNES or NES 2.0"))
+        }
+
+        Ok(raw)
+    }
+
+    // Ref: https://wiki.nesdev.com/w/index.php/NES_2.0
+    fn nes20(raw: &[u8]) -> Header {
+        let flg6 = Flags6::from_bits_truncate(raw[6]);
+
+        let system = match raw[7] & 0b0000_0011 {
+            0 => System::NES,
+            1 => System::VsUnisystem,
+            2 => System::PlayChoice10,
+            _ => System::Extended(raw[13] & 0x0F)
+        };
+
+        let mapper = ((raw[8] as u16 & 0x0F) << 8) | (raw[7] & 0xF0) as u16 | (raw[6] >> 4) as u16;
+        let submapper = raw[8] >> 4;
+
+        let (vs_ppu, vs_hardware) = if system == System::VsUnisystem {
+            (Some(VsPpu::from(raw[13] & 0x0F)), Some(VsHardware::from(raw[13] >> 4)))
+        } else {
+            (None, None)
+        };
+
+        Header {
+            format: Format::NES20,
+            prg_size: nes20_rom_size(raw[4], raw[9] & 0x0F, PRG_ROM_PAGE_LENGTH),
+            chr_size: nes20_rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_LENGTH),
+            trainer: flg6.contains(Flags6::TRAINER),
+            battery: flg6.contains(Flags6::SRAM),
+            screen_mode: flg6.into(),
+            system,
+            region: match raw[12] & 0b0000_0011 {
+                0 => Region::NTSC,
+                1 => Region::PAL,
+                2 => Region::Multi,
+                _ => Region::Dendy
+            },
+            mapper,
+            submapper,
+            bus_conflicts: BUS_CONFLICT_MAPPERS.contains(&mapper) && submapper == BUS_CONFLICTS_SUBMAPPER,
+            prg_ram_size: nes20_ram_size(raw[10] & 0x0F),
+            prg_nvram_size: nes20_ram_size(raw[10] >> 4),
+            chr_ram_size: nes20_ram_size(raw[11] & 0x0F),
+            chr_nvram_size: nes20_ram_size(raw[11] >> 4),
+            vs_ppu,
+            vs_hardware,
+            misc_roms: raw[14] & 0b0000_0011,
+            expansion_device: raw[15] & 0b0011_1111,
+            board: None
+        }
+    }
+}
+
+// The size of the PRG or CHR-ROM, which is either a 12-bit amount of pages, or when the
+// upper nibble is $F, a size in the form of 2^E * (MM * 2 + 1) with the lower byte as EEEEEEMM
+// Ref: https://wiki.nesdev.com/w/index.php/NES_2.0#PRG-ROM_Area
+fn nes20_rom_size(lsb: u8, msb: u8, page_length: usize) -> usize {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b0000_0011) as usize * 2 + 1;
+        // Sizes that don't fit are bogus anyway, they'll fail to parse as the file can never be that large
+        2usize.checked_pow(exponent).and_then(|size| size.checked_mul(multiplier)).unwrap_or(usize::MAX)
+    } else {
+        (((msb as usize) << 8) | lsb as usize) * page_length
+    }
+}
+
+// RAM sizes are stored as a shift count, where 0 means there is no RAM at all
+// Ref: https://wiki.nesdev.com/w/index.php/NES_2.0#PRG-.28NV.29RAM.2FEEPROM
+fn nes20_ram_size(shift: u8) -> usize {
+    if shift == 0 { 0 } else { 64 << shift }
+}
+
+// The inverse of nes20_rom_size, pages are preferred over the exponent-multiplier form
+fn nes20_rom_size_bytes(size: usize, page_length: usize) -> Option<(u8, u8)> {
+    let pages = size / page_length;
+    if size.is_multiple_of(page_length) && pages < 0xF00 {
+        return Some((pages as u8, (pages >> 8) as u8));
+    }
+
+    (0..64u8)
+        .flat_map(|exponent| (0..4u8).map(move |multiplier| (exponent, multiplier)))
+        .find(|&(exponent, multiplier)| 2usize.checked_pow(exponent as u32).and_then(|power| power.checked_mul(multiplier as usize * 2 + 1)) == Some(size))
+        .map(|(exponent, multiplier)| ((exponent << 2) | multiplier, 0x0F))
+}
+
+// The inverse of nes20_ram_size
+fn nes20_ram_shift(size: usize) -> Option<u8> {
+    match size {
+        0 => Some(0),
+        _ if size.is_power_of_two() && (128..=64 << 15).contains(&size) => Some((size / 64).trailing_zeros() as u8),
+        _ => None
+    }
+}
+
+fn ines_pages(size: usize, page_length: usize) -> Option<u8> {
+    if size.is_multiple_of(page_length) && size / page_length <= 0xFF {
+        Some((size / page_length) as u8)
+    } else {
+        None
+    }
+}
+
+impl From<u8> for VsPpu {
+    fn from(value: u8) -> VsPpu {
+        match value {
+            0x0 => VsPpu::RP2C03B,
+            0x1 => VsPpu::RP2C03G,
+            0x2 => VsPpu::RP2C04_0001,
+            0x3 => VsPpu::RP2C04_0002,
+            0x4 => VsPpu::RP2C04_0003,
+            0x5 => VsPpu::RP2C04_0004,
+            0x6 => VsPpu::RC2C03B,
+            0x7 => VsPpu::RC2C03C,
+            0x8 => VsPpu::RC2C05_01,
+            0x9 => VsPpu::RC2C05_02,
+            0xA => VsPpu::RC2C05_03,
+            0xB => VsPpu::RC2C05_04,
+            0xC => VsPpu::RC2C05_05,
+            _   => VsPpu::Reserved(value)
+        }
+    }
+}
+
+impl From<VsPpu> for u8 {
+    fn from(ppu: VsPpu) -> u8 {
+        match ppu {
+            VsPpu::RP2C03B      => 0x0,
+            VsPpu::RP2C03G      => 0x1,
+            VsPpu::RP2C04_0001  => 0x2,
+            VsPpu::RP2C04_0002  => 0x3,
+            VsPpu::RP2C04_0003  => 0x4,
+            VsPpu::RP2C04_0004  => 0x5,
+            VsPpu::RC2C03B      => 0x6,
+            VsPpu::RC2C03C      => 0x7,
+            VsPpu::RC2C05_01    => 0x8,
+            VsPpu::RC2C05_02    => 0x9,
+            VsPpu::RC2C05_03    => 0xA,
+            VsPpu::RC2C05_04    => 0xB,
+            VsPpu::RC2C05_05    => 0xC,
+            VsPpu::Reserved(value) => value
+        }
+    }
+}
+
+impl From<u8> for VsHardware {
+    fn from(value: u8) -> VsHardware {
+        match value {
+            0x0 => VsHardware::Unisystem,
+            0x1 => VsHardware::UnisystemRbiBaseball,
+            0x2 => VsHardware::UnisystemTkoBoxing,
+            0x3 => VsHardware::UnisystemSuperXevious,
+            0x4 => VsHardware::UnisystemIceClimber,
+            0x5 => VsHardware::DualSystem,
+            0x6 => VsHardware::DualSystemRaidOnBungelingBay,
+            _   => VsHardware::Reserved(value)
+        }
+    }
+}
+
+impl From<VsHardware> for u8 {
+    fn from(hardware: VsHardware) -> u8 {
+        match hardware {
+            VsHardware::Unisystem                       => 0x0,
+            VsHardware::UnisystemRbiBaseball            => 0x1,
+            VsHardware::UnisystemTkoBoxing              => 0x2,
+            VsHardware::UnisystemSuperXevious           => 0x3,
+            VsHardware::UnisystemIceClimber             => 0x4,
+            VsHardware::DualSystem                      => 0x5,
+            VsHardware::DualSystemRaidOnBungelingBay    => 0x6,
+            VsHardware::Reserved(value)                 => value
+        }
+    }
+}
+
+fn parse_ines(buf: &[u8]) -> Result<ROM, RomError> {
+    let raw = match parse_header(buf) {
+        IResult::Done(_, raw)   => raw,
+        IResult::Incomplete(_)  if buf.len() >= 4 => return Err(RomError::TruncatedHeader(buf.len())),
+        _                       => return Err(RomError::BadMagic)
+    };
+    let mut warnings = vec![];
+    let header = Header::new(raw, &mut warnings)?;
+    let mut rest = &buf[HEADER_LENGTH..];
+
+    // If bit 2 in flags6 is set (aka the Trainer flag), the next 512 bytes contain a trainer
+    let trainer = if header.trainer {
+        if rest.len() < TRAINER_LENGTH {
+            return Err(RomError::TruncatedTrainer(rest.len()));
         }
+        let (trainer, remaining) = rest.split_at(TRAINER_LENGTH);
+        rest = remaining;
+        Some(trainer.to_vec())
+    } else {
+        None
+    };
+
+    if rest.len() < header.prg_size {
+        return Err(RomError::TruncatedPrg { expected: header.prg_size, actual: rest.len() });
+    }
+    let (prg_rom, rest) = rest.split_at(header.prg_size);
+
+    if rest.len() < header.chr_size {
+        return Err(RomError::TruncatedChr { expected: header.chr_size, actual: rest.len() });
     }
+    let chr_rom = &rest[..header.chr_size];
+
+    Ok(ROM::new(header, prg_rom.into(), chr_rom.into(), trainer, warnings))
 }
 
-named!(parse_ines<&[u8], ROM>,
+named!(parse_header<&[u8], &[u8]>,
     do_parse!(
-        header:     parse_header     >>
-                    // If bit 2 in flags6 is set (aka the Trainer flag), the next 512 bytes contain a trainer
-                    cond!(header.trainer, take!(TRAINER_LENGTH)) >>
-        prg_rom:    take!(header.prg_size * PRG_ROM_PAGE_LENGTH) >>
-        chr_rom:    take!(header.chr_size * CHR_ROM_PAGE_LENGTH) >>
+                    peek!(tag!("NES\x1a"))  >>
+        raw:        take!(HEADER_LENGTH)    >>
 
-        (ROM::new(header, prg_rom.into(), chr_rom.into()))
+        (raw)
     )
 );
 
-named!(parse_header<&[u8], Header>,
-    do_parse!(
-                    tag!("NES")     >>
-                    tag!([0x1a])    >>
-        prg_size:   be_u8           >>
-        chr_size:   be_u8           >>
-        flags6:     be_u8           >>
-        flags7:     be_u8           >>
-        prg_ram:    be_u8           >>
-        flags9:     be_u8           >>
-        flags10:    be_u8           >>
-                    take!(5)        >>  // These are the remaining 5 0x00 bytes
-
-        (Header::new(prg_size as usize, chr_size as usize, flags6, flags7, prg_ram, flags9, flags10))
-    )
-);
\ No newline at end of file
+// Builds ROMs for the mapper tests without going through a file, every 16KB PRG-ROM page and every 4KB of CHR-ROM
+// starts with its number so the tests can tell which bank is mapped where
+#[cfg(test)]
+pub struct RomBuilder {
+    header: Header,
+    trainer: Option<Vec<u8>>
+}
+
+#[cfg(test)]
+impl RomBuilder {
+    // An NES 2.0 ROM with 16KB of PRG-ROM, 8KB of CHR-ROM, horizontal mirroring and no RAM
+    pub fn new(mapper: u16) -> RomBuilder {
+        RomBuilder {
+            header: Header {
+                format: Format::NES20,
+                prg_size: PRG_ROM_PAGE_LENGTH,
+                chr_size: CHR_ROM_PAGE_LENGTH,
+                trainer: false,
+                battery: false,
+                screen_mode: ScreenMode::Horizontal,
+                system: System::NES,
+                region: Region::NTSC,
+                mapper,
+                submapper: 0,
+                bus_conflicts: false,
+                prg_ram_size: 0,
+                prg_nvram_size: 0,
+                chr_ram_size: 0,
+                chr_nvram_size: 0,
+                vs_ppu: None,
+                vs_hardware: None,
+                misc_roms: 0,
+                expansion_device: 0,
+                board: None
+            },
+            trainer: None
+        }
+    }
+
+    // An iNES header, which always claims 8KB of PRG-RAM
+    pub fn ines(mut self) -> RomBuilder {
+        self.header.format = Format::INES;
+        self.header.prg_ram_size = PRG_RAM_PAGE_LENGTH;
+        self
+    }
+
+    pub fn prg_rom(mut self, pages: usize) -> RomBuilder {
+        self.header.prg_size = pages * PRG_ROM_PAGE_LENGTH;
+        self
+    }
+
+    pub fn chr_rom(mut self, pages: usize) -> RomBuilder {
+        self.header.chr_size = pages * CHR_ROM_PAGE_LENGTH;
+        self
+    }
+
+    pub fn prg_ram(mut self, ram: usize, nvram: usize) -> RomBuilder {
+        self.header.prg_ram_size = ram;
+        self.header.prg_nvram_size = nvram;
+        self.header.battery = nvram > 0;
+        self
+    }
+
+    pub fn chr_ram(mut self, ram: usize) -> RomBuilder {
+        self.header.chr_ram_size = ram;
+        self
+    }
+
+    pub fn screen_mode(mut self, screen_mode: ScreenMode) -> RomBuilder {
+        self.header.screen_mode = screen_mode;
+        self
+    }
+
+    pub fn trainer(mut self) -> RomBuilder {
+        self.header.trainer = true;
+        self.trainer = Some((0..TRAINER_LENGTH).map(|i| (i * 3) as u8).collect());
+        self
+    }
+
+    pub fn build(self) -> ROM {
+        let prg_rom = (0..self.header.prg_size)
+            .map(|i| if i % PRG_ROM_PAGE_LENGTH == 0 { (i / PRG_ROM_PAGE_LENGTH) as u8 } else { (i * 7) as u8 })
+            .collect();
+        let chr_rom = (0..self.header.chr_size)
+            .map(|i| if i % 0x1000 == 0 { (i / 0x1000) as u8 } else { (i * 11) as u8 })
+            .collect();
+        ROM::new(self.header, prg_rom, chr_rom, self.trainer, vec![])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes::hash::crc32;
+
+    // Builds a file from a header followed by an optional trainer and recognizable PRG and CHR data
+    fn file(header: [u8; HEADER_LENGTH], trainer: bool, prg_size: usize, chr_size: usize) -> Vec<u8> {
+        let mut bytes = header.to_vec();
+        if trainer {
+            bytes.extend((0..TRAINER_LENGTH).map(|i| (i * 3) as u8));
+        }
+        bytes.extend((0..prg_size).map(|i| (i * 7) as u8));
+        bytes.extend((0..chr_size).map(|i| (i * 11) as u8));
+        bytes
+    }
+
+    fn assert_round_trip(bytes: &[u8], format: Format) {
+        let rom = parse_ines(bytes).unwrap();
+        assert_eq!(rom.header.format, format);
+        assert_eq!(rom.to_bytes(format).unwrap(), bytes);
+    }
+
+    #[test]
+    fn ines_round_trip() {
+        // Mapper 4 with a battery, trainer and vertical mirroring
+        assert_round_trip(&file([0x4E, 0x45, 0x53, 0x1A, 2, 1, 0x47, 0x00, 1, 0, 0, 0, 0, 0, 0, 0], true, 32768, 8192), Format::INES);
+        // Mapper 66 on PAL with four-screen mirroring and CHR-RAM
+        assert_round_trip(&file([0x4E, 0x45, 0x53, 0x1A, 1, 0, 0x28, 0x40, 1, 1, 0, 0, 0, 0, 0, 0], false, 16384, 0), Format::INES);
+        // Mapper 2 running on both NTSC and PAL, with bus conflicts and 16KB of PRG-RAM
+        assert_round_trip(&file([0x4E, 0x45, 0x53, 0x1A, 1, 0, 0x20, 0x00, 2, 0, 0x21, 0, 0, 0, 0, 0], false, 16384, 0), Format::INES);
+        // Vs. System without PRG-RAM
+        assert_round_trip(&file([0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x00, 0x01, 0, 0, 0x10, 0, 0, 0, 0, 0], false, 16384, 8192), Format::INES);
+    }
+
+    #[test]
+    fn nes20_round_trip() {
+        // Mapper 321, submapper 2, Dendy, with PRG-RAM, CHR-NVRAM, a misc ROM count and an expansion device
+        assert_round_trip(&file([0x4E, 0x45, 0x53, 0x1A, 2, 1, 0x11, 0x48, 0x21, 0x00, 0x07, 0x70, 3, 0, 1, 0x23], false, 32768, 8192), Format::NES20);
+        // Vs. System with a RC2C05-03 and Vs. Dual System hardware, and a trainer
+        assert_round_trip(&file([0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x04, 0x09, 0x00, 0x00, 0x00, 0x00, 0, 0x5A, 0, 0], true, 16384, 8192), Format::NES20);
+        // PRG-ROM size in exponent-multiplier form, 2^2 * 3 bytes
+        assert_round_trip(&file([0x4E, 0x45, 0x53, 0x1A, 0x09, 0, 0x00, 0x0B, 0x00, 0x0F, 0x00, 0x00, 0, 0x05, 0, 0], false, 12, 0), Format::NES20);
+    }
+
+    #[test]
+    fn ines_refuses_12_bit_mappers() {
+        let rom = parse_ines(&file([0x4E, 0x45, 0x53, 0x1A, 2, 1, 0x11, 0x48, 0x21, 0x00, 0x07, 0x70, 3, 0, 1, 0x23], false, 32768, 8192)).unwrap();
+        assert!(rom.to_bytes(Format::INES).is_err());
+    }
+
+    fn database(rom: &ROM, tags: &str) -> Database {
+        Database::parse(&format!(r#"<nes20db><game><rom crc32="{:08X}"/>{}</game></nes20db>"#,
+                                 crc32(&[&rom.prg_rom[..], &rom.chr_rom[..]].concat()), tags))
+    }
+
+    #[test]
+    fn ines_nrom_has_no_prg_ram_unless_the_database_says_so() {
+        let mut rom = RomBuilder::new(0).ines().build();
+        correct_ines(&mut rom, &Database::default());
+        assert_eq!((rom.header.prg_ram_size, rom.header.prg_nvram_size), (0, 0));
+
+        // Family BASIC
+        let mut rom = RomBuilder::new(0).ines().prg_rom(2).build();
+        let database = database(&rom, r#"<pcb mapper="0" battery="1"/><prgnvram size="4096"/>"#);
+        correct_ines(&mut rom, &database);
+        assert_eq!((rom.header.prg_ram_size, rom.header.prg_nvram_size), (0, 4096));
+        assert!(rom.header.battery);
+
+        // Other boards do have the PRG-RAM iNES headers claim
+        let mut rom = RomBuilder::new(1).ines().build();
+        correct_ines(&mut rom, &Database::default());
+        assert_eq!(rom.header.prg_ram_size, PRG_RAM_PAGE_LENGTH);
+    }
+
+    fn error(buf: &[u8]) -> RomError {
+        match load(buf) {
+            Err(err) => err,
+            Ok(rom) => panic!("Expected an error, got a ROM with {:?}", rom.header)
+        }
+    }
+
+    #[test]
+    fn header_errors() {
+        let err = error(b"NES\x00\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+        match err {
+            RomError::BadMagic => assert_eq!(err.to_string(), "Not an iNES, NES 2.0 or UNIF file, the header does not start with \"NES<EOF>\" or \"UNIF\""),
+            err => panic!("Expected a bad magic number, got {:?}", err)
+        }
+        match error(b"NES\x1a\x01") {
+            err @ RomError::TruncatedHeader(5) => assert_eq!(err.to_string(), "Header is truncated, expected 16 bytes but found 5"),
+            err => panic!("Expected a truncated header, got {:?}", err)
+        }
+        match error(&file([0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0x02, 0, 0, 0, 0, 0, 0], false, 16384, 8192)) {
+            err @ RomError::ReservedBits { byte: 9, value: 0x02 } => assert_eq!(err.to_string(), "Header byte 9 ($02) has reserved bits set"),
+            err => panic!("Expected reserved bits, got {:?}", err)
+        }
+    }
+
+    #[test]
+    fn truncation_errors() {
+        let header = [0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        match error(&file(header, false, 100, 0)) {
+            err @ RomError::TruncatedTrainer(100) => assert_eq!(err.to_string(), "Trainer is truncated, expected 512 bytes but found 100"),
+            err => panic!("Expected a truncated trainer, got {:?}", err)
+        }
+        match error(&file(header, true, 100, 0)) {
+            err @ RomError::TruncatedPrg { expected: 16384, actual: 100 } => assert_eq!(err.to_string(), "PRG-ROM is truncated, expected 16384 bytes but found 100"),
+            err => panic!("Expected a truncated PRG-ROM, got {:?}", err)
+        }
+        match error(&file(header, true, 16384, 100)) {
+            err @ RomError::TruncatedChr { expected: 8192, actual: 100 } => assert_eq!(err.to_string(), "CHR-ROM is truncated, expected 8192 bytes but found 100"),
+            err => panic!("Expected a truncated CHR-ROM, got {:?}", err)
+        }
+    }
+
+    #[test]
+    fn unrepresentable_headers() {
+        let rom = RomBuilder::new(256).build();
+        match rom.to_bytes(Format::INES) {
+            Err(err @ RomError::Unrepresentable(_)) => assert_eq!(err.to_string(), "Header can't be written in this format: iNES mappers are limited to 8 bits"),
+            result => panic!("Expected an unrepresentable header, got {:?}", result)
+        }
+        match rom.to_bytes(Format::UNIF) {
+            Err(err @ RomError::Unrepresentable(_)) => assert_eq!(err.to_string(), "Header can't be written in this format: ROMs can only be written as iNES or NES 2.0"),
+            result => panic!("Expected an unrepresentable header, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn patch_and_io_errors() {
+        let bytes = file([0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], false, 16384, 8192);
+        match load_with_patch(&bytes, Some(b"XXXX")) {
+            Err(err @ RomError::Patch(PatchError::UnknownFormat)) => assert_eq!(err.to_string(), "Failed to apply patch: Not an IPS, UPS or BPS patch"),
+            result => panic!("Expected a patch error, got {:?}", result.err())
+        }
+
+        let err = RomError::from(io::Error::other("disk on fire"));
+        assert_eq!(err.to_string(), "Failed to read ROM: disk on fire");
+    }
+
+    // The UNIF loader produces these, see unif.rs for when
+    #[test]
+    fn unif_error_messages() {
+        let truncated = RomError::TruncatedChunk { id: "PRG0".to_string(), expected: 16, actual: 8 };
+        assert_eq!(truncated.to_string(), "PRG0 chunk is truncated, expected 16 bytes but found 8");
+        assert_eq!(RomError::MissingBoard.to_string(), "UNIF file doesn't name its board");
+        assert_eq!(RomError::UnknownBoard("UNL-Unknown".to_string()).to_string(), "Unknown UNIF board \"UNL-Unknown\"");
+    }
+
+    fn nes20_header(bytes: [u8; 12], prg_size: usize, chr_size: usize) -> Header {
+        let mut header = [0x4E, 0x45, 0x53, 0x1A, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        header[4..].copy_from_slice(&bytes);
+        header[7] |= 0b0000_1000;
+        let header = parse_ines(&file(header, false, prg_size, chr_size)).unwrap().header;
+        assert_eq!(header.format, Format::NES20);
+        header
+    }
+
+    // The mapper's upper 4 bits and the submapper share byte 8
+    #[test]
+    fn nes20_mapper_and_submapper() {
+        let header = nes20_header([1, 1, 0x10, 0x20, 0x53, 0, 0, 0, 0, 0, 0, 0], 16384, 8192);
+        assert_eq!((header.mapper, header.submapper), (0x321, 5));
+
+        let header = nes20_header([1, 1, 0xF0, 0xF0, 0xFF, 0, 0, 0, 0, 0, 0, 0], 16384, 8192);
+        assert_eq!((header.mapper, header.submapper), (0xFFF, 15));
+    }
+
+    #[test]
+    fn nes20_rom_sizes() {
+        // 12-bit page counts, with the upper 4 bits of both in byte 9
+        let header = nes20_header([0x00, 0x02, 0, 0, 0, 0x01, 0, 0, 0, 0, 0, 0], 0x100 * 16384, 2 * 8192);
+        assert_eq!((header.prg_size, header.chr_size), (0x100 * 16384, 2 * 8192));
+
+        // Exponent-multiplier sizes, 2^2 * 3 bytes of PRG-ROM and 2^10 * 1 bytes of CHR-ROM
+        let header = nes20_header([0x09, 0x28, 0, 0, 0, 0xFF, 0, 0, 0, 0, 0, 0], 12, 1024);
+        assert_eq!((header.prg_size, header.chr_size), (12, 1024));
+    }
+
+    // RAM sizes are 64 bytes shifted left by the given amount, 0 meaning none
+    #[test]
+    fn nes20_ram_sizes() {
+        let header = nes20_header([1, 0, 0, 0, 0, 0, 0x97, 0x07, 0, 0, 0, 0], 16384, 0);
+        assert_eq!((header.prg_ram_size, header.prg_nvram_size), (8192, 32768));
+        assert_eq!((header.chr_ram_size, header.chr_nvram_size), (8192, 0));
+
+        let header = nes20_header([1, 0, 0, 0, 0, 0, 0x01, 0xE0, 0, 0, 0, 0], 16384, 0);
+        assert_eq!((header.prg_ram_size, header.prg_nvram_size), (128, 0));
+        assert_eq!((header.chr_ram_size, header.chr_nvram_size), (0, 64 << 14));
+    }
+
+    #[test]
+    fn nes20_timing() {
+        for &(timing, region) in &[(0, Region::NTSC), (1, Region::PAL), (2, Region::Multi), (3, Region::Dendy)] {
+            assert_eq!(nes20_header([1, 1, 0, 0, 0, 0, 0, 0, timing, 0, 0, 0], 16384, 8192).region, region);
+        }
+    }
+
+    // Byte 13 holds the Vs. System PPU and hardware, or the type of an extended console
+    #[test]
+    fn nes20_console_type() {
+        let header = nes20_header([1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], 16384, 8192);
+        assert_eq!((header.system, header.vs_ppu, header.vs_hardware), (System::NES, None, None));
+
+        let header = nes20_header([1, 1, 0, 0x01, 0, 0, 0, 0, 0, 0x5A, 0, 0], 16384, 8192);
+        assert_eq!(header.system, System::VsUnisystem);
+        assert_eq!((header.vs_ppu, header.vs_hardware), (Some(VsPpu::RC2C05_03), Some(VsHardware::DualSystem)));
+
+        let header = nes20_header([1, 1, 0, 0x02, 0, 0, 0, 0, 0, 0x5A, 0, 0], 16384, 8192);
+        assert_eq!((header.system, header.vs_ppu), (System::PlayChoice10, None));
+
+        let header = nes20_header([1, 1, 0, 0x03, 0, 0, 0, 0, 0, 0x03, 0, 0], 16384, 8192);
+        assert_eq!(header.system, System::Extended(3));
+    }
+
+    // Old tools wrote their name over bytes 7-15, which would otherwise give mapper 0x44 ('D' is $44)
+    #[test]
+    fn diskdude_header() {
+        let mut header = [0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x31, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        header[7..16].copy_from_slice(b"DiskDude!");
+        let rom = parse_ines(&file(header, false, 16384, 8192)).unwrap();
+        assert_eq!(rom.header.format, Format::INES);
+        assert_eq!(rom.header.mapper, 3);
+        assert_eq!(rom.header.system, System::NES);
+        assert_eq!(rom.header.region, Region::NTSC);
+        assert_eq!(rom.header.screen_mode, ScreenMode::Vertical);
+        assert_eq!(rom.warnings, vec!["Header contains the \"DiskDude!\" signature, ignored bytes 7-15 and used a 4-bit mapper number"]);
+    }
+
+    #[test]
+    fn garbage_in_bytes_12_to_15() {
+        let rom = parse_ines(&file([0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x10, 0x40, 0, 1, 0, 0, 0, b'N', b'I', 0], false, 16384, 8192)).unwrap();
+        assert_eq!((rom.header.mapper, rom.header.region), (1, Region::NTSC));
+        assert_eq!(rom.warnings, vec!["Header bytes 12-15 are not zero, ignored bytes 7-15 and used a 4-bit mapper number"]);
+
+        let rom = parse_ines(&file([0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x10, 0x40, 0, 1, 0, 0, 0, 0, 0, 0], false, 16384, 8192)).unwrap();
+        assert_eq!((rom.header.mapper, rom.header.region), (0x41, Region::PAL));
+        assert!(rom.warnings.is_empty());
+    }
+
+    // The trainer sits between the header and the PRG-ROM
+    #[test]
+    fn trainer_comes_before_prg_rom() {
+        let rom = parse_ines(&file([0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0], true, 16384, 8192)).unwrap();
+        assert!(rom.header.trainer);
+        let trainer = rom.trainer.unwrap();
+        assert_eq!(trainer.len(), TRAINER_LENGTH);
+        assert_eq!((trainer[1], trainer[TRAINER_LENGTH - 1]), (3, (0x1FF * 3) as u8));
+        assert_eq!((rom.prg_rom[0], rom.prg_rom[1]), (0, 7));
+        assert_eq!((rom.chr_rom[0], rom.chr_rom[1]), (0, 11));
+
+        let rom = parse_ines(&file([0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], false, 16384, 8192)).unwrap();
+        assert!(!rom.header.trainer && rom.trainer.is_none());
+        assert_eq!(rom.prg_rom[1], 7);
+    }
+
+    #[test]
+    fn battery() {
+        let header = |flags6| parse_ines(&file([0x4E, 0x45, 0x53, 0x1A, 1, 1, flags6, 0, 0, 0, 0, 0, 0, 0, 0, 0], false, 16384, 8192)).unwrap().header;
+        assert!(header(0x02).battery);
+        assert!(!header(0x00).battery);
+        assert!(header(0x0A).battery);
+    }
+
+    // Byte 8 counts 8KB pages of PRG-RAM, 0 means 8KB for older dumps and only byte 10 can say there's none
+    #[test]
+    fn ines_prg_ram_size() {
+        let prg_ram_size = |pages, flags10| {
+            parse_ines(&file([0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x10, 0, pages, 0, flags10, 0, 0, 0, 0, 0], false, 16384, 8192)).unwrap().header.prg_ram_size
+        };
+        assert_eq!(prg_ram_size(0, 0), PRG_RAM_PAGE_LENGTH);
+        assert_eq!(prg_ram_size(1, 0), PRG_RAM_PAGE_LENGTH);
+        assert_eq!(prg_ram_size(4, 0), 4 * PRG_RAM_PAGE_LENGTH);
+        assert_eq!(prg_ram_size(0, 0x10), 0);
+    }
+
+    // Older dumps use 0 for 8KB of PRG-RAM, which is written out as 1
+    #[test]
+    fn ines_prg_ram_size_is_written_out() {
+        let rom = parse_ines(&file([0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0], false, 16384, 8192)).unwrap();
+        assert_eq!(rom.to_bytes(Format::INES).unwrap()[8], 1);
+    }
+
+    // Flags 10 gives the TV system as 0 for NTSC, 2 for PAL and 1 or 3 for both
+    #[test]
+    fn ines_flags_10() {
+        let header = |flags9, flags10| parse_ines(&file([0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x20, 0, 0, flags9, flags10, 0, 0, 0, 0, 0], false, 16384, 8192)).unwrap().header;
+        assert_eq!(header(0, 0x02).region, Region::PAL);
+        assert_eq!(header(0, 0x03).region, Region::Multi);
+        assert_eq!(header(1, 0x01).region, Region::PAL);
+        assert!(header(0, 0x20).bus_conflicts);
+        assert!(!header(0, 0x00).bus_conflicts);
+    }
+
+    // NES 2.0 only has bus conflicts as submapper 2 of mappers 2, 3 and 7
+    #[test]
+    fn bus_conflicts_as_a_submapper() {
+        let rom = parse_ines(&file([0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x20, 0, 0, 0, 0x20, 0, 0, 0, 0, 0], false, 16384, 8192)).unwrap();
+        let bytes = rom.to_bytes(Format::NES20).unwrap();
+        assert_eq!(bytes[8], 0x20);
+        let rom = parse_ines(&bytes).unwrap();
+        assert!(rom.header.bus_conflicts);
+        assert_eq!(rom.to_bytes(Format::INES).unwrap()[10] & 0x20, 0x20);
+
+        let rom = parse_ines(&file([0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x40, 0, 0, 0, 0x20, 0, 0, 0, 0, 0], false, 16384, 8192)).unwrap();
+        match rom.to_bytes(Format::NES20) {
+            Err(RomError::Unrepresentable(_)) => (),
+            result => panic!("Expected an unrepresentable header, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn one_screen_mirroring_is_refused() {
+        let rom = RomBuilder::new(7).screen_mode(ScreenMode::OneScreenLower).build();
+        for &format in &[Format::INES, Format::NES20] {
+            match rom.to_bytes(format) {
+                Err(err @ RomError::Unrepresentable(_)) => assert_eq!(err.to_string(), "Header can't be written in this format: one-screen mirroring has no header bit"),
+                result => panic!("Expected an unrepresentable header, got {:?}", result)
+            }
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use byteorder::{ByteOrder, LittleEndian};
+
+use nes::hash::crc32;
+use nes::rom::{Format, Header, Region, RomError, ROM, ScreenMode, System, CHR_ROM_PAGE_LENGTH};
+
+pub const UNIF_MAGIC: &[u8] = b"UNIF";
+const UNIF_HEADER_LENGTH: usize = 32;
+const CHUNK_HEADER_LENGTH: usize = 8;
+
+// Boards known by their UNIF name and the iNES mapper that implements them
+// Licensed boards are listed without their NES-, HVC- or similar prefix, as they're used interchangeably
+// Ref: https://wiki.nesdev.com/w/index.php/UNIF_to_NES_2.0_Mapping
+// Ref: https://wiki.nesdev.com/w/index.php/Cartridge_board_reference
+const BOARDS: [(&str, u16); 80] = [
+    ("NROM", 0), ("NROM-128", 0), ("NROM-256", 0), ("RROM", 0), ("RROM-128", 0),
+    ("SAROM", 1), ("SBROM", 1), ("SCROM", 1), ("SEROM", 1), ("SFROM", 1), ("SGROM", 1), ("SHROM", 1),
+    ("SJROM", 1), ("SKROM", 1), ("SLROM", 1), ("SL1ROM", 1), ("SNROM", 1), ("SOROM", 1), ("SUROM", 1),
+    ("SXROM", 1),
+    ("UNROM", 2), ("UOROM", 2),
+    ("CNROM", 3),
+    ("TBROM", 4), ("TEROM", 4), ("TFROM", 4), ("TGROM", 4), ("TKROM", 4), ("TLROM", 4), ("TL1ROM", 4),
+    ("TNROM", 4), ("TR1ROM", 4), ("TSROM", 4), ("TVROM", 4), ("HKROM", 4), ("B4", 4),
+    ("EKROM", 5), ("ELROM", 5), ("ETROM", 5), ("EWROM", 5),
+    ("AMROM", 7), ("ANROM", 7), ("AN1ROM", 7), ("AOROM", 7),
+    ("PEEOROM", 9), ("PNROM", 9),
+    ("FJROM", 10), ("FKROM", 10),
+    ("CPROM", 13),
+    ("BNROM", 34), ("NINA-001", 34),
+    ("GNROM", 66), ("MHROM", 66),
+    ("JLROM", 69), ("JSROM", 69), ("BTR", 69),
+    ("TLSROM", 118), ("TKSROM", 118),
+    ("TQROM", 119),
+    ("UNL-CC-21", 27),
+    ("UNL-AC08", 42),
+    ("UNL-Supervision16in1", 53),
+    ("UNL-D1038", 59),
+    ("BMC-Super700in1", 62),
+    ("UNL-H2288", 123),
+    ("UNL-Sachen-8259D", 137),
+    ("UNL-Sachen-8259B", 138),
+    ("UNL-Sachen-8259C", 139),
+    ("UNL-Sachen-8259A", 141),
+    ("UNL-SA-72007", 145),
+    ("UNL-SA-016-1M", 146),
+    ("UNL-TC-U01-1.5M", 147),
+    ("UNL-SA-0037", 148),
+    ("UNL-SA-0036", 149),
+    ("UNL-Sachen-74LS374N", 150),
+    ("BMC-FK23C", 176),
+    ("UNL-8237", 215),
+    ("BMC-Ghostbusters63in1", 226),
+    ("BMC-70in1", 236),
+    ("UNL-KOF97", 263)
+];
+
+// Prefixes of licensed boards, which have no bearing on the hardware
+const BOARD_PREFIXES: [&str; 4] = ["NES-", "HVC-", "IREM-", "KONAMI-"];
+
+// Looks up the mapper of a board by its UNIF name
+pub fn board_mapper(board: &str) -> Option<u16> {
+    let name = BOARD_PREFIXES.iter()
+        .find(|prefix| board.starts_with(*prefix))
+        .map_or(board, |prefix| &board[prefix.len()..]);
+
+    BOARDS.iter()
+        .find(|&&(known, _)| known.eq_ignore_ascii_case(name))
+        .map(|&(_, mapper)| mapper)
+}
+
+// UNIF files consist of a 32 byte header followed by chunks, each with a 4 character ID and a 32-bit length
+// PRG and CHR are split over up to 16 chunks each, which are concatenated in the order of their number
+// Ref: https://wiki.nesdev.com/w/index.php/UNIF
+pub fn parse_unif(buf: &[u8]) -> Result<ROM, RomError> {
+    if !buf.starts_with(UNIF_MAGIC) {
+        return Err(RomError::BadMagic);
+    }
+    if buf.len() < UNIF_HEADER_LENGTH {
+        return Err(RomError::TruncatedHeader(buf.len()));
+    }
+
+    let mut board = None;
+    let mut prg_chunks: [Option<&[u8]>; 16] = [None; 16];
+    let mut chr_chunks: [Option<&[u8]>; 16] = [None; 16];
+    let mut prg_checksums: [Option<u32>; 16] = [None; 16];
+    let mut chr_checksums: [Option<u32>; 16] = [None; 16];
+    let mut screen_mode = ScreenMode::Horizontal;
+    let mut region = Region::NTSC;
+    let mut battery = false;
+    let mut chr_ram = false;
+    let mut expansion_device = 0;
+    let mut warnings = vec![];
+
+    let mut rest = &buf[UNIF_HEADER_LENGTH..];
+    while !rest.is_empty() {
+        if rest.len() < CHUNK_HEADER_LENGTH {
+            return Err(RomError::TruncatedHeader(rest.len()));
+        }
+        let id = &rest[..4];
+        let length = LittleEndian::read_u32(&rest[4..]) as usize;
+        let data = rest.get(CHUNK_HEADER_LENGTH..CHUNK_HEADER_LENGTH + length)
+            .ok_or_else(|| RomError::TruncatedChunk { id: string(id), expected: length, actual: rest.len() - CHUNK_HEADER_LENGTH })?;
+        rest = &rest[CHUNK_HEADER_LENGTH + length..];
+
+        match (&id[..3], chunk_number(id[3])) {
+            (b"PRG", Some(number)) => prg_chunks[number] = Some(data),
+            (b"CHR", Some(number)) => chr_chunks[number] = Some(data),
+            (b"PCK", Some(number)) if data.len() >= 4 => prg_checksums[number] = Some(LittleEndian::read_u32(data)),
+            (b"CCK", Some(number)) if data.len() >= 4 => chr_checksums[number] = Some(LittleEndian::read_u32(data)),
+            _ => match id {
+                b"MAPR" => board = Some(string(data)),
+                b"MIRR" => screen_mode = match data.first() {
+                    Some(0) => ScreenMode::Horizontal,
+                    Some(1) => ScreenMode::Vertical,
+                    Some(2) => ScreenMode::OneScreenLower,
+                    Some(3) => ScreenMode::OneScreenUpper,
+                    Some(4) => ScreenMode::FourScreen,
+                    _ => screen_mode    // Controlled by the mapper
+                },
+                b"BATR" => battery = true,
+                b"VROR" => chr_ram = true,
+                b"TVCI" => region = match data.first() {
+                    Some(1) => Region::PAL,
+                    Some(2) => Region::Multi,
+                    _ => Region::NTSC
+                },
+                b"CTRL" => expansion_device = data.first().map_or(0, |&controllers| expansion_device_of(controllers)),
+                _ => ()     // NAME, READ, DINF and unknown chunks only hold information for people
+            }
+        }
+    }
+
+    let board = board.ok_or(RomError::MissingBoard)?;
+    let mapper = board_mapper(&board).ok_or_else(|| RomError::UnknownBoard(board.clone()))?;
+
+    let prg_rom = concat_chunks("PRG", &prg_chunks, &prg_checksums, &mut warnings);
+    let chr_rom = concat_chunks("CHR", &chr_chunks, &chr_checksums, &mut warnings);
+
+    // UNIF doesn't specify RAM sizes, so the usual 8KB of PRG-RAM is assumed, and 8KB CHR-RAM without CHR-ROM
+    let header = Header {
+        format: Format::UNIF,
+        prg_size: prg_rom.len(),
+        chr_size: chr_rom.len(),
+        trainer: false,
+        battery,
+        screen_mode,
+        system: System::NES,
+        region,
+        mapper,
+        submapper: 0,
+        bus_conflicts: false,
+        prg_ram_size: if battery { 0 } else { 0x2000 },
+        prg_nvram_size: if battery { 0x2000 } else { 0 },
+        chr_ram_size: if chr_ram || chr_rom.is_empty() { CHR_ROM_PAGE_LENGTH } else { 0 },
+        chr_nvram_size: 0,
+        vs_ppu: None,
+        vs_hardware: None,
+        misc_roms: 0,
+        expansion_device,
+        board: Some(board)
+    };
+
+    Ok(ROM::new(header, prg_rom, chr_rom, None, warnings))
+}
+
+// Chunk numbers are a single hexadecimal digit
+fn chunk_number(digit: u8) -> Option<usize> {
+    (digit as char).to_digit(16).map(|number| number as usize)
+}
+
+fn string(data: &[u8]) -> String {
+    let end = data.iter().position(|&byte| byte == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+fn concat_chunks(kind: &str, chunks: &[Option<&[u8]>; 16], checksums: &[Option<u32>; 16], warnings: &mut Vec<String>) -> Vec<u8> {
+    let mut data = vec![];
+
+    for (number, chunk) in chunks.iter().enumerate() {
+        if let Some(chunk) = *chunk {
+            if let Some(expected) = checksums[number] {
+                let actual = crc32(chunk);
+                if actual != expected {
+                    warnings.push(format!("{}{:X} chunk should have CRC32 {:08x}, but it is {:08x}", kind, number, expected, actual));
+                }
+            }
+            data.extend_from_slice(chunk);
+        }
+    }
+
+    data
+}
+
+// The CTRL chunk is a set of flags for the supported controllers, the most specific device is taken
+// Ref: https://wiki.nesdev.com/w/index.php/NES_2.0#Default_Expansion_Device
+fn expansion_device_of(controllers: u8) -> u8 {
+    if controllers & 0b0000_0010 != 0 {
+        0x08        // Zapper
+    } else if controllers & 0b0000_1000 != 0 {
+        0x0F        // Arkanoid controller
+    } else if controllers & 0b0001_0000 != 0 {
+        0x0B        // Power Pad
+    } else if controllers & 0b0010_0000 != 0 {
+        0x02        // Four Score
+    } else if controllers & 0b0000_0001 != 0 {
+        0x01        // Standard controllers
+    } else {
+        0x00
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&[0; 4]);
+        LittleEndian::write_u32(&mut bytes[4..], data.len() as u32);
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    // Builds a file from a revision 7 header followed by the given chunks
+    fn file(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = UNIF_MAGIC.to_vec();
+        bytes.extend_from_slice(&[7, 0, 0, 0]);
+        bytes.resize(UNIF_HEADER_LENGTH, 0);
+        for chunk in chunks {
+            bytes.extend_from_slice(chunk);
+        }
+        bytes
+    }
+
+    fn checksum(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut crc = [0; 4];
+        LittleEndian::write_u32(&mut crc, crc32(data));
+        chunk(id, &crc)
+    }
+
+    #[test]
+    fn unif_chunks() {
+        let prg0: Vec<u8> = (0..0x4000).map(|i| (i * 7) as u8).collect();
+        let prg1: Vec<u8> = (0..0x4000).map(|i| (i * 3) as u8).collect();
+        let chr0: Vec<u8> = (0..0x2000).map(|i| (i * 11) as u8).collect();
+        let rom = parse_unif(&file(&[
+            chunk(b"NAME", b"Test\0"),
+            chunk(b"MAPR", b"NES-SLROM\0"),
+            chunk(b"PRG1", &prg1),              // PRG chunks are concatenated by number, not by order in the file
+            chunk(b"PRG0", &prg0),
+            chunk(b"CHR0", &chr0),
+            chunk(b"MIRR", &[1]),
+            chunk(b"BATR", &[0]),
+            chunk(b"TVCI", &[1]),
+            chunk(b"CTRL", &[0b0000_0011]),
+            chunk(b"DINF", &[0; 204])
+        ])).unwrap();
+
+        assert_eq!(rom.header.format, Format::UNIF);
+        assert_eq!(rom.header.board, Some("NES-SLROM".to_string()));
+        assert_eq!(rom.header.mapper, 1);
+        assert_eq!(rom.prg_rom, [&prg0[..], &prg1[..]].concat());
+        assert_eq!(rom.chr_rom, chr0);
+        assert_eq!(rom.header.screen_mode, ScreenMode::Vertical);
+        assert_eq!(rom.header.region, Region::PAL);
+        assert!(rom.header.battery);
+        assert_eq!((rom.header.prg_ram_size, rom.header.prg_nvram_size), (0, 0x2000));
+        assert_eq!(rom.header.chr_ram_size, 0);
+        assert_eq!(rom.header.expansion_device, 0x08);
+        assert!(rom.warnings.is_empty());
+    }
+
+    #[test]
+    fn unif_chr_ram() {
+        let rom = parse_unif(&file(&[chunk(b"MAPR", b"UNROM"), chunk(b"PRG0", &[0; 0x8000])])).unwrap();
+        assert_eq!(rom.header.mapper, 2);
+        assert!(rom.chr_rom.is_empty());
+        assert_eq!(rom.header.chr_ram_size, CHR_ROM_PAGE_LENGTH);
+        assert_eq!((rom.header.prg_ram_size, rom.header.prg_nvram_size), (0x2000, 0));
+    }
+
+    #[test]
+    fn unif_checksums() {
+        let prg = [1, 2, 3, 4];
+        let chr = [5, 6, 7, 8];
+        let rom = parse_unif(&file(&[
+            chunk(b"MAPR", b"NROM"),
+            chunk(b"PRG0", &prg),
+            checksum(b"PCK0", &prg),
+            chunk(b"CHRA", &chr),
+            checksum(b"CCKA", &prg)
+        ])).unwrap();
+
+        // A checksum mismatch is a warning rather than an error, so a damaged dump can still be tried
+        assert_eq!(rom.chr_rom, chr);
+        assert_eq!(rom.warnings, vec![format!("CHRA chunk should have CRC32 {:08x}, but it is {:08x}", crc32(&prg), crc32(&chr))]);
+    }
+
+    #[test]
+    fn unif_board_names() {
+        assert_eq!(board_mapper("NROM-256"), Some(0));
+        assert_eq!(board_mapper("NES-SNROM"), Some(1));
+        assert_eq!(board_mapper("HVC-UNROM"), Some(2));
+        assert_eq!(board_mapper("KONAMI-TLROM"), Some(4));
+        assert_eq!(board_mapper("unl-sachen-8259a"), Some(141));
+        assert_eq!(board_mapper("UNL-KOF97"), Some(263));
+        assert_eq!(board_mapper("NES-"), None);
+        assert_eq!(board_mapper("UNL-Unknown"), None);
+    }
+
+    #[test]
+    fn unif_errors() {
+        match parse_unif(b"NES\x1a") {
+            Err(RomError::BadMagic) => (),
+            result => panic!("Expected bad magic, got {:?}", result)
+        }
+        match parse_unif(b"UNIF\x07\0\0\0") {
+            Err(RomError::TruncatedHeader(8)) => (),
+            result => panic!("Expected a truncated header, got {:?}", result)
+        }
+        match parse_unif(&file(&[chunk(b"PRG0", &[0; 16])])) {
+            Err(RomError::MissingBoard) => (),
+            result => panic!("Expected a missing board, got {:?}", result)
+        }
+        match parse_unif(&file(&[chunk(b"MAPR", b"UNL-Unknown\0")])) {
+            Err(RomError::UnknownBoard(ref board)) if board == "UNL-Unknown" => (),
+            result => panic!("Expected an unknown board, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn unif_truncated_chunks() {
+        let mut bytes = file(&[chunk(b"MAPR", b"NROM"), chunk(b"PRG0", &[0; 16])]);
+        bytes.truncate(bytes.len() - 8);
+        match parse_unif(&bytes) {
+            Err(RomError::TruncatedChunk { ref id, expected: 16, actual: 8 }) if id == "PRG0" => (),
+            result => panic!("Expected a truncated chunk, got {:?}", result)
+        }
+
+        // The file ends within the ID and length of the next chunk
+        let mut bytes = file(&[chunk(b"MAPR", b"NROM")]);
+        bytes.extend_from_slice(b"PRG");
+        match parse_unif(&bytes) {
+            Err(RomError::TruncatedHeader(3)) => (),
+            result => panic!("Expected a truncated chunk header, got {:?}", result)
+        }
+    }
+}
@@ -0,0 +1,8 @@
+#!/bin/sh
+# Downloads nestest and the reference log from Nintendulator next to this script, for the nestest test in cpu/trace.rs
+# Ref: https://wiki.nesdev.com/w/index.php/Emulator_tests
+set -e
+cd "$(dirname "$0")"
+for file in nestest.nes nestest.log; do
+    curl --fail --location --silent --show-error --output "$file" "http://www.qmtpro.com/~nes/misc/$file"
+done